@@ -5,9 +5,11 @@ use benchmark_rs::stopwatch::StopWatch;
 use clap::{arg, Command};
 use log::LevelFilter;
 use osmpbf::{Element, ElementReader};
-use postgres::{Client, NoTls};
+use postgres::Config;
 use simple_logger::SimpleLogger;
 
+use osm_admin::tls;
+
 fn command() -> Command {
     Command::new("osm-import-test").about("Integration tests for OSM import")
         .arg(arg!(--input <INPUT> "Input file path").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
@@ -29,7 +31,17 @@ pub fn main() {
     let port = matches.get_one::<String>("port").unwrap().clone();
     let user = matches.get_one::<String>("user").unwrap().clone();
     let password = matches.get_one::<String>("password").unwrap().clone();
-    let mut client = Client::connect(format!("host={host} port={port} user={user} password={password} dbname=openstreetmap").as_str(), NoTls)
+    // the SSL settings are read from PGSSLMODE, PGSSLROOTCERT, PGSSLCERT and PGSSLKEY
+    let ssl_mode = tls::ssl_mode().expect("Invalid PGSSLMODE").client_ssl_mode();
+    let connector = tls::connector().expect("Failed to create a TLS connector");
+    let mut client = Config::new()
+        .host(&host)
+        .port(port.parse::<u16>().expect("Invalid port"))
+        .user(&user)
+        .password(&password)
+        .dbname("openstreetmap")
+        .ssl_mode(ssl_mode)
+        .connect(connector)
         .expect("Failed to connect to test database");
 
     let reader = ElementReader::from_path(&input_path).expect("Failed to create a reader");
@@ -1,3 +1,4 @@
+use std::fs;
 use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
@@ -10,7 +11,11 @@ use osm_io::osm::model::bounding_box::BoundingBox;
 use simple_logger::SimpleLogger;
 use tikv_jemallocator::Jemalloc;
 
-use osm_admin::{export, import};
+use osm_admin::{apply_changes, changes, diff, export, import, sync, verify};
+use osm_admin::connection::{ConnectionParameters, Database};
+use osm_admin::metrics::set_metrics_file;
+use osm_admin::options::{FilterOptions, InputOptions, LoadOptions, OutputOptions, ReadOptions, TagFilterOptions, WorkPaths};
+use osm_admin::progress::{set_progress_format, ProgressFormat};
 
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
@@ -18,38 +23,139 @@ static GLOBAL: Jemalloc = Jemalloc;
 fn command() -> Command {
     Command::new("osm").about("Tools for OSM database administration").subcommand_required(true).arg_required_else_help(true).allow_external_subcommands(true)
         .arg(arg!(--verbose "Print progress information").required(false).num_args(0))
+        .arg(arg!(--"log-dir" <LOG_DIR> "Directory of the pg_dump and pg_restore logs, created if missing").env("OSM_ADMIN_LOG_DIR").value_parser(clap::value_parser!(PathBuf)).default_value("/var/log/osm/").global(true).num_args(1))
+        .arg(arg!(--"work-dir" <WORK_DIR> "Working directory of the tool, created if missing").env("OSM_ADMIN_WORK_DIR").value_parser(clap::value_parser!(PathBuf)).default_value("/var/lib/osm/").global(true).num_args(1))
+        .arg(arg!(--pgpass <PGPASS> "Password file of the database connections in the pgpass format. Defaults to ~/.pgpass").env("PGPASSFILE").value_parser(clap::value_parser!(PathBuf)).global(true).num_args(1))
+        .arg(arg!(--sslmode <SSLMODE> "SSL mode of the database connections as for libpq. verify-ca and verify-full require a root certificate. Defaults to prefer").env("PGSSLMODE").value_parser(["disable", "allow", "prefer", "require", "verify-ca", "verify-full"]).global(true).num_args(1))
+        .arg(arg!(--sslrootcert <SSLROOTCERT> "Root certificate file for verifying the database server certificate. Defaults to ~/.postgresql/root.crt").env("PGSSLROOTCERT").value_parser(clap::value_parser!(PathBuf)).global(true).num_args(1))
+        .arg(arg!(--sslcert <SSLCERT> "Client certificate file for the database connections. Defaults to ~/.postgresql/postgresql.crt").env("PGSSLCERT").value_parser(clap::value_parser!(PathBuf)).global(true).num_args(1))
+        .arg(arg!(--sslkey <SSLKEY> "Private key file of the client certificate. Defaults to ~/.postgresql/postgresql.key").env("PGSSLKEY").value_parser(clap::value_parser!(PathBuf)).global(true).num_args(1))
         .subcommand(
             Command::new("import").about("Import OSM from file into database")
-                .arg(arg!(--input <INPUT> "Input file path").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
-                .arg(arg!(--"input-format" <INPUT_FORMAT> "The input format. Currently, only pbf is supported").value_parser(["pbf"]).default_value("pbf").num_args(1))
-                .arg(arg!(--output <OUTPUT> "Output directory path").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
-                .arg(arg!(--jobs <JOBS> "Number of database load jobs. Zero means autodetect CPU allocation and use all CPUs. When more jobs than CPUs specified, the number is capped on CPU limit").value_parser(0..1024).default_value("0").num_args(1))
-                .arg(arg!(--host <HOST> "Database host").required(true).num_args(1))
-                .arg(arg!(--port <PORT> "Database port").default_value("5432").num_args(1))
-                .arg(arg!(--database <DATABASE> "Database name").default_value("openstreetmap").num_args(1))
-                .arg(arg!(--user <USER> "Database administrator user name").value_parser(clap::value_parser!(String)).required(true).num_args(1))
-                .arg(arg!(--password "Prompt for password. Either --password or --no-password must be present.").required(false).num_args(0))
-                .arg(arg!(--"no-password" "Don't prompt for password. Use PGPASSFILE if available").required(false).num_args(0))
+                .arg(arg!(--input <INPUT> "Input file path, or - to read the input from stdin. A gzip or bzip2 compressed stream is decompressed on the fly").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"input-format" <INPUT_FORMAT> "The input format, pbf or osm for OSM XML (*.osm, *.osm.gz, *.osm.bz2). OSM XML input must be sorted by type, id and version").value_parser(["pbf", "osm"]).default_value("pbf").num_args(1))
+                .arg(arg!(--output <OUTPUT> "Output directory path for the apidb dump. Required unless --load is copy").required_unless_present_any(["load", "schema"]).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--load <LOAD> "How to load the data into the database. pg-restore writes an apidb dump to --output and loads it with pg_restore. copy streams the data directly into an existing, empty apidb schema using COPY, without a dump on disk. Defaults to pg-restore for the public schema and to copy for other schemas").value_parser(["pg-restore", "copy"]).num_args(1))
+                .arg(arg!(--resume "Resume an interrupted import from the manifest in the --output directory, skipping the completed steps").required(false).num_args(0))
+                .arg(arg!(--"keep-tags" <KEEP_TAGS> "Keep only the tags of nodes, ways and relations matching one of the expressions: key, key=value or ~regex matching the key").value_parser(clap::value_parser!(String)).num_args(1..))
+                .arg(arg!(--"drop-tags" <DROP_TAGS> "Drop the tags of nodes, ways and relations matching one of the expressions: key, key=value or ~regex matching the key. Applied after --keep-tags").value_parser(clap::value_parser!(String)).num_args(1..))
+                .arg(arg!(--"progress-format" <PROGRESS_FORMAT> "Format of the progress reports. json writes one event per line to stderr with the phase, counts, rate, ETA, disk usage and elapsed time, and implies --verbose").value_parser(["text", "json"]).default_value("text").num_args(1))
+                .arg(arg!(--"metrics-file" <METRICS_FILE> "Write Prometheus metrics of the long running phases periodically to the file, for the node-exporter textfile collector (*.prom)").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--jobs <JOBS> "Number of database load jobs and of PBF decoding threads. Zero means autodetect CPU allocation and use all CPUs. When more jobs than CPUs specified, the number is capped on CPU limit").value_parser(0..1024).default_value("0").num_args(1))
+                .arg(arg!(--host <HOST> "Database host, or the directory of a Unix-domain socket. Defaults to the connection URI, the service or PGHOST").num_args(1))
+                .arg(arg!(--port <PORT> "Database port. Defaults to the connection URI, the service, PGPORT or 5432").num_args(1))
+                .arg(arg!(--database <DATABASE> "Database name, or a postgresql:// connection URI. Defaults to the service, PGDATABASE or openstreetmap").num_args(1))
+                .arg(arg!(--schema <SCHEMA> "Database schema of the apidb tables. The apidb dump is bound to the public schema, so other schemas are loaded only with --load copy").default_value("public").num_args(1))
+                .arg(arg!(--user <USER> "Database administrator user name. Defaults to the connection URI, the service or PGUSER").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--password "Prompt for password. One of --password, --password-file, --password-env or --no-password must be present.").required(false).num_args(0))
+                .arg(arg!(--"password-file" <PASSWORD_FILE> "Read the password from the first line of the file, for non-interactive runs").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"password-env" <PASSWORD_ENV> "Read the password from the environment variable, for non-interactive runs").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--"no-password" "Don't prompt for password. Use the --pgpass file if available").required(false).num_args(0))
                 .arg(arg!(--verbose "Print progress information").required(false).num_args(0))
                 .arg_required_else_help(true),
         )
         .subcommand(
             Command::new("export").about("Export OSM data into a file")
-                .arg(arg!(--dump <DUMP> "Dump directory path").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
-                .arg(arg!(--output <OUTPUT> "Output file path").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
-                .arg(arg!(--"output-format" <OUTPUT_FORMAT> "The output format, currently only pbf is supported").value_parser(["pbf"]).default_value("pbf").num_args(1))
+                .arg(arg!(--dump <DUMP> "Dump directory path. Required unless --read is copy").required_unless_present_any(["read", "schema"]).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--read <READ> "How to read the data from the database. pg-dump dumps the database to --dump with pg_dump and reads the dump. copy reads the data directly with COPY, without a dump on disk. Defaults to pg-dump for the public schema and to copy for other schemas").value_parser(["pg-dump", "copy"]).num_args(1))
+                .arg(arg!(--output <OUTPUT> "Output file path, or - to write the output to stdout").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"output-format" <OUTPUT_FORMAT> "The output format, pbf, osm for OSM XML or opl. OSM XML and OPL output is compressed when the output path ends with .gz or .bz2").value_parser(["pbf", "osm", "opl"]).default_value("pbf").num_args(1))
+                .arg(arg!(--compression <COMPRESSION> "Compression of the PBF blobs. zstd and lz4 are faster but not supported by all readers and are announced as optional header features").value_parser(["none", "zlib", "zstd", "lz4"]).default_value("zlib").num_args(1))
+                .arg(arg!(--"compression-level" <COMPRESSION_LEVEL> "Compression level of the PBF blobs, 0 to 9 for zlib and 1 to 22 for zstd. Defaults to the default level of the codec").value_parser(clap::value_parser!(i32)).num_args(1))
+                .arg(arg!(--"keep-tags" <KEEP_TAGS> "Keep only the tags of nodes, ways and relations matching one of the expressions: key, key=value or ~regex matching the key").value_parser(clap::value_parser!(String)).num_args(1..))
+                .arg(arg!(--"drop-tags" <DROP_TAGS> "Drop the tags of nodes, ways and relations matching one of the expressions: key, key=value or ~regex matching the key. Applied after --keep-tags").value_parser(clap::value_parser!(String)).num_args(1..))
                 .arg(arg!(--"bounding-box" <BOUNDING_BOX> "The precomputed bounding box in the form 'left,bottom,right,top' as in 5.8663153,47.2701114,15.0419309,55.099161").value_parser(clap::value_parser!(String)).num_args(1))
                 .arg(arg!(--"calc-bounding-box" "Calculate the bounding box. Will incur an iteration over all the node elements. When present --bounding-box is ignored").required(false).num_args(0))
+                .arg(arg!(--"current-only" "Export only the latest visible version of each element. Historical versions and deleted elements are dropped").required(false).num_args(0))
+                .arg(arg!(--"extract-bbox" <EXTRACT_BBOX> "Export only the region inside the bounding box in the form 'left,bottom,right,top' as in 5.8663153,47.2701114,15.0419309,55.099161").value_parser(clap::value_parser!(String)).conflicts_with("extract-poly").num_args(1))
+                .arg(arg!(--"extract-poly" <EXTRACT_POLY> "Export only the region inside the polygon from an osmosis polygon filter file (*.poly)").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"extract-strategy" <EXTRACT_STRATEGY> "Strategy for selecting the elements of the extract region").value_parser(["simple", "complete-ways", "smart"]).default_value("complete-ways").num_args(1))
+                .arg(arg!(--types <TYPES> "Export only elements of the given types. Defaults to the types of --ids, or all types").value_parser(["node", "way", "relation"]).num_args(1..))
+                .arg(arg!(--ids <IDS> "Export only elements with ids in the given ranges, in the form type:id or type:first-last as in way:1000-2000. Types without ranges are not restricted by id").value_parser(clap::value_parser!(String)).num_args(1..))
+                .arg(arg!(--referenced "Also export the nodes of the selected ways and the members of the selected relations, including the nodes of the member ways. Requires an additional pass").required(false).num_args(0))
                 .arg(arg!(--"osmosis-replication-timestamp" <OSMOSIS_REPLICATION_TIMESTAMP> "Osmosis replication timestamp").value_parser(clap::value_parser!(i64)).num_args(1))
                 .arg(arg!(--"osmosis-replication-sequence-number" <OSMOSIS_REPLICATION_SEQUENCE_NUMBER> "Osmosis replication sequence number").value_parser(clap::value_parser!(i64)).num_args(1))
                 .arg(arg!(--"osmosis-replication-base-url" <OSMOSIS_REPLICATION_BASE_URL> "Osmosis replication base url").value_parser(clap::value_parser!(String)).num_args(1))
-                .arg(arg!(--jobs <JOBS> "Number of database dump jobs. Zero means autodetect CPU allocation and use all CPUs. When more jobs than CPUs specified, the number is capped on CPU limit").value_parser(0..1024).default_value("0").num_args(1))
-                .arg(arg!(--host <HOST> "Database host").required(true).num_args(1))
-                .arg(arg!(--port <PORT> "Database port").default_value("5432").num_args(1))
-                .arg(arg!(--database <DATABASE> "Database name").default_value("openstreetmap").num_args(1))
-                .arg(arg!(--user <USER> "Database administrator user name").value_parser(clap::value_parser!(String)).required(true).num_args(1))
-                .arg(arg!(--password "Prompt for password. Either --password or --no-password must be present.").required(false).num_args(0))
-                .arg(arg!(--"no-password" "Don't prompt for password. Use PGPASSFILE if available").required(false).num_args(0))
+                .arg(arg!(--"progress-format" <PROGRESS_FORMAT> "Format of the progress reports. json writes one event per line to stderr with the phase, counts, rate, ETA, disk usage and elapsed time, and implies --verbose").value_parser(["text", "json"]).default_value("text").num_args(1))
+                .arg(arg!(--"metrics-file" <METRICS_FILE> "Write Prometheus metrics of the long running phases periodically to the file, for the node-exporter textfile collector (*.prom)").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--jobs <JOBS> "Number of database dump jobs and of PBF encoding threads. Zero means autodetect CPU allocation and use all CPUs. When more jobs than CPUs specified, the number is capped on CPU limit").value_parser(0..1024).default_value("0").num_args(1))
+                .arg(arg!(--host <HOST> "Database host, or the directory of a Unix-domain socket. Defaults to the connection URI, the service or PGHOST").num_args(1))
+                .arg(arg!(--port <PORT> "Database port. Defaults to the connection URI, the service, PGPORT or 5432").num_args(1))
+                .arg(arg!(--database <DATABASE> "Database name, or a postgresql:// connection URI. Defaults to the service, PGDATABASE or openstreetmap").num_args(1))
+                .arg(arg!(--schema <SCHEMA> "Database schema of the apidb tables. The apidb dump is bound to the public schema, so other schemas are read only with --read copy").default_value("public").num_args(1))
+                .arg(arg!(--user <USER> "Database administrator user name. Defaults to the connection URI, the service or PGUSER").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--password "Prompt for password. One of --password, --password-file, --password-env or --no-password must be present.").required(false).num_args(0))
+                .arg(arg!(--"password-file" <PASSWORD_FILE> "Read the password from the first line of the file, for non-interactive runs").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"password-env" <PASSWORD_ENV> "Read the password from the environment variable, for non-interactive runs").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--"no-password" "Don't prompt for password. Use the --pgpass file if available").required(false).num_args(0))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("apply-changes").about("Apply OSM changes from osmChange files to the database in a single transaction")
+                .arg(arg!(--input <INPUT> "Input osmChange file path (*.osc or *.osc.gz). Multiple files are applied in the order given").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1..))
+                .arg(arg!(--host <HOST> "Database host, or the directory of a Unix-domain socket. Defaults to the connection URI, the service or PGHOST").num_args(1))
+                .arg(arg!(--port <PORT> "Database port. Defaults to the connection URI, the service, PGPORT or 5432").num_args(1))
+                .arg(arg!(--database <DATABASE> "Database name, or a postgresql:// connection URI. Defaults to the service, PGDATABASE or openstreetmap").num_args(1))
+                .arg(arg!(--schema <SCHEMA> "Database schema of the apidb tables").default_value("public").num_args(1))
+                .arg(arg!(--user <USER> "Database administrator user name. Defaults to the connection URI, the service or PGUSER").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--password "Prompt for password. One of --password, --password-file, --password-env or --no-password must be present.").required(false).num_args(0))
+                .arg(arg!(--"password-file" <PASSWORD_FILE> "Read the password from the first line of the file, for non-interactive runs").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"password-env" <PASSWORD_ENV> "Read the password from the environment variable, for non-interactive runs").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--"no-password" "Don't prompt for password. Use the --pgpass file if available").required(false).num_args(0))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("changes").about("Publish the changes in the database as the next osmChange file of a replication directory")
+                .arg(arg!(--"replication-dir" <REPLICATION_DIR> "Replication directory to publish to, with state.txt and change files in the osmosis AAA/BBB/CCC.osc.gz layout").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--since <SINCE> "Publish the changes after a timestamp, as in 2023-06-12T10:00:02Z, or after a sequence number of the replication directory. Defaults to the state.txt of the replication directory").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--host <HOST> "Database host, or the directory of a Unix-domain socket. Defaults to the connection URI, the service or PGHOST").num_args(1))
+                .arg(arg!(--port <PORT> "Database port. Defaults to the connection URI, the service, PGPORT or 5432").num_args(1))
+                .arg(arg!(--database <DATABASE> "Database name, or a postgresql:// connection URI. Defaults to the service, PGDATABASE or openstreetmap").num_args(1))
+                .arg(arg!(--schema <SCHEMA> "Database schema of the apidb tables").default_value("public").num_args(1))
+                .arg(arg!(--user <USER> "Database administrator user name. Defaults to the connection URI, the service or PGUSER").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--password "Prompt for password. One of --password, --password-file, --password-env or --no-password must be present.").required(false).num_args(0))
+                .arg(arg!(--"password-file" <PASSWORD_FILE> "Read the password from the first line of the file, for non-interactive runs").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"password-env" <PASSWORD_ENV> "Read the password from the environment variable, for non-interactive runs").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--"no-password" "Don't prompt for password. Use the --pgpass file if available").required(false).num_args(0))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("diff").about("Compare the current objects of two sorted PBF files and report the added, removed and modified objects by type")
+                .arg(arg!(--from <FROM> "The PBF file to compare from").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--to <TO> "The PBF file to compare to").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--report <REPORT> "Path of the detailed report, a JSON line per changed object with the field level differences of modified objects. Compressed when the path ends with .gz or .bz2").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--osc <OSC> "Path of an osmChange file that transforms --from into --to. Compressed when the path ends with .gz or .bz2").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("verify").about("Verify an import by comparing per type counts, max ids, version sums and a hash of the tags of the input file and the database")
+                .arg(arg!(--input <INPUT> "Input file path").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"input-format" <INPUT_FORMAT> "The input format, pbf or osm for OSM XML").value_parser(["pbf", "osm"]).default_value("pbf").num_args(1))
+                .arg(arg!(--report <REPORT> "Path of the JSON verification report. Printed to stdout when not specified").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--host <HOST> "Database host, or the directory of a Unix-domain socket. Defaults to the connection URI, the service or PGHOST").num_args(1))
+                .arg(arg!(--port <PORT> "Database port. Defaults to the connection URI, the service, PGPORT or 5432").num_args(1))
+                .arg(arg!(--database <DATABASE> "Database name, or a postgresql:// connection URI. Defaults to the service, PGDATABASE or openstreetmap").num_args(1))
+                .arg(arg!(--schema <SCHEMA> "Database schema of the apidb tables").default_value("public").num_args(1))
+                .arg(arg!(--user <USER> "Database administrator user name. Defaults to the connection URI, the service or PGUSER").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--password "Prompt for password. One of --password, --password-file, --password-env or --no-password must be present.").required(false).num_args(0))
+                .arg(arg!(--"password-file" <PASSWORD_FILE> "Read the password from the first line of the file, for non-interactive runs").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"password-env" <PASSWORD_ENV> "Read the password from the environment variable, for non-interactive runs").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--"no-password" "Don't prompt for password. Use the --pgpass file if available").required(false).num_args(0))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("sync").about("Apply the OSM changes missing from the database from a local replication directory")
+                .arg(arg!(--"replication-dir" <REPLICATION_DIR> "Replication directory with state.txt and change files in the osmosis AAA/BBB/CCC.osc.gz layout").required(true).value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"start-sequence-number" <START_SEQUENCE_NUMBER> "The first sequence number to apply when the database has no replication state").value_parser(clap::value_parser!(i64)).num_args(1))
+                .arg(arg!(--host <HOST> "Database host, or the directory of a Unix-domain socket. Defaults to the connection URI, the service or PGHOST").num_args(1))
+                .arg(arg!(--port <PORT> "Database port. Defaults to the connection URI, the service, PGPORT or 5432").num_args(1))
+                .arg(arg!(--database <DATABASE> "Database name, or a postgresql:// connection URI. Defaults to the service, PGDATABASE or openstreetmap").num_args(1))
+                .arg(arg!(--schema <SCHEMA> "Database schema of the apidb tables").default_value("public").num_args(1))
+                .arg(arg!(--user <USER> "Database administrator user name. Defaults to the connection URI, the service or PGUSER").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--password "Prompt for password. One of --password, --password-file, --password-env or --no-password must be present.").required(false).num_args(0))
+                .arg(arg!(--"password-file" <PASSWORD_FILE> "Read the password from the first line of the file, for non-interactive runs").value_parser(clap::value_parser!(PathBuf)).num_args(1))
+                .arg(arg!(--"password-env" <PASSWORD_ENV> "Read the password from the environment variable, for non-interactive runs").value_parser(clap::value_parser!(String)).num_args(1))
+                .arg(arg!(--"no-password" "Don't prompt for password. Use the --pgpass file if available").required(false).num_args(0))
                 .arg_required_else_help(true),
         )
 }
@@ -78,16 +184,51 @@ fn main() -> Result<(), Error> {
     }
 
     log::info!("Started OSM Admin.");
-    let var_log_path = PathBuf::from("/var/log/osm/");
-    let var_lib_path = PathBuf::from("/var/lib/osm/");
+    let var_log_path = matches.get_one::<PathBuf>("log-dir")
+        .unwrap()
+        .clone();
+    let var_lib_path = matches.get_one::<PathBuf>("work-dir")
+        .unwrap()
+        .clone();
+    if let Some(pgpass_path) = matches.get_one::<PathBuf>("pgpass") {
+        // the in-process client and the spawned pg_dump and pg_restore read the password file
+        // from PGPASSFILE
+        std::env::set_var("PGPASSFILE", pgpass_path);
+    }
+    // likewise for the SSL settings
+    if let Some(ssl_mode) = matches.get_one::<String>("sslmode") {
+        std::env::set_var("PGSSLMODE", ssl_mode);
+    }
+    for (id, var) in [("sslrootcert", "PGSSLROOTCERT"), ("sslcert", "PGSSLCERT"), ("sslkey", "PGSSLKEY")] {
+        if let Some(path) = matches.get_one::<PathBuf>(id) {
+            std::env::set_var(var, path);
+        }
+    }
 
     let result = match matches.subcommand() {
         Some(("import", sub_matches)) => {
+            create_dirs(&var_log_path, &var_lib_path)?;
             handle_import(&var_log_path, &var_lib_path, sub_matches, verbose)
         }
         Some(("export", sub_matches)) => {
+            create_dirs(&var_log_path, &var_lib_path)?;
             handle_export(&var_log_path, &var_lib_path, sub_matches, verbose)
         }
+        Some(("apply-changes", sub_matches)) => {
+            handle_apply_changes(sub_matches)
+        }
+        Some(("sync", sub_matches)) => {
+            handle_sync(sub_matches)
+        }
+        Some(("changes", sub_matches)) => {
+            handle_changes(sub_matches)
+        }
+        Some(("diff", sub_matches)) => {
+            handle_diff(sub_matches)
+        }
+        Some(("verify", sub_matches)) => {
+            handle_verify(sub_matches)
+        }
         Some((_, _)) => {
             command_clone.print_help()?;
             exit(1);
@@ -116,135 +257,348 @@ fn handle_import(
     verbose: bool,
 ) -> Result<(), Error> {
     log::info!("Started OSM import");
-    let input_path = sub_matches.get_one::<PathBuf>("input")
-        .unwrap()
-        .clone();
-    let input_format = sub_matches.get_one::<String>("input-format")
-        .unwrap()
-        .clone();
-    let output_path = sub_matches.get_one::<PathBuf>("output")
-        .unwrap()
-        .clone();
+    let verbose = verbose || get_progress_format(sub_matches)? == ProgressFormat::Json;
+    if let Some(metrics_path) = sub_matches.get_one::<PathBuf>("metrics-file") {
+        set_metrics_file(metrics_path.clone())?;
+    }
+    let input = get_input_options(sub_matches);
+    let load = LoadOptions {
+        load: sub_matches.get_one::<String>("load")
+            .cloned()
+            .unwrap_or(default_transfer(sub_matches, "pg-restore")),
+        output_path: sub_matches.get_one::<PathBuf>("output").cloned(),
+        resume: sub_matches.get_flag("resume"),
+    };
+    let tags = get_tag_filter_options(sub_matches);
     let jobs: i16 = adjust_jobs_to_available_cpus(
         *sub_matches.get_one::<i64>("jobs").unwrap() as i16
     );
-    let host = sub_matches.get_one::<String>("host")
-        .unwrap()
-        .clone();
-    let port = sub_matches.get_one::<String>("port")
-        .unwrap()
-        .clone();
-    let database = sub_matches.get_one::<String>("database")
-        .unwrap()
-        .clone();
-    let user = sub_matches.get_one::<String>("user")
-        .unwrap()
-        .clone();
-    let prompt_password = sub_matches.get_flag("password");
-    let dont_prompt_password = sub_matches.get_flag("no-password");
-    let password = get_password(prompt_password, dont_prompt_password)?;
+    let database = get_database(sub_matches)?;
+    let work_paths = WorkPaths {
+        var_lib_path: var_lib_path.clone(),
+        var_log_path: var_log_path.clone(),
+    };
 
     import(
-        input_path,
-        input_format,
-        output_path,
+        input,
+        load,
+        tags,
         jobs,
-        host,
-        port,
         database,
-        user,
-        password,
-        &var_lib_path,
-        &var_log_path,
+        &work_paths,
         verbose,
     )
 }
 
+/// Set the progress format of the import or export and return it
+fn get_progress_format(sub_matches: &ArgMatches) -> Result<ProgressFormat, Error> {
+    let progress_format = ProgressFormat::from_str(sub_matches.get_one::<String>("progress-format").unwrap())?;
+    set_progress_format(progress_format)?;
+    Ok(progress_format)
+}
+
+/// The apidb dump is bound to the public schema, other schemas are transferred with COPY
+fn default_transfer(sub_matches: &ArgMatches, dump_transfer: &str) -> String {
+    match sub_matches.get_one::<String>("schema").map(|schema| schema.as_str()) {
+        None | Some("public") => { dump_transfer.to_string() }
+        Some(_) => { "copy".to_string() }
+    }
+}
+
+fn get_input_options(sub_matches: &ArgMatches) -> InputOptions {
+    InputOptions {
+        path: sub_matches.get_one::<PathBuf>("input")
+            .unwrap()
+            .clone(),
+        format: sub_matches.get_one::<String>("input-format")
+            .unwrap()
+            .clone(),
+    }
+}
+
+fn get_tag_filter_options(sub_matches: &ArgMatches) -> TagFilterOptions {
+    TagFilterOptions {
+        keep_tags: get_many_strings(sub_matches, "keep-tags"),
+        drop_tags: get_many_strings(sub_matches, "drop-tags"),
+    }
+}
+
+fn get_many_strings(sub_matches: &ArgMatches, id: &str) -> Vec<String> {
+    sub_matches.get_many::<String>(id)
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default()
+}
+
 fn handle_export(
     var_log_path: &PathBuf,
     var_lib_path: &PathBuf,
     sub_matches: &ArgMatches,
     verbose: bool,
 ) -> Result<(), Error> {
-    let dump_path = sub_matches.get_one::<PathBuf>("dump")
-        .unwrap()
-        .clone();
-    let output_path = sub_matches.get_one::<PathBuf>("output")
-        .unwrap()
-        .clone();
-    let output_format = sub_matches.get_one::<String>("output-format")
-        .unwrap()
-        .clone();
-    let bounding_box_opt = sub_matches.get_one::<String>("bounding-box")
-        .clone();
-    let bounding_box = match bounding_box_opt {
-        None => { None }
-        Some(s) => {
-            Some(BoundingBox::from_str(s)?)
-        }
+    let verbose = verbose || get_progress_format(sub_matches)? == ProgressFormat::Json;
+    if let Some(metrics_path) = sub_matches.get_one::<PathBuf>("metrics-file") {
+        set_metrics_file(metrics_path.clone())?;
+    }
+    let read = ReadOptions {
+        read: sub_matches.get_one::<String>("read")
+            .cloned()
+            .unwrap_or(default_transfer(sub_matches, "pg-dump")),
+        dump_path: sub_matches.get_one::<PathBuf>("dump").cloned(),
+    };
+    let output = OutputOptions {
+        path: sub_matches.get_one::<PathBuf>("output")
+            .unwrap()
+            .clone(),
+        format: sub_matches.get_one::<String>("output-format")
+            .unwrap()
+            .clone(),
+        compression: sub_matches.get_one::<String>("compression")
+            .unwrap()
+            .clone(),
+        compression_level: sub_matches.get_one::<i32>("compression-level").copied(),
+        bounding_box: match sub_matches.get_one::<String>("bounding-box") {
+            None => { None }
+            Some(s) => {
+                Some(BoundingBox::from_str(s)?)
+            }
+        },
+        calc_bounding_box: sub_matches.get_flag("calc-bounding-box"),
+        osmosis_replication_timestamp: sub_matches.get_one::<i64>("osmosis-replication-timestamp").copied(),
+        osmosis_replication_sequence_number: sub_matches.get_one::<i64>("osmosis-replication-sequence-number").copied(),
+        osmosis_replication_base_url: sub_matches.get_one::<String>("osmosis-replication-base-url").cloned(),
+    };
+    let filter = FilterOptions {
+        tags: get_tag_filter_options(sub_matches),
+        select_types: get_many_strings(sub_matches, "types"),
+        select_ids: get_many_strings(sub_matches, "ids"),
+        select_referenced: sub_matches.get_flag("referenced"),
+        current_only: sub_matches.get_flag("current-only"),
+        extract_bounding_box: match sub_matches.get_one::<String>("extract-bbox") {
+            None => { None }
+            Some(s) => {
+                Some(BoundingBox::from_str(s)?)
+            }
+        },
+        extract_polygon_path: sub_matches.get_one::<PathBuf>("extract-poly").cloned(),
+        extract_strategy: sub_matches.get_one::<String>("extract-strategy")
+            .unwrap()
+            .clone(),
     };
-    let calc_bounding_box = sub_matches.get_flag("calc-bounding-box");
-    let osmosis_replication_timestamp = sub_matches.get_one::<i64>("osmosis-replication-timestamp").copied();
-    let osmosis_replication_sequence_number = sub_matches.get_one::<i64>("osmosis-replication-sequence-number").copied();
-    let osmosis_replication_base_url = sub_matches.get_one::<String>("osmosis-replication-base-url").cloned();
     let jobs: i16 = adjust_jobs_to_available_cpus(
         *sub_matches.get_one::<i64>("jobs").unwrap() as i16
     );
-    let host = sub_matches.get_one::<String>("host")
+    let database = get_database(sub_matches)?;
+    let work_paths = WorkPaths {
+        var_lib_path: var_lib_path.clone(),
+        var_log_path: var_log_path.clone(),
+    };
+
+    log::info!("Started OSM export");
+    let result = export(
+        read,
+        output,
+        filter,
+        jobs,
+        database,
+        &work_paths,
+        verbose,
+    );
+    match &result {
+        Ok(_) => {
+            log::info!("Finished OSM export")
+        }
+        Err(e) => {
+            log::error!("Failed OSM export: {}", e);
+        }
+    }
+    result
+}
+
+fn handle_apply_changes(
+    sub_matches: &ArgMatches,
+) -> Result<(), Error> {
+    let input_paths: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("input")
+        .unwrap()
+        .cloned()
+        .collect();
+    let database = get_database(sub_matches)?;
+
+    log::info!("Started OSM apply changes");
+    let result = apply_changes(
+        input_paths,
+        database,
+    );
+    match &result {
+        Ok(_) => {
+            log::info!("Finished OSM apply changes")
+        }
+        Err(e) => {
+            log::error!("Failed OSM apply changes: {}", e);
+        }
+    }
+    result
+}
+
+fn handle_sync(
+    sub_matches: &ArgMatches,
+) -> Result<(), Error> {
+    let replication_path = sub_matches.get_one::<PathBuf>("replication-dir")
         .unwrap()
         .clone();
-    let port = sub_matches.get_one::<String>("port")
+    let start_sequence_number = sub_matches.get_one::<i64>("start-sequence-number").copied();
+    let database = get_database(sub_matches)?;
+
+    log::info!("Started OSM sync");
+    let result = sync(
+        replication_path,
+        start_sequence_number,
+        database,
+    );
+    match &result {
+        Ok(_) => {
+            log::info!("Finished OSM sync")
+        }
+        Err(e) => {
+            log::error!("Failed OSM sync: {}", e);
+        }
+    }
+    result
+}
+
+fn handle_changes(
+    sub_matches: &ArgMatches,
+) -> Result<(), Error> {
+    let replication_path = sub_matches.get_one::<PathBuf>("replication-dir")
         .unwrap()
         .clone();
-    let database = sub_matches.get_one::<String>("database")
+    let since = sub_matches.get_one::<String>("since").cloned();
+    let database = get_database(sub_matches)?;
+
+    log::info!("Started OSM changes");
+    let result = changes(replication_path, since, database);
+    match &result {
+        Ok(_) => {
+            log::info!("Finished OSM changes")
+        }
+        Err(e) => {
+            log::error!("Failed OSM changes: {}", e);
+        }
+    }
+    result
+}
+
+fn handle_diff(
+    sub_matches: &ArgMatches,
+) -> Result<(), Error> {
+    let from_path = sub_matches.get_one::<PathBuf>("from")
         .unwrap()
         .clone();
-    let user = sub_matches.get_one::<String>("user")
+    let to_path = sub_matches.get_one::<PathBuf>("to")
         .unwrap()
         .clone();
-    let prompt_password = sub_matches.get_flag("password");
-    let dont_prompt_password = sub_matches.get_flag("no-password");
-    let password = get_password(prompt_password, dont_prompt_password)?;
+    let report_path = sub_matches.get_one::<PathBuf>("report").cloned();
+    let osc_path = sub_matches.get_one::<PathBuf>("osc").cloned();
 
-    log::info!("Started OSM export");
-    let result = export(
-        &dump_path,
-        &output_path,
-        output_format,
-        bounding_box,
-        calc_bounding_box,
-        osmosis_replication_timestamp,
-        osmosis_replication_sequence_number,
-        osmosis_replication_base_url,
-        jobs,
-        host,
-        port,
-        database,
-        user,
-        password,
-        &var_lib_path,
-        &var_log_path,
-        verbose,
+    log::info!("Started OSM diff");
+    let result = diff(
+        from_path,
+        to_path,
+        report_path,
+        osc_path,
     );
     match &result {
         Ok(_) => {
-            log::info!("Finished OSM export")
+            log::info!("Finished OSM diff")
         }
         Err(e) => {
-            log::error!("Failed OSM export: {}", e);
+            log::error!("Failed OSM diff: {}", e);
         }
     }
     result
 }
 
-fn get_password(prompt_password: bool, dont_prompt_password: bool) -> Result<Option<String>, anyhow::Error> {
-    let password = if prompt_password {
+fn handle_verify(
+    sub_matches: &ArgMatches,
+) -> Result<(), Error> {
+    let input = get_input_options(sub_matches);
+    let report_path = sub_matches.get_one::<PathBuf>("report").cloned();
+    let database = get_database(sub_matches)?;
+
+    log::info!("Started OSM verify");
+    let result = verify(input, report_path, database);
+    match &result {
+        Ok(_) => {
+            log::info!("Finished OSM verify")
+        }
+        Err(e) => {
+            log::error!("Failed OSM verify: {}", e);
+        }
+    }
+    result
+}
+
+fn create_dirs(var_log_path: &PathBuf, var_lib_path: &PathBuf) -> Result<(), Error> {
+    for path in [var_log_path, var_lib_path] {
+        fs::create_dir_all(path)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// The database of the connection parameters and the schema of the options
+fn get_database(sub_matches: &ArgMatches) -> Result<Database, anyhow::Error> {
+    Ok(
+        Database {
+            connection: get_connection_parameters(sub_matches)?,
+            schema: sub_matches.get_one::<String>("schema")
+                .unwrap()
+                .clone(),
+        }
+    )
+}
+
+/// The connection parameters of the options, the connection URI, the service and the libpq
+/// environment variables. A prompted password overrides the password of the URI.
+fn get_connection_parameters(sub_matches: &ArgMatches) -> Result<ConnectionParameters, anyhow::Error> {
+    let password = get_password(sub_matches)?;
+    let mut connection_parameters = ConnectionParameters::resolve(
+        sub_matches.get_one::<String>("host"),
+        sub_matches.get_one::<String>("port"),
+        sub_matches.get_one::<String>("database"),
+        sub_matches.get_one::<String>("user"),
+    )?;
+    if password.is_some() {
+        connection_parameters.password = password;
+    }
+    Ok(connection_parameters)
+}
+
+fn get_password(sub_matches: &ArgMatches) -> Result<Option<String>, anyhow::Error> {
+    let prompt_password = sub_matches.get_flag("password");
+    let password_file = sub_matches.get_one::<PathBuf>("password-file");
+    let password_env = sub_matches.get_one::<String>("password-env");
+    let dont_prompt_password = sub_matches.get_flag("no-password");
+    let sources = [prompt_password, password_file.is_some(), password_env.is_some(), dont_prompt_password];
+    if sources.iter().filter(|source| **source).count() != 1 {
+        return Err(anyhow!("Exactly one of --password, --password-file, --password-env or --no-password must be specified"));
+    }
+    if prompt_password {
         Ok(Some(rpassword::prompt_password("Please enter password: ")?))
-    } else if dont_prompt_password {
+    } else if let Some(password_file) = password_file {
+        let text = fs::read_to_string(password_file)
+            .map_err(|e| anyhow!("{}: {}", password_file.display(), e))?;
+        let password = text.lines().next().unwrap_or("").to_string();
+        if password.is_empty() {
+            return Err(anyhow!("{}: empty password", password_file.display()));
+        }
+        Ok(Some(password))
+    } else if let Some(password_env) = password_env {
+        match std::env::var(password_env) {
+            Ok(password) if !password.is_empty() => { Ok(Some(password)) }
+            _ => { Err(anyhow!("Environment variable {} with the password is not set", password_env)) }
+        }
+    } else {
         // direct pg client to PGPASSFILE
         Ok(None)
-    } else {
-        Err(anyhow!("Either --password or --no-password must be specified"))
-    };
-    password
+    }
 }
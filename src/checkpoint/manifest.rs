@@ -0,0 +1,340 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use json::{object, JsonValue};
+
+use crate::input::stream::is_stream;
+use crate::replication::state::State;
+
+/// Name of the manifest file in the output directory of the import
+pub(crate) const MANIFEST_FILE_NAME: &str = "osm-admin-import.json";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum StepStatus {
+    Started,
+    Complete,
+    Failed,
+}
+
+impl StepStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StepStatus::Started => { "started" }
+            StepStatus::Complete => { "complete" }
+            StepStatus::Failed => { "failed" }
+        }
+    }
+}
+
+impl FromStr for StepStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "started" => { Ok(StepStatus::Started) }
+            "complete" => { Ok(StepStatus::Complete) }
+            "failed" => { Ok(StepStatus::Failed) }
+            _ => { Err(anyhow!("Unknown step status: {}", s)) }
+        }
+    }
+}
+
+/// Progress of an import, kept in a JSON file in the output directory
+///
+/// Each step of the import, such as the dump generation or the restore of one table, is recorded
+/// when it starts, completes or fails, together with the number of elements written to the dump
+/// and the error of the failed step. The manifest is saved after every change so that an
+/// interrupted import can be resumed with the completed steps skipped. The dump generation is
+/// checkpointed with the number of elements written and the lengths of the table data files at
+/// that point, so that a partially generated dump is continued from its last checkpoint.
+#[derive(Debug)]
+pub(crate) struct Manifest {
+    path: PathBuf,
+    input: String,
+    input_size: u64,
+    resumed: bool,
+    steps: Vec<(String, StepStatus)>,
+    elements_written: u64,
+    dump_tables: Vec<(String, u64)>,
+    replication_state: Option<State>,
+    failed_step: Option<String>,
+    error: Option<String>,
+}
+
+impl Manifest {
+    /// Open the manifest in the output directory. When resuming, the existing manifest is loaded
+    /// and must have been created for the same input file, otherwise a new manifest is created.
+    /// The identity of an input streamed from stdin cannot be checked and is recorded with size 0.
+    pub(crate) fn open(output_path: &Path, input_path: &Path, resume: bool) -> Result<Manifest, anyhow::Error> {
+        let path = output_path.join(MANIFEST_FILE_NAME);
+        let input = input_path.display().to_string();
+        let input_size = if is_stream(input_path) {
+            0
+        } else {
+            fs::metadata(input_path)
+                .with_context(|| anyhow!("path: {}", input_path.display()))?
+                .len()
+        };
+        if resume && path.exists() {
+            let manifest = Manifest::load(&path)?;
+            if manifest.input != input || manifest.input_size != input_size {
+                return Err(
+                    anyhow!(
+                        "Cannot resume, the import in {} was started for a different input: {}, size: {}",
+                        output_path.display(),
+                        manifest.input,
+                        manifest.input_size,
+                    )
+                );
+            }
+            log::info!("Resume import from manifest: {}", path.display());
+            if is_stream(input_path) {
+                log::warn!("Resuming an import from stdin, the identity of the input cannot be checked");
+            }
+            if let (Some(failed_step), Some(error)) = (&manifest.failed_step, &manifest.error) {
+                log::info!("Previous import failed at step: {}, error: {}", failed_step, error);
+            }
+            Ok(manifest)
+        } else {
+            if resume {
+                log::info!("No manifest found at {}, starting a new import", path.display());
+            }
+            fs::create_dir_all(output_path)
+                .with_context(|| anyhow!("path: {}", output_path.display()))?;
+            let manifest = Manifest {
+                path,
+                input,
+                input_size,
+                resumed: false,
+                steps: Vec::new(),
+                elements_written: 0,
+                dump_tables: Vec::new(),
+                replication_state: None,
+                failed_step: None,
+                error: None,
+            };
+            manifest.save()?;
+            Ok(manifest)
+        }
+    }
+
+    fn load(path: &PathBuf) -> Result<Manifest, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| anyhow!("path: {}", path.display()))?;
+        let value = json::parse(&content)
+            .with_context(|| anyhow!("path: {}", path.display()))?;
+        let mut steps = Vec::new();
+        for (step, status) in value["steps"].entries() {
+            steps.push((step.to_string(), StepStatus::from_str(status.as_str().unwrap_or_default())?));
+        }
+        let mut dump_tables = Vec::new();
+        for (table, length) in value["dump_tables"].entries() {
+            dump_tables.push((table.to_string(), length.as_u64().ok_or(anyhow!("Invalid length of dump table {} in manifest: {}", table, path.display()))?));
+        }
+        let replication_state = match value["replication_state"].as_str() {
+            None => { None }
+            Some(s) => { Some(State::from_str(s)?) }
+        };
+        Ok(
+            Manifest {
+                path: path.clone(),
+                input: value["input"].as_str().ok_or(anyhow!("Missing input in manifest: {}", path.display()))?.to_string(),
+                input_size: value["input_size"].as_u64().ok_or(anyhow!("Missing input size in manifest: {}", path.display()))?,
+                resumed: true,
+                steps,
+                elements_written: value["elements_written"].as_u64().unwrap_or(0),
+                dump_tables,
+                replication_state,
+                failed_step: value["failed_step"].as_str().map(|s| s.to_string()),
+                error: value["error"].as_str().map(|s| s.to_string()),
+            }
+        )
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let mut steps = JsonValue::new_object();
+        for (step, status) in &self.steps {
+            steps[step.as_str()] = status.as_str().into();
+        }
+        let mut dump_tables = JsonValue::new_object();
+        for (table, length) in &self.dump_tables {
+            dump_tables[table.as_str()] = (*length).into();
+        }
+        let value = object! {
+            input: self.input.as_str(),
+            input_size: self.input_size,
+            elements_written: self.elements_written,
+            dump_tables: dump_tables,
+            replication_state: self.replication_state.as_ref().map(|state| state.to_string()),
+            failed_step: self.failed_step.clone(),
+            error: self.error.clone(),
+            steps: steps,
+        };
+        // write and rename, so that an interruption never leaves a truncated manifest
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, value.pretty(2))
+            .with_context(|| anyhow!("path: {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| anyhow!("path: {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// True if the manifest was loaded from a previous import
+    pub(crate) fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    pub(crate) fn status(&self, step: &str) -> Option<StepStatus> {
+        self.steps.iter()
+            .find(|(name, _)| name == step)
+            .map(|(_, status)| *status)
+    }
+
+    pub(crate) fn is_complete(&self, step: &str) -> bool {
+        self.status(step) == Some(StepStatus::Complete)
+    }
+
+    pub(crate) fn start(&mut self, step: &str) -> Result<(), anyhow::Error> {
+        self.set_status(step, StepStatus::Started);
+        self.save()
+    }
+
+    pub(crate) fn complete(&mut self, step: &str) -> Result<(), anyhow::Error> {
+        self.set_status(step, StepStatus::Complete);
+        if self.failed_step.as_deref() == Some(step) {
+            self.failed_step = None;
+            self.error = None;
+        }
+        self.save()
+    }
+
+    pub(crate) fn fail(&mut self, step: &str, error: &anyhow::Error) -> Result<(), anyhow::Error> {
+        self.set_status(step, StepStatus::Failed);
+        self.failed_step = Some(step.to_string());
+        self.error = Some(format!("{:#}", error));
+        self.save()
+    }
+
+    /// Run the step, recording its start and its completion or failure
+    pub(crate) fn run<T, F>(&mut self, step: &str, f: F) -> Result<T, anyhow::Error>
+        where F: FnOnce(&mut Manifest) -> Result<T, anyhow::Error> {
+        self.start(step)?;
+        match f(self) {
+            Ok(result) => {
+                self.complete(step)?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.fail(step, &e)?;
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn elements_written(&self) -> u64 {
+        self.elements_written
+    }
+
+    /// Record the number of elements written to the completed dump, dropping the checkpoint
+    pub(crate) fn set_elements_written(&mut self, elements_written: u64) -> Result<(), anyhow::Error> {
+        self.elements_written = elements_written;
+        self.dump_tables.clear();
+        self.save()
+    }
+
+    /// Length of the data file of the table at the last checkpoint of the dump generation, if any
+    pub(crate) fn dump_table_length(&self, table: &str) -> Option<u64> {
+        self.dump_tables.iter()
+            .find(|(name, _)| name == table)
+            .map(|(_, length)| *length)
+    }
+
+    /// Record a checkpoint of the dump generation: the number of elements written and the lengths
+    /// of the data files of the tables, which contain the rows of exactly these elements
+    pub(crate) fn set_dump_checkpoint(&mut self, elements_written: u64, dump_tables: Vec<(String, u64)>) -> Result<(), anyhow::Error> {
+        self.elements_written = elements_written;
+        self.dump_tables = dump_tables;
+        self.save()
+    }
+
+    pub(crate) fn replication_state(&self) -> Option<&State> {
+        self.replication_state.as_ref()
+    }
+
+    pub(crate) fn set_replication_state(&mut self, replication_state: Option<State>) -> Result<(), anyhow::Error> {
+        self.replication_state = replication_state;
+        self.save()
+    }
+
+    fn set_status(&mut self, step: &str, status: StepStatus) {
+        match self.steps.iter_mut().find(|(name, _)| name == step) {
+            None => {
+                self.steps.push((step.to_string(), status));
+            }
+            Some((_, current)) => {
+                *current = status;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use anyhow::anyhow;
+    use chrono::DateTime;
+
+    use crate::checkpoint::manifest::{Manifest, StepStatus};
+    use crate::replication::state::State;
+
+    #[test]
+    fn test_resume_manifest() -> Result<(), anyhow::Error> {
+        let output_path = std::env::temp_dir().join("osm-admin-test-resume-manifest");
+        let input_path = PathBuf::from("./tests/fixtures/small.osm");
+        let _ = fs::remove_dir_all(&output_path);
+
+        let mut manifest = Manifest::open(&output_path, &input_path, false)?;
+        let state = State::new(42, DateTime::from_timestamp(1686564002, 0).unwrap());
+        let result: Result<(), anyhow::Error> = manifest.run("dump", |manifest| {
+            manifest.set_dump_checkpoint(5, vec![("nodes".to_string(), 1024), ("users".to_string(), 0)])?;
+            Err(anyhow!("interrupted"))
+        });
+        assert!(result.is_err());
+
+        let mut manifest = Manifest::open(&output_path, &input_path, true)?;
+        assert_eq!(manifest.status("dump"), Some(StepStatus::Failed));
+        assert_eq!(manifest.elements_written(), 5);
+        assert_eq!(manifest.dump_table_length("nodes"), Some(1024));
+        assert_eq!(manifest.dump_table_length("users"), Some(0));
+        assert_eq!(manifest.dump_table_length("ways"), None);
+        manifest.run("dump", |manifest| {
+            manifest.set_elements_written(7)?;
+            manifest.set_replication_state(Some(state.clone()))
+        })?;
+        let result: Result<(), anyhow::Error> = manifest.run("restore data public.nodes", |_| Err(anyhow!("copy failed")));
+        assert!(result.is_err());
+
+        let manifest = Manifest::open(&output_path, &input_path, true)?;
+        assert!(manifest.resumed());
+        assert!(manifest.is_complete("dump"));
+        assert_eq!(manifest.elements_written(), 7);
+        assert_eq!(manifest.dump_table_length("nodes"), None);
+        assert_eq!(manifest.status("restore data public.nodes"), Some(StepStatus::Failed));
+        assert_eq!(manifest.status("restore post-data"), None);
+        assert_eq!(manifest.replication_state(), Some(&state));
+        assert_eq!(manifest.error.as_deref(), Some("copy failed"));
+
+        let other_input_path = PathBuf::from("./tests/fixtures/small.osm.bz2");
+        assert!(Manifest::open(&output_path, &other_input_path, true).is_err());
+
+        let manifest = Manifest::open(&output_path, &input_path, false)?;
+        assert!(!manifest.resumed());
+        assert_eq!(manifest.status("dump"), None);
+        fs::remove_dir_all(&output_path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::node::Node;
+use osm_io::osm::model::relation::{Member, Relation};
+use osm_io::osm::model::way::Way;
+use postgres::{GenericClient, Statement, Transaction};
+
+use crate::connection::Database;
+use crate::db::pg::create_client;
+use crate::db::rows::placeholder_display_names;
+use crate::db::sql::{calculate_tile, to_sql_time_millis};
+use crate::xml::action::Action;
+use crate::xml::reader::Reader;
+
+/// Counts of changes applied to one element type
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ActionCounts {
+    pub(crate) created: i64,
+    pub(crate) modified: i64,
+    pub(crate) deleted: i64,
+    pub(crate) skipped: i64,
+}
+
+impl ActionCounts {
+    pub(crate) fn increment(&mut self, action: Action, applied: bool) {
+        if !applied {
+            self.skipped += 1;
+        } else {
+            match action {
+                Action::Create => { self.created += 1 }
+                Action::Modify => { self.modified += 1 }
+                Action::Delete => { self.deleted += 1 }
+            }
+        }
+    }
+}
+
+impl Display for ActionCounts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "created: {}, modified: {}, deleted: {}, skipped: {}", self.created, self.modified, self.deleted, self.skipped)
+    }
+}
+
+/// Counts of changes applied to the database
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ChangeCounts {
+    pub(crate) nodes: ActionCounts,
+    pub(crate) ways: ActionCounts,
+    pub(crate) relations: ActionCounts,
+}
+
+/// Apply osmChange files to the apidb schema in a single transaction
+///
+/// Each element version is added to the history tables (nodes, ways, relations and their tags,
+/// way nodes and members) and replaces the current version in the current_* tables when it is
+/// newer. Versions that are already present are skipped, so overlapping change files may be
+/// applied more than once.
+pub(crate) fn apply_changes(database: &Database, input_paths: &Vec<PathBuf>) -> Result<ChangeCounts, anyhow::Error> {
+    log::info!("Apply changes, host: {}:{}, user: {:?}, password provided: {}, files: {}",
+        database.connection.host,
+        database.connection.port,
+        database.connection.user,
+        match database.connection.password {Some(_) => "Yes", None => "No"},
+        input_paths.len(),
+    );
+    let mut client = create_client(database)?;
+    let mut transaction = client.transaction()?;
+    let mut change_applier = ChangeApplier::new(&mut transaction)?;
+    for input_path in input_paths {
+        change_applier.apply_file(&mut transaction, input_path)?;
+    }
+    transaction.commit()?;
+    Ok(change_applier.counts().clone())
+}
+
+/// Applies changes using statements prepared once per connection
+pub(crate) struct ChangeApplier {
+    statements: Statements,
+    changesets: HashSet<i64>,
+    counts: ChangeCounts,
+}
+
+impl ChangeApplier {
+    pub(crate) fn new<C: GenericClient>(client: &mut C) -> Result<ChangeApplier, anyhow::Error> {
+        Ok(
+            ChangeApplier {
+                statements: Statements::prepare(client)?,
+                changesets: HashSet::new(),
+                counts: ChangeCounts::default(),
+            }
+        )
+    }
+
+    pub(crate) fn apply_file(&mut self, transaction: &mut Transaction, input_path: &PathBuf) -> Result<(), anyhow::Error> {
+        log::info!("Apply changes from: {}", input_path.display());
+        for result in Reader::from_path(input_path)? {
+            let (action, element) = result?;
+            let action = action.ok_or(
+                anyhow!("Element outside of create, modify or delete in osmChange file: {}", input_path.display())
+            )?;
+            self.apply(transaction, action, element)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn counts(&self) -> &ChangeCounts {
+        &self.counts
+    }
+
+    fn apply(&mut self, transaction: &mut Transaction, action: Action, element: Element) -> Result<(), anyhow::Error> {
+        match element {
+            Element::Node { node } => {
+                let applied = self.apply_node(transaction, action, node)?;
+                self.counts.nodes.increment(action, applied);
+            }
+            Element::Way { way } => {
+                let applied = self.apply_way(transaction, way)?;
+                self.counts.ways.increment(action, applied);
+            }
+            Element::Relation { relation } => {
+                let applied = self.apply_relation(transaction, relation)?;
+                self.counts.relations.increment(action, applied);
+            }
+            Element::Sentinel => {}
+        }
+        Ok(())
+    }
+
+    fn apply_node(&mut self, transaction: &mut Transaction, action: Action, node: Node) -> Result<bool, anyhow::Error> {
+        let timestamp = to_sql_time_millis(node.timestamp())?;
+        self.ensure_changeset(transaction, node.changeset(), node.uid(), node.user(), &timestamp)?;
+        let (latitude, longitude, tile) = self.node_location(transaction, action, &node)?;
+        let version = node.version() as i64;
+
+        let inserted = transaction.execute(
+            &self.statements.insert_node,
+            &[&node.id(), &latitude, &longitude, &node.changeset(), &node.visible(), &timestamp, &tile, &version],
+        )?;
+        if inserted > 0 {
+            for tag in node.tags() {
+                transaction.execute(&self.statements.insert_node_tag, &[&node.id(), &version, tag.k(), tag.v()])?;
+            }
+        }
+
+        let updated = transaction.execute(
+            &self.statements.upsert_current_node,
+            &[&node.id(), &latitude, &longitude, &node.changeset(), &node.visible(), &timestamp, &tile, &version],
+        )?;
+        if updated > 0 {
+            transaction.execute(&self.statements.delete_current_node_tags, &[&node.id()])?;
+            if node.visible() {
+                for tag in node.tags() {
+                    transaction.execute(&self.statements.insert_current_node_tag, &[&node.id(), tag.k(), tag.v()])?;
+                }
+            }
+        }
+        Ok(updated > 0)
+    }
+
+    fn apply_way(&mut self, transaction: &mut Transaction, way: Way) -> Result<bool, anyhow::Error> {
+        let timestamp = to_sql_time_millis(way.timestamp())?;
+        self.ensure_changeset(transaction, way.changeset(), way.uid(), way.user(), &timestamp)?;
+        let version = way.version() as i64;
+
+        let inserted = transaction.execute(
+            &self.statements.insert_way,
+            &[&way.id(), &way.changeset(), &timestamp, &version, &way.visible()],
+        )?;
+        if inserted > 0 {
+            for (sequence_id, node_id) in way.refs().iter().enumerate() {
+                let sequence_id = sequence_id as i64 + 1;
+                transaction.execute(&self.statements.insert_way_node, &[&way.id(), node_id, &version, &sequence_id])?;
+            }
+            for tag in way.tags() {
+                transaction.execute(&self.statements.insert_way_tag, &[&way.id(), tag.k(), tag.v(), &version])?;
+            }
+        }
+
+        let updated = transaction.execute(
+            &self.statements.upsert_current_way,
+            &[&way.id(), &way.changeset(), &timestamp, &way.visible(), &version],
+        )?;
+        if updated > 0 {
+            transaction.execute(&self.statements.delete_current_way_nodes, &[&way.id()])?;
+            transaction.execute(&self.statements.delete_current_way_tags, &[&way.id()])?;
+            if way.visible() {
+                for (sequence_id, node_id) in way.refs().iter().enumerate() {
+                    let sequence_id = sequence_id as i64 + 1;
+                    transaction.execute(&self.statements.insert_current_way_node, &[&way.id(), node_id, &sequence_id])?;
+                }
+                for tag in way.tags() {
+                    transaction.execute(&self.statements.insert_current_way_tag, &[&way.id(), tag.k(), tag.v()])?;
+                }
+            }
+        }
+        Ok(updated > 0)
+    }
+
+    fn apply_relation(&mut self, transaction: &mut Transaction, relation: Relation) -> Result<bool, anyhow::Error> {
+        let timestamp = to_sql_time_millis(relation.timestamp())?;
+        self.ensure_changeset(transaction, relation.changeset(), relation.uid(), relation.user(), &timestamp)?;
+        let version = relation.version() as i64;
+
+        let inserted = transaction.execute(
+            &self.statements.insert_relation,
+            &[&relation.id(), &relation.changeset(), &timestamp, &version, &relation.visible()],
+        )?;
+        if inserted > 0 {
+            for (sequence_id, member) in relation.members().iter().enumerate() {
+                let (member_type, member_id, member_role) = member_values(member);
+                let sequence_id = sequence_id as i32 + 1;
+                transaction.execute(
+                    &self.statements.insert_relation_member,
+                    &[&relation.id(), &member_type, &member_id, member_role, &version, &sequence_id],
+                )?;
+            }
+            for tag in relation.tags() {
+                transaction.execute(&self.statements.insert_relation_tag, &[&relation.id(), tag.k(), tag.v(), &version])?;
+            }
+        }
+
+        let updated = transaction.execute(
+            &self.statements.upsert_current_relation,
+            &[&relation.id(), &relation.changeset(), &timestamp, &relation.visible(), &version],
+        )?;
+        if updated > 0 {
+            transaction.execute(&self.statements.delete_current_relation_members, &[&relation.id()])?;
+            transaction.execute(&self.statements.delete_current_relation_tags, &[&relation.id()])?;
+            if relation.visible() {
+                for (sequence_id, member) in relation.members().iter().enumerate() {
+                    let (member_type, member_id, member_role) = member_values(member);
+                    let sequence_id = sequence_id as i32 + 1;
+                    transaction.execute(
+                        &self.statements.insert_current_relation_member,
+                        &[&relation.id(), &member_type, &member_id, member_role, &sequence_id],
+                    )?;
+                }
+                for tag in relation.tags() {
+                    transaction.execute(&self.statements.insert_current_relation_tag, &[&relation.id(), tag.k(), tag.v()])?;
+                }
+            }
+        }
+        Ok(updated > 0)
+    }
+
+    /// Create the changeset and its user unless they exist, as the element tables reference them.
+    /// A user whose display name is taken by another user, for example after a rename, is created
+    /// with the first free placeholder display name, `{name}_{uid}` or `{name}_{uid}_{counter}`.
+    fn ensure_changeset(&mut self, transaction: &mut Transaction, changeset: i64, uid: i32, user: &str, timestamp: &NaiveDateTime) -> Result<(), anyhow::Error> {
+        if self.changesets.insert(changeset) {
+            let uid = uid as i64;
+            let email = format!("osm-admin-user-{}@example.com", uid);
+            for display_name in display_names(user, uid) {
+                if transaction.execute(&self.statements.insert_user, &[&email, &uid, &display_name])? > 0 {
+                    break;
+                }
+                if !transaction.query(&self.statements.select_user, &[&uid])?.is_empty() {
+                    break;
+                }
+            }
+            transaction.execute(&self.statements.insert_changeset, &[&changeset, &uid, timestamp])?;
+        }
+        Ok(())
+    }
+
+    /// Deleted nodes in osmChange files carry no coordinates, keep the last known location
+    fn node_location(&mut self, transaction: &mut Transaction, action: Action, node: &Node) -> Result<(i32, i32, i64), anyhow::Error> {
+        if action == Action::Delete {
+            let rows = transaction.query(&self.statements.select_current_node_location, &[&node.id()])?;
+            if let Some(row) = rows.first() {
+                return Ok((row.get("latitude"), row.get("longitude"), row.get("tile")));
+            }
+        }
+        Ok(
+            (
+                node.coordinate().lat7() as i32,
+                node.coordinate().lon7() as i32,
+                calculate_tile(node.coordinate().lat(), node.coordinate().lon()),
+            )
+        )
+    }
+}
+
+/// Display names to create a user with, in order, until one is not taken: the name and then the
+/// placeholders of [placeholder_display_names]
+fn display_names(user: &str, uid: i64) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(user.to_string()).chain(placeholder_display_names(user, uid))
+}
+
+fn member_values(member: &Member) -> (&'static str, i64, &String) {
+    match member {
+        Member::Node { member } => {
+            ("Node", member.id(), member.role())
+        }
+        Member::Way { member } => {
+            ("Way", member.id(), member.role())
+        }
+        Member::Relation { member } => {
+            ("Relation", member.id(), member.role())
+        }
+    }
+}
+
+struct Statements {
+    insert_user: Statement,
+    select_user: Statement,
+    insert_changeset: Statement,
+    select_current_node_location: Statement,
+    insert_node: Statement,
+    insert_node_tag: Statement,
+    upsert_current_node: Statement,
+    delete_current_node_tags: Statement,
+    insert_current_node_tag: Statement,
+    insert_way: Statement,
+    insert_way_node: Statement,
+    insert_way_tag: Statement,
+    upsert_current_way: Statement,
+    delete_current_way_nodes: Statement,
+    insert_current_way_node: Statement,
+    delete_current_way_tags: Statement,
+    insert_current_way_tag: Statement,
+    insert_relation: Statement,
+    insert_relation_member: Statement,
+    insert_relation_tag: Statement,
+    upsert_current_relation: Statement,
+    delete_current_relation_members: Statement,
+    insert_current_relation_member: Statement,
+    delete_current_relation_tags: Statement,
+    insert_current_relation_tag: Statement,
+}
+
+impl Statements {
+    fn prepare<C: GenericClient>(client: &mut C) -> Result<Statements, anyhow::Error> {
+        Ok(
+            Statements {
+                insert_user: client.prepare(
+                    "insert into users (email, id, pass_crypt, creation_time, display_name, data_public) \
+                    values ($1, $2, '00000000000000000000000000000000', now() at time zone 'utc', $3, true) \
+                    on conflict do nothing"
+                )?,
+                select_user: client.prepare(
+                    "select id from users where id = $1"
+                )?,
+                insert_changeset: client.prepare(
+                    "insert into changesets (id, user_id, created_at, min_lat, max_lat, min_lon, max_lon, closed_at, num_changes) \
+                    values ($1, $2, $3, -900000000, 900000000, -1800000000, 1800000000, $3, 0) \
+                    on conflict do nothing"
+                )?,
+                select_current_node_location: client.prepare(
+                    "select latitude, longitude, tile from current_nodes where id = $1"
+                )?,
+                insert_node: client.prepare(
+                    "insert into nodes (node_id, latitude, longitude, changeset_id, visible, \"timestamp\", tile, version) \
+                    values ($1, $2, $3, $4, $5, $6, $7, $8) \
+                    on conflict do nothing"
+                )?,
+                insert_node_tag: client.prepare(
+                    "insert into node_tags (node_id, version, k, v) values ($1, $2, $3, $4) on conflict do nothing"
+                )?,
+                upsert_current_node: client.prepare(
+                    "insert into current_nodes (id, latitude, longitude, changeset_id, visible, \"timestamp\", tile, version) \
+                    values ($1, $2, $3, $4, $5, $6, $7, $8) \
+                    on conflict (id) do update set latitude = excluded.latitude, longitude = excluded.longitude, \
+                    changeset_id = excluded.changeset_id, visible = excluded.visible, \"timestamp\" = excluded.\"timestamp\", \
+                    tile = excluded.tile, version = excluded.version \
+                    where current_nodes.version < excluded.version"
+                )?,
+                delete_current_node_tags: client.prepare(
+                    "delete from current_node_tags where node_id = $1"
+                )?,
+                insert_current_node_tag: client.prepare(
+                    "insert into current_node_tags (node_id, k, v) values ($1, $2, $3) on conflict do nothing"
+                )?,
+                insert_way: client.prepare(
+                    "insert into ways (way_id, changeset_id, \"timestamp\", version, visible) \
+                    values ($1, $2, $3, $4, $5) \
+                    on conflict do nothing"
+                )?,
+                insert_way_node: client.prepare(
+                    "insert into way_nodes (way_id, node_id, version, sequence_id) values ($1, $2, $3, $4) on conflict do nothing"
+                )?,
+                insert_way_tag: client.prepare(
+                    "insert into way_tags (way_id, k, v, version) values ($1, $2, $3, $4) on conflict do nothing"
+                )?,
+                upsert_current_way: client.prepare(
+                    "insert into current_ways (id, changeset_id, \"timestamp\", visible, version) \
+                    values ($1, $2, $3, $4, $5) \
+                    on conflict (id) do update set changeset_id = excluded.changeset_id, \"timestamp\" = excluded.\"timestamp\", \
+                    visible = excluded.visible, version = excluded.version \
+                    where current_ways.version < excluded.version"
+                )?,
+                delete_current_way_nodes: client.prepare(
+                    "delete from current_way_nodes where way_id = $1"
+                )?,
+                insert_current_way_node: client.prepare(
+                    "insert into current_way_nodes (way_id, node_id, sequence_id) values ($1, $2, $3)"
+                )?,
+                delete_current_way_tags: client.prepare(
+                    "delete from current_way_tags where way_id = $1"
+                )?,
+                insert_current_way_tag: client.prepare(
+                    "insert into current_way_tags (way_id, k, v) values ($1, $2, $3) on conflict do nothing"
+                )?,
+                insert_relation: client.prepare(
+                    "insert into relations (relation_id, changeset_id, \"timestamp\", version, visible) \
+                    values ($1, $2, $3, $4, $5) \
+                    on conflict do nothing"
+                )?,
+                insert_relation_member: client.prepare(
+                    "insert into relation_members (relation_id, member_type, member_id, member_role, version, sequence_id) \
+                    values ($1, $2::text::nwr_enum, $3, $4, $5, $6) \
+                    on conflict do nothing"
+                )?,
+                insert_relation_tag: client.prepare(
+                    "insert into relation_tags (relation_id, k, v, version) values ($1, $2, $3, $4) on conflict do nothing"
+                )?,
+                upsert_current_relation: client.prepare(
+                    "insert into current_relations (id, changeset_id, \"timestamp\", visible, version) \
+                    values ($1, $2, $3, $4, $5) \
+                    on conflict (id) do update set changeset_id = excluded.changeset_id, \"timestamp\" = excluded.\"timestamp\", \
+                    visible = excluded.visible, version = excluded.version \
+                    where current_relations.version < excluded.version"
+                )?,
+                delete_current_relation_members: client.prepare(
+                    "delete from current_relation_members where relation_id = $1"
+                )?,
+                insert_current_relation_member: client.prepare(
+                    "insert into current_relation_members (relation_id, member_type, member_id, member_role, sequence_id) \
+                    values ($1, $2::text::nwr_enum, $3, $4, $5) \
+                    on conflict do nothing"
+                )?,
+                delete_current_relation_tags: client.prepare(
+                    "delete from current_relation_tags where relation_id = $1"
+                )?,
+                insert_current_relation_tag: client.prepare(
+                    "insert into current_relation_tags (relation_id, k, v) values ($1, $2, $3) on conflict do nothing"
+                )?,
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::db::changes::display_names;
+
+    #[test]
+    fn test_display_names() {
+        let taken: HashSet<String> = ["mapper", "mapper_7", "mapper_7_1"].iter().map(|name| name.to_string()).collect();
+        assert_eq!(display_names("other", 7).find(|name| !taken.contains(name)), Some("other".to_string()));
+        assert_eq!(display_names("mapper", 7).find(|name| !taken.contains(name)), Some("mapper_7_2".to_string()));
+        assert_eq!(
+            display_names("mapper", 7).take(3).collect::<Vec<String>>(),
+            vec!["mapper".to_string(), "mapper_7".to_string(), "mapper_7_1".to_string()]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::io::Write;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+use postgres::Client;
+
+use crate::connection::Database;
+use crate::db::pg::create_client;
+use crate::db::rows::{BATCH_SIZE, ChangesetUsers, Table, TableRows};
+use crate::output::writer::ElementWriter;
+
+/// Record of the foreign keys dropped for the load
+const FOREIGN_KEYS_TABLE: &str = "osm_admin_dropped_foreign_keys";
+
+/// Loader of elements directly into an existing, empty apidb schema using COPY FROM STDIN
+///
+/// Produces the same rows as the apidb dump writer, without writing them to disk and without
+/// pg_restore. Rows are buffered per table and the batches are copied by `jobs` worker
+/// connections in parallel. Foreign keys of the loaded tables are dropped before the load and
+/// recreated after it, so that rows can be copied in any order. The dropped foreign keys are
+/// recorded in the database until the load is complete. A failed load recreates them as NOT VALID,
+/// and the next load finds the record, drops them again and truncates the partially loaded tables
+/// before it starts. Elements must be sorted by type, id and version.
+pub(crate) struct CopyLoader {
+    client: Client,
+    buffers: Vec<String>,
+    table_rows: Vec<TableRows>,
+    changeset_users: ChangesetUsers,
+    closed: bool,
+    sender: Option<SyncSender<(Table, String)>>,
+    workers: Vec<JoinHandle<Result<(), anyhow::Error>>>,
+}
+
+impl CopyLoader {
+    pub(crate) fn new(jobs: i16, database: &Database) -> Result<CopyLoader, anyhow::Error> {
+        let mut client = create_client(database)?;
+        clear_unfinished_load(&mut client)?;
+        check_empty(&mut client)?;
+        drop_foreign_keys(&mut client)?;
+
+        let jobs = jobs.max(1) as usize;
+        let (sender, receiver) = sync_channel::<(Table, String)>(jobs * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(jobs);
+        for _ in 0..jobs {
+            let worker_client = create_client(database)?;
+            let worker_receiver = receiver.clone();
+            workers.push(std::thread::spawn(move || copy_worker(worker_client, worker_receiver)));
+        }
+
+        Ok(
+            CopyLoader {
+                client,
+                buffers: vec![String::new(); Table::ALL.len()],
+                table_rows: Table::ALL.iter()
+                    .filter(|table| table.element_type().is_some())
+                    .map(|table| TableRows::new(*table))
+                    .collect(),
+                changeset_users: ChangesetUsers::new()?,
+                closed: false,
+                sender: Some(sender),
+                workers,
+            }
+        )
+    }
+
+    fn send(&mut self, table: Table, batch: String) -> Result<(), anyhow::Error> {
+        let sender = self.sender.as_ref().ok_or(anyhow!("Loader is closed"))?;
+        if sender.send((table, batch)).is_err() {
+            // all workers are gone, report the reason
+            self.join_workers()?;
+            return Err(anyhow!("COPY workers stopped unexpectedly"));
+        }
+        Ok(())
+    }
+
+    fn join_workers(&mut self) -> Result<(), anyhow::Error> {
+        self.sender = None;
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            let worker_result = worker.join()
+                .map_err(|_| anyhow!("COPY worker panicked"))
+                .and_then(|r| r);
+            if result.is_ok() {
+                result = worker_result;
+            }
+        }
+        result
+    }
+
+    /// Append the rows of the element to the buffers of its tables
+    fn load_element(&mut self, element: &Element) -> Result<(), anyhow::Error> {
+        self.changeset_users.add(element)?;
+        for i in 0..self.table_rows.len() {
+            let table = self.table_rows[i].table();
+            self.table_rows[i].write_element(element, &mut self.buffers[table as usize])?;
+            self.send_full(table)?;
+        }
+        Ok(())
+    }
+
+    fn send_full(&mut self, table: Table) -> Result<(), anyhow::Error> {
+        if self.buffers[table as usize].len() >= BATCH_SIZE {
+            let batch = std::mem::take(&mut self.buffers[table as usize]);
+            self.send(table, batch)?;
+        }
+        Ok(())
+    }
+}
+
+impl ElementWriter for CopyLoader {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        self.load_element(&element)
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        for table_rows in self.table_rows.iter_mut() {
+            table_rows.finish(&mut self.buffers[table_rows.table() as usize]);
+        }
+        let mut changeset_users = std::mem::replace(&mut self.changeset_users, ChangesetUsers::new()?);
+        changeset_users.write_rows(|table, row| {
+            self.buffers[table as usize].push_str(&row);
+            self.send_full(table)
+        })?;
+        for table in Table::ALL {
+            let batch = std::mem::take(&mut self.buffers[table as usize]);
+            if !batch.is_empty() {
+                self.send(table, batch)?;
+            }
+        }
+        self.join_workers()?;
+        log::info!("Finished COPY, restore sequences and foreign keys");
+        restore_sequences(&mut self.client)?;
+        restore_foreign_keys(&mut self.client, true)?;
+        self.closed = true;
+        Ok(())
+    }
+}
+
+impl Drop for CopyLoader {
+    /// Recreate the dropped foreign keys of a failed load without validating the partially loaded
+    /// rows. The record is kept, so that the next load drops them again and truncates the tables.
+    fn drop(&mut self) {
+        if !self.closed {
+            // stop the workers before altering the tables they copy into
+            self.sender.take();
+            for worker in self.workers.drain(..) {
+                let _ = worker.join();
+            }
+            if let Err(e) = restore_foreign_keys(&mut self.client, false) {
+                log::error!("Failed to restore the foreign keys, they are recorded in {} and restored by the next completed load: {}", FOREIGN_KEYS_TABLE, e);
+            }
+        }
+    }
+}
+
+fn copy_worker(mut client: Client, receiver: Arc<Mutex<Receiver<(Table, String)>>>) -> Result<(), anyhow::Error> {
+    loop {
+        let batch = receiver.lock()
+            .map_err(|_| anyhow!("COPY batch queue is poisoned"))?
+            .recv();
+        match batch {
+            Ok((table, rows)) => {
+                let mut writer = client.copy_in(format!("copy {} ({}) from stdin", table.name(), table.columns()).as_str())?;
+                writer.write_all(rows.as_bytes())?;
+                writer.finish()
+                    .map_err(|e| anyhow!("COPY into {}: {}", table.name(), e))?;
+            }
+            Err(_) => {
+                // the loader closed the queue
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Prepare the retry of a load that did not complete, as found by its record of the dropped
+/// foreign keys. The foreign keys recreated as NOT VALID by the failed load are dropped again and
+/// the loaded tables are truncated. The record is kept for the new load.
+fn clear_unfinished_load(client: &mut Client) -> Result<(), anyhow::Error> {
+    if !has_foreign_keys_record(client)? {
+        return Ok(());
+    }
+    let mut transaction = client.transaction()?;
+    let rows = transaction.query(format!("select table_name, name from {FOREIGN_KEYS_TABLE}").as_str(), &[])?;
+    log::warn!("Found {} foreign keys dropped by an unfinished load, truncate the loaded tables", rows.len());
+    for row in rows {
+        let (table, name): (String, String) = (row.get("table_name"), row.get("name"));
+        transaction.execute(format!("alter table {} drop constraint if exists \"{}\"", table, name).as_str(), &[])?;
+    }
+    let tables: Vec<&str> = Table::ALL.iter().map(|table| table.name()).collect();
+    transaction.batch_execute(format!("truncate {}", tables.join(", ")).as_str())?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn has_foreign_keys_record(client: &mut Client) -> Result<bool, anyhow::Error> {
+    let row = client.query_one(format!("select to_regclass('{FOREIGN_KEYS_TABLE}') is not null as exists").as_str(), &[])?;
+    Ok(row.get("exists"))
+}
+
+fn check_empty(client: &mut Client) -> Result<(), anyhow::Error> {
+    for table in [Table::CurrentNodes, Table::CurrentWays, Table::CurrentRelations, Table::Changesets, Table::Users] {
+        let row = client.query_one(format!("select exists (select 1 from {}) as not_empty", table.name()).as_str(), &[])?;
+        let not_empty: bool = row.get("not_empty");
+        if not_empty {
+            return Err(anyhow!("Table {} is not empty, direct load requires an empty apidb schema", table.name()));
+        }
+    }
+    Ok(())
+}
+
+/// Drop the foreign keys of and referencing the loaded tables, recording the table, name and
+/// definition of each dropped foreign key in the same transaction
+fn drop_foreign_keys(client: &mut Client) -> Result<(), anyhow::Error> {
+    let tables: Vec<String> = Table::ALL.iter().map(|table| table.name().to_string()).collect();
+    let mut transaction = client.transaction()?;
+    transaction.batch_execute(
+        format!(
+            "create table if not exists {FOREIGN_KEYS_TABLE} (\
+            table_name text not null, \
+            name text not null, \
+            definition text not null, \
+            primary key (table_name, name))"
+        ).as_str()
+    )?;
+    let rows = transaction.query(
+        "select conrelid::regclass::text as table_name, conname::text as name, pg_get_constraintdef(oid) as definition \
+         from pg_constraint \
+         where contype = 'f' and (conrelid::regclass::text = any($1) or confrelid::regclass::text = any($1))",
+        &[&tables],
+    )?;
+    for row in rows {
+        let (table, name, definition): (String, String, String) = (row.get("table_name"), row.get("name"), row.get("definition"));
+        // a foreign key restored by an unfinished load is validated when it is restored again
+        let definition = definition.strip_suffix(" NOT VALID").unwrap_or(&definition).to_string();
+        log::info!("Drop foreign key {} on {}: {}", name, table, definition);
+        transaction.execute(
+            format!("insert into {FOREIGN_KEYS_TABLE} (table_name, name, definition) values ($1, $2, $3) on conflict do nothing").as_str(),
+            &[&table, &name, &definition],
+        )?;
+        transaction.execute(format!("alter table {} drop constraint \"{}\"", table, name).as_str(), &[])?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Recreate the recorded foreign keys. Validated foreign keys complete the load and the record is
+/// dropped in the same transaction. Otherwise the foreign keys are created as NOT VALID, checking
+/// only the rows written later, and the record is kept for the next load.
+fn restore_foreign_keys(client: &mut Client, validate: bool) -> Result<(), anyhow::Error> {
+    if !has_foreign_keys_record(client)? {
+        return Ok(());
+    }
+    let mut transaction = client.transaction()?;
+    let rows = transaction.query(format!("select table_name, name, definition from {FOREIGN_KEYS_TABLE}").as_str(), &[])?;
+    if !validate && !rows.is_empty() {
+        log::warn!("Restoring {} foreign keys dropped by the failed load, not valid", rows.len());
+    }
+    for row in rows {
+        let (table, name, definition): (String, String, String) = (row.get("table_name"), row.get("name"), row.get("definition"));
+        log::info!("Restore foreign key {} on {}", name, table);
+        let not_valid = if validate { "" } else { " not valid" };
+        transaction.execute(format!("alter table {} add constraint \"{}\" {}{}", table, name, definition, not_valid).as_str(), &[])?;
+    }
+    if validate {
+        transaction.execute(format!("drop table {FOREIGN_KEYS_TABLE}").as_str(), &[])?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Advance the id sequences past the loaded ids
+fn restore_sequences(client: &mut Client) -> Result<(), anyhow::Error> {
+    for table in [Table::CurrentNodes, Table::CurrentWays, Table::CurrentRelations, Table::Changesets, Table::Users] {
+        client.execute(
+            format!(
+                "select setval(pg_get_serial_sequence('{0}', 'id'), coalesce(max(id), 0) + 1, false) from {0}",
+                table.name()
+            ).as_str(),
+            &[],
+        )?;
+    }
+    Ok(())
+}
+
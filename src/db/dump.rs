@@ -0,0 +1,403 @@
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, sync_channel, SyncSender};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, Context};
+use osm_io::osm::apidb_dump::write::writer::Writer as ApiDbDumpWriter;
+use osm_io::osm::model::element::Element;
+
+use crate::checkpoint::manifest::Manifest;
+use crate::db::rows::{BATCH_SIZE, ChangesetUsers, element_key, Table, TableRows};
+use crate::output::writer::ElementWriter;
+
+/// Maximum number of elements in a block sent to the table writers
+const BLOCK_SIZE: usize = 8000;
+/// Number of blocks queued for a table writer
+const QUEUE_SIZE: usize = 4;
+
+/// Message to the writer thread of a table
+enum TableMessage {
+    /// A block of elements of the type of the table
+    Elements(Arc<Vec<Element>>),
+    /// Rows of the table
+    Rows(String),
+    /// Write the remaining rows, sync the data file and reply with its length
+    Checkpoint(SyncSender<(Table, u64)>),
+    /// Write the remaining rows, the table terminator and flush the data file
+    Close,
+}
+
+/// Writer of the element tables of an apidb directory dump, restored with pg_restore
+///
+/// The TOC and the empty data files of the other tables are written by the osm-io dump writer. The
+/// rows of each element table are formatted and written to its data file on a dedicated thread,
+/// which receives the blocks of elements of its type in the order of the input. The changesets
+/// and users of the elements are collected on the calling thread and written when the writer is
+/// closed. Elements must be sorted by type, id and version.
+///
+/// With a manifest, the number of elements written and the lengths of the data files are
+/// checkpointed every `checkpoint_interval` elements, at the first element with another id, when
+/// the rows of the current tables are complete. A dump with a checkpoint in the manifest is
+/// continued from it: the data files are truncated to their lengths at the checkpoint, and the
+/// elements written before it, which must be written again, only have their changesets and users
+/// collected.
+pub(crate) struct DumpWriter<'a> {
+    block: Vec<Element>,
+    block_type: Option<u8>,
+    changeset_users: ChangesetUsers,
+    senders: Vec<SyncSender<TableMessage>>,
+    writers: Vec<JoinHandle<Result<(), anyhow::Error>>>,
+    manifest: Option<&'a mut Manifest>,
+    checkpoint_interval: u64,
+    checkpoint_due: bool,
+    elements_written: u64,
+    elements_skipped: u64,
+    last_key: Option<(u8, i64)>,
+}
+
+impl<'a> DumpWriter<'a> {
+    /// Create the dump in the output directory, replacing the data files of a previous dump, or
+    /// continue the dump from the checkpoint in the manifest, if any
+    pub(crate) fn new(output_path: &Path, manifest: Option<&'a mut Manifest>, checkpoint_interval: u64) -> Result<DumpWriter<'a>, anyhow::Error> {
+        let checkpoint = manifest.as_deref()
+            .and_then(|manifest| {
+                Table::ALL.iter()
+                    .map(|table| manifest.dump_table_length(table.name()))
+                    .collect::<Option<Vec<u64>>>()
+                    .map(|lengths| (manifest.elements_written(), lengths))
+            });
+        let (elements_skipped, lengths) = match checkpoint {
+            None => {
+                let mut template = ApiDbDumpWriter::new(output_path.to_path_buf(), 0)?;
+                template.close()?;
+                (0, vec![0; Table::ALL.len()])
+            }
+            Some((elements_written, lengths)) => {
+                let toc_path = output_path.join("toc.dat");
+                if !toc_path.exists() {
+                    return Err(anyhow!("Cannot resume the apidb dump generation, missing: {}", toc_path.display()));
+                }
+                log::info!("Resume apidb dump generation from checkpoint, elements written: {}", elements_written);
+                (elements_written, lengths)
+            }
+        };
+
+        let mut senders = Vec::with_capacity(Table::ALL.len());
+        let mut writers = Vec::with_capacity(Table::ALL.len());
+        for (table, length) in Table::ALL.into_iter().zip(lengths) {
+            let (sender, receiver) = sync_channel(QUEUE_SIZE);
+            let path = output_path.join(table.dump_file());
+            senders.push(sender);
+            writers.push(std::thread::spawn(move || write_table(table, path, length, receiver)));
+        }
+        Ok(
+            DumpWriter {
+                block: Vec::with_capacity(BLOCK_SIZE),
+                block_type: None,
+                changeset_users: ChangesetUsers::new()?,
+                senders,
+                writers,
+                manifest,
+                checkpoint_interval,
+                checkpoint_due: false,
+                elements_written: 0,
+                elements_skipped,
+                last_key: None,
+            }
+        )
+    }
+
+    /// Send the block to the writers of the tables of its element type
+    fn send_block(&mut self) -> Result<(), anyhow::Error> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = Arc::new(std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_SIZE)));
+        for table in Table::ALL {
+            if table.element_type() == self.block_type {
+                self.send(table, TableMessage::Elements(block.clone()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, table: Table, message: TableMessage) -> Result<(), anyhow::Error> {
+        if self.senders[table as usize].send(message).is_err() {
+            // the writer thread stopped, report the reason
+            self.join()?;
+            return Err(anyhow!("Dump writer of table {} stopped unexpectedly", table.name()));
+        }
+        Ok(())
+    }
+
+    /// Write the rows of the elements written so far to the data files and record their lengths in
+    /// the manifest
+    fn checkpoint(&mut self) -> Result<(), anyhow::Error> {
+        self.checkpoint_due = false;
+        self.send_block()?;
+        let (sender, receiver) = sync_channel(Table::ALL.len());
+        for table in Table::ALL {
+            self.send(table, TableMessage::Checkpoint(sender.clone()))?;
+        }
+        drop(sender);
+        let mut lengths = vec![None; Table::ALL.len()];
+        for (table, length) in receiver {
+            lengths[table as usize] = Some(length);
+        }
+        let mut dump_tables = Vec::with_capacity(Table::ALL.len());
+        for (table, length) in Table::ALL.into_iter().zip(lengths) {
+            match length {
+                None => {
+                    self.join()?;
+                    return Err(anyhow!("Dump writer of table {} stopped unexpectedly", table.name()));
+                }
+                Some(length) => {
+                    dump_tables.push((table.name().to_string(), length));
+                }
+            }
+        }
+        if let Some(manifest) = self.manifest.as_deref_mut() {
+            manifest.set_dump_checkpoint(self.elements_written, dump_tables)?;
+        }
+        Ok(())
+    }
+
+    fn join(&mut self) -> Result<(), anyhow::Error> {
+        self.senders.clear();
+        let mut result = Ok(());
+        for writer in self.writers.drain(..) {
+            let writer_result = writer.join()
+                .map_err(|_| anyhow!("Dump table writer panicked"))
+                .and_then(|r| r);
+            if result.is_ok() {
+                result = writer_result;
+            }
+        }
+        result
+    }
+}
+
+impl ElementWriter for DumpWriter<'_> {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        let key = element_key(&element);
+        if key.is_none() {
+            return Ok(());
+        }
+        self.changeset_users.add(&element)?;
+        if self.elements_written < self.elements_skipped {
+            // written before the checkpoint the dump is continued from
+            self.elements_written += 1;
+            return Ok(());
+        }
+        if self.checkpoint_due && key != self.last_key {
+            self.checkpoint()?;
+        }
+        let element_type = key.map(|(element_type, _)| element_type);
+        if element_type != self.block_type || self.block.len() >= BLOCK_SIZE {
+            self.send_block()?;
+            self.block_type = element_type;
+        }
+        self.block.push(element);
+        self.last_key = key;
+        self.elements_written += 1;
+        if self.manifest.is_some() && self.elements_written.is_multiple_of(self.checkpoint_interval) {
+            self.checkpoint_due = true;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        self.send_block()?;
+        let mut buffers = vec![String::new(); Table::ALL.len()];
+        let mut changeset_users = std::mem::replace(&mut self.changeset_users, ChangesetUsers::new()?);
+        changeset_users.write_rows(|table, row| {
+            let buffer = &mut buffers[table as usize];
+            buffer.push_str(&row);
+            if buffer.len() >= BATCH_SIZE {
+                let batch = std::mem::take(buffer);
+                self.send(table, TableMessage::Rows(batch))?;
+            }
+            Ok(())
+        })?;
+        for table in Table::ALL {
+            let batch = std::mem::take(&mut buffers[table as usize]);
+            if !batch.is_empty() {
+                self.send(table, TableMessage::Rows(batch))?;
+            }
+            self.send(table, TableMessage::Close)?;
+        }
+        self.join()?;
+        if let Some(manifest) = self.manifest.as_deref_mut() {
+            manifest.set_elements_written(self.elements_written)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DumpWriter<'_> {
+    /// Stop the table writers of an unclosed dump, the data files are continued from the last
+    /// checkpoint
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// Write the rows of the table to its data file, truncated to `length`, until the writer is closed
+fn write_table(table: Table, path: PathBuf, length: u64, receiver: Receiver<TableMessage>) -> Result<(), anyhow::Error> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| anyhow!("path: {}", path.display()))?;
+    if file.metadata()?.len() < length {
+        return Err(anyhow!("Cannot resume the apidb dump generation, {} is shorter than at the checkpoint: {}", path.display(), length));
+    }
+    file.set_len(length)?;
+    file.seek(SeekFrom::End(0))?;
+    let mut writer = BufWriter::new(file);
+    let mut table_rows = TableRows::new(table);
+    let mut rows = String::new();
+    for message in receiver {
+        match message {
+            TableMessage::Elements(elements) => {
+                for element in elements.iter() {
+                    table_rows.write_element(element, &mut rows)?;
+                }
+            }
+            TableMessage::Rows(batch) => {
+                rows.push_str(&batch);
+            }
+            TableMessage::Checkpoint(reply) => {
+                table_rows.finish(&mut rows);
+                writer.write_all(rows.as_bytes())?;
+                rows.clear();
+                writer.flush()?;
+                writer.get_ref().sync_data()
+                    .with_context(|| anyhow!("path: {}", path.display()))?;
+                let _ = reply.send((table, writer.get_ref().metadata()?.len()));
+                continue;
+            }
+            TableMessage::Close => {
+                table_rows.finish(&mut rows);
+                rows.push_str("\\.\n");
+                writer.write_all(rows.as_bytes())?;
+                writer.flush()
+                    .with_context(|| anyhow!("path: {}", path.display()))?;
+                return Ok(());
+            }
+        }
+        writer.write_all(rows.as_bytes())
+            .with_context(|| anyhow!("path: {}", path.display()))?;
+        rows.clear();
+    }
+    // the dump writer was dropped without closing
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use osm_io::osm::apidb_dump::write::writer::Writer as ApiDbDumpWriter;
+    use osm_io::osm::pbf::reader::Reader as PbfReader;
+
+    use crate::checkpoint::manifest::Manifest;
+    use crate::db::dump::DumpWriter;
+    use crate::db::rows::Table;
+    use crate::output::writer::ElementWriter;
+
+    #[test]
+    fn test_dump_tables() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/niue-230612.osm.pbf");
+        let output_path = std::env::temp_dir().join("osm-admin-test-dump-tables");
+        let template_path = std::env::temp_dir().join("osm-admin-test-dump-tables-template");
+        let _ = fs::remove_dir_all(&output_path);
+        let _ = fs::remove_dir_all(&template_path);
+
+        let mut writer = DumpWriter::new(&output_path, None, 0)?;
+        let mut template = ApiDbDumpWriter::new(template_path.clone(), 0)?;
+        for element in PbfReader::new(&input_path)?.elements()? {
+            writer.write_element(element.clone())?;
+            template.write_element(element)?;
+        }
+        writer.close()?;
+        template.close()?;
+
+        // the tables of the elements are the same as written by osm-io, the changesets and users
+        // are created at the time of writing
+        for table in Table::ALL.iter().filter(|table| table.element_type().is_some()) {
+            let rows = fs::read_to_string(output_path.join(table.dump_file()))?;
+            let template_rows = fs::read_to_string(template_path.join(table.dump_file()))?;
+            assert!(rows.ends_with("\\.\n"));
+            assert!(rows == template_rows, "table: {}", table.name());
+        }
+        let users = fs::read_to_string(output_path.join(Table::Users.dump_file()))?;
+        let template_users = fs::read_to_string(template_path.join(Table::Users.dump_file()))?;
+        assert_eq!(users.lines().count(), template_users.lines().count());
+        assert_eq!(fs::read(output_path.join("toc.dat"))?, fs::read(template_path.join("toc.dat"))?);
+        assert_eq!(fs::read_to_string(output_path.join("4210.dat"))?, "\\.\n");
+        fs::remove_dir_all(&output_path)?;
+        fs::remove_dir_all(&template_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_dump() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/niue-230612.osm.pbf");
+        let output_path = std::env::temp_dir().join("osm-admin-test-resume-dump");
+        let template_path = std::env::temp_dir().join("osm-admin-test-resume-dump-template");
+        let _ = fs::remove_dir_all(&output_path);
+        let _ = fs::remove_dir_all(&template_path);
+
+        let mut template = ApiDbDumpWriter::new(template_path.clone(), 0)?;
+        let mut elements = 0;
+        for element in PbfReader::new(&input_path)?.elements()? {
+            template.write_element(element)?;
+            elements += 1;
+        }
+        template.close()?;
+
+        // interrupt the dump between two checkpoints, after the rows of some elements were written
+        let mut manifest = Manifest::open(&output_path, &input_path, false)?;
+        {
+            let mut writer = DumpWriter::new(&output_path, Some(&mut manifest), 1000)?;
+            for element in PbfReader::new(&input_path)?.elements()?.take(elements / 2) {
+                writer.write_element(element)?;
+            }
+        }
+        let elements_written = manifest.elements_written();
+        assert!(elements_written > 0 && elements_written <= elements as u64 / 2);
+        assert!(manifest.dump_table_length(Table::Nodes.name()).unwrap() > 0);
+
+        let mut manifest = Manifest::open(&output_path, &input_path, true)?;
+        {
+            let mut writer = DumpWriter::new(&output_path, Some(&mut manifest), 1000)?;
+            for element in PbfReader::new(&input_path)?.elements()? {
+                writer.write_element(element)?;
+            }
+            writer.close()?;
+        }
+        assert_eq!(manifest.dump_table_length(Table::Nodes.name()), None);
+
+        for table in Table::ALL.iter().filter(|table| table.element_type().is_some()) {
+            let rows = fs::read_to_string(output_path.join(table.dump_file()))?;
+            let template_rows = fs::read_to_string(template_path.join(table.dump_file()))?;
+            assert!(rows == template_rows, "table: {}", table.name());
+        }
+        let users = fs::read_to_string(output_path.join(Table::Users.dump_file()))?;
+        let template_users = fs::read_to_string(template_path.join(Table::Users.dump_file()))?;
+        assert_eq!(users.lines().count(), template_users.lines().count());
+        fs::remove_dir_all(&output_path)?;
+        fs::remove_dir_all(&template_path)?;
+        Ok(())
+    }
+}
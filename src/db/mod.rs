@@ -1 +1,9 @@
 pub(crate) mod pg;
+pub(crate) mod copy;
+pub(crate) mod dump;
+pub(crate) mod changes;
+pub(crate) mod sql;
+pub(crate) mod replication;
+pub(crate) mod rows;
+pub(crate) mod snapshot;
+pub(crate) mod stats;
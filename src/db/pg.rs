@@ -1,25 +1,46 @@
-use std::fs::File;
-use std::fs::Permissions;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::anyhow;
 use benchmark_rs::stopwatch::StopWatch;
 use chrono::{DateTime, Utc};
-use postgres::{Client, NoTls};
-
-pub(crate) fn count_objects(
-    host: String,
-    port: String,
-    database: String,
-    user: String,
-    password: Option<String>,
-) -> Result<(i64, i64, i64), anyhow::Error> {
-    let mut client = create_client(&host, &port, &database, &user, password)?;
-    let rows = client.query(format!("select relname, n_live_tup from pg_stat_user_tables where schemaname = 'public' AND (relname = 'nodes' OR relname = 'ways' OR relname = 'relations');").as_str(), &[])?;
+use postgres::{Client, Config};
+
+use crate::checkpoint::manifest::Manifest;
+use crate::connection::{ConnectionParameters, Database};
+use crate::db::replication::read_state;
+use crate::metrics;
+use crate::options::WorkPaths;
+use crate::replication::state::State;
+use crate::tls;
+
+/// The schema of the apidb dump written on import and read on export
+pub(crate) const PUBLIC_SCHEMA: &str = "public";
+
+/// The apidb tables dumped for the export
+const DUMP_TABLES: [&str; 10] = [
+    "nodes",
+    "node_tags",
+    "ways",
+    "way_tags",
+    "way_nodes",
+    "relations",
+    "relation_tags",
+    "relation_members",
+    "users",
+    "changesets",
+];
+
+pub(crate) fn count_objects(database: &Database) -> Result<(i64, i64, i64), anyhow::Error> {
+    let mut client = create_client(database)?;
+    let rows = client.query("select relname, n_live_tup from pg_stat_user_tables where schemaname = $1 AND (relname = 'nodes' OR relname = 'ways' OR relname = 'relations');", &[&database.schema])?;
     let mut result = (0, 0, 0);
     for row in rows {
         let relname: String = row.get("relname");
@@ -34,172 +55,338 @@ pub(crate) fn count_objects(
     Ok(result)
 }
 
-fn create_client(host: &String, port: &String, database: &String, user: &String, password: Option<String>) -> Result<Client, anyhow::Error> {
-    let connection_string = match password {
+/// Connect to the database with the schema as the search path, so that the apidb tables are
+/// resolved in the schema. The connection uses TLS according to the SSL settings, see [tls].
+pub(crate) fn create_client(database: &Database) -> Result<Client, anyhow::Error> {
+    let Database { connection: ConnectionParameters { host, port, database, user, password }, schema } = database;
+    check_schema(schema)?;
+    let password = match password.clone() {
         None => {
-            let pgpass_path = PathBuf::from("/root/.pgpass");
-            let pgpass_password_opt = read_password_file(&host, &port, &database, &user, &pgpass_path)?;
-            match pgpass_password_opt {
+            let pgpass_password_opt = match pgpass_path() {
+                None => { None }
+                Some(pgpass_path) => { read_password_file(host, port, database, user, &pgpass_path)? }
+            };
+            match &pgpass_password_opt {
                 None => {
                     log::info!("No credentials and no correct PGPASSFILE entry provided. Will succeed on trust connections");
-                    format!("host={host} port={port} user={user} dbname=openstreetmap")
                 }
-                Some(pgpass_password) => {
+                Some(_) => {
                     log::info!("Using password from PGPASSFILE");
-                    format!("host={host} port={port} user={user} password={pgpass_password} dbname=openstreetmap")
                 }
             }
+            pgpass_password_opt
         }
-        Some(password) => {
-            format!("host={host} port={port} user={user} password={password} dbname=openstreetmap")
-        }
+        Some(password) => { Some(password) }
     };
-    let client = Client::connect(
-        connection_string.as_str(),
-        NoTls,
-    ).or_else(|e| Err(anyhow!("{}: {}", connection_string, e)))?;
+    // the parameters are passed unquoted, so that passwords with spaces, quotes or backslashes
+    // reach the server as they are
+    let mut config = Config::new();
+    config
+        .host(host)
+        .port(port.parse::<u16>().map_err(|_| anyhow!("Invalid database port: {}", port))?)
+        .user(user)
+        .dbname(database)
+        .options(&format!("-c search_path={schema}"))
+        .ssl_mode(tls::ssl_mode()?.client_ssl_mode());
+    if let Some(password) = &password {
+        config.password(password);
+    }
+    // the error never includes the password
+    let client = config.connect(tls::connector()?)
+        .map_err(|e| anyhow!("host={host} port={port} dbname={database} user={user}: {}", e))?;
 
     Ok(client)
 }
 
+/// Load the apidb dump into the database with pg_restore, in checkpointed steps recorded in the
+/// manifest: the schema (pre-data section), the data of each table, the sequence values and the
+/// indexes and constraints (post-data section). Completed steps are skipped. A step that was
+/// attempted before is cleaned up before it is run again, that is the schema objects of the
+/// section are dropped or the table is truncated.
 pub(crate) fn restore(
     jobs: i16,
-    host: String,
-    port: String,
-    database: String,
-    user: String,
-    password: Option<String>,
-    dump_path: &PathBuf,
-    _var_lib_path: &PathBuf,
-    var_log_path: &PathBuf,
+    connection: &ConnectionParameters,
+    dump_path: &Path,
+    work_paths: &WorkPaths,
+    manifest: &mut Manifest,
 ) -> Result<(), anyhow::Error> {
     log::info!("Load OSM, host: {}:{}, user: {:?}, password provided: {}, jobs: {}, dump path: {:?}",
-        host,
-        port,
-        user,
-        match password {Some(_) => "Yes", None => "No"},
+        connection.host,
+        connection.port,
+        connection.user,
+        match connection.password {Some(_) => "Yes", None => "No"},
         jobs,
         dump_path
     );
 
-    let pgpass_path = PathBuf::from("/root/.pgpass");
-    write_password_file(&host, &port, &database, &user, &password, &pgpass_path)?;
+    check_password_file(&connection.password);
+    let password_file = TempPasswordFile::create(connection, &work_paths.var_lib_path)?;
 
-    let stdout_path = var_log_path.join("pg_restore.log");
-    let stderr_path = var_log_path.join("pg_restore.error.log");
+    let stdout_path = work_paths.var_log_path.join("pg_restore.log");
+    let stderr_path = work_paths.var_log_path.join("pg_restore.error.log");
+    if !manifest.resumed() {
+        create_redirects(&stdout_path, &stderr_path)?;
+    }
+    let pg_restore = PgRestore {
+        connection: connection.clone(),
+        password_file,
+        dump_path: dump_path.to_path_buf(),
+        stdout_path,
+        stderr_path,
+    };
 
-    let (stdout, stderr) = create_redirects(&stdout_path, &stderr_path)?;
+    let toc = pg_restore.list()?;
 
-    let p = Command::new("pg_restore")
-        .arg("-h").arg(host)
-        .arg("-p").arg(port)
-        .arg("-U").arg(user)
-        .arg("-j").arg(jobs.to_string())
-        .arg("-d").arg(database)
-        .arg("--no-password")
-        .arg(dump_path)
-        .stdout(std::process::Stdio::from(stdout))
-        .stderr(std::process::Stdio::from(stderr))
-        .spawn()?;
+    let step = "restore pre-data";
+    if manifest.is_complete(step) {
+        log::info!("Skip completed step: {}", step);
+    } else {
+        let clean = manifest.status(step).is_some();
+        manifest.run(step, |_| {
+            pg_restore.run(&["--section=pre-data"], clean)
+        })?;
+    }
 
-    let result = p.wait_with_output();
-    match result {
-        Ok(output) => {
-            match output.status.code() {
-                None => {
-                    log::error!("Failed loading OSM database, see pg_restore stdout at: {:?}, see stderr at: {:?}",
-                        stdout_path,
-                        stderr_path
-                    );
-                    Err(anyhow!("Failed loading OSM database"))
-                }
-                Some(0) => {
-                    Ok(())
+    let tables: Vec<(String, String)> = toc.iter()
+        .filter_map(|entry| toc_entry_name(entry, "TABLE DATA").map(|name| (format!("restore data {}", name), entry.clone())))
+        .filter(|(step, _)| {
+            let complete = manifest.is_complete(step);
+            if complete {
+                log::info!("Skip completed step: {}", step);
+            }
+            !complete
+        })
+        .collect();
+    let pending = Mutex::new(tables);
+    let shared_manifest = Mutex::new(&mut *manifest);
+    let failed = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| {
+                loop {
+                    let next = pending.lock().unwrap().pop();
+                    let Some((step, entry)) = next else { break };
+                    let result = restore_table_data(&pg_restore, &shared_manifest, &step, &entry);
+                    if let Err(e) = result {
+                        log::error!("Failed step: {}, error: {:#}", step, e);
+                        failed.lock().unwrap().push(step);
+                    }
                 }
-                Some(code) => {
-                    log::error!("Failed loading OSM database, error code: {}, see stdout at: {:?}, see stderr at: {:?}",
+            });
+        }
+    });
+    let failed = failed.into_inner().map_err(|e| anyhow!("{}", e))?;
+    if !failed.is_empty() {
+        return Err(anyhow!("Failed loading OSM database, failed steps: {}", failed.join(", ")));
+    }
+
+    let step = "restore sequences";
+    if manifest.is_complete(step) {
+        log::info!("Skip completed step: {}", step);
+    } else {
+        let sequences: Vec<String> = toc.iter()
+            .filter(|entry| toc_entry_name(entry, "SEQUENCE SET").is_some())
+            .cloned()
+            .collect();
+        manifest.run(step, |_| {
+            pg_restore.run_entries("sequences", &sequences, &[])
+        })?;
+    }
+
+    let step = "restore post-data";
+    if manifest.is_complete(step) {
+        log::info!("Skip completed step: {}", step);
+    } else {
+        let clean = manifest.status(step).is_some();
+        let jobs = jobs.to_string();
+        manifest.run(step, |_| {
+            pg_restore.run(&["--section=post-data", "-j", jobs.as_str()], clean)
+        })?;
+    }
+    Ok(())
+}
+
+fn restore_table_data(
+    pg_restore: &PgRestore,
+    manifest: &Mutex<&mut Manifest>,
+    step: &str,
+    entry: &str,
+) -> Result<(), anyhow::Error> {
+    let attempted = {
+        let mut manifest = manifest.lock().map_err(|e| anyhow!("{}", e))?;
+        let attempted = manifest.status(step).is_some();
+        manifest.start(step)?;
+        attempted
+    };
+    let name = toc_entry_name(entry, "TABLE DATA").ok_or(anyhow!("Not a table data entry: {}", entry))?;
+    let result = (|| {
+        if attempted {
+            log::info!("Truncate partially restored table: {}", name);
+            // the table name of the TOC entry is qualified, the dump always restores into public
+            let database = Database { connection: pg_restore.connection.clone(), schema: PUBLIC_SCHEMA.to_string() };
+            let mut client = create_client(&database)?;
+            client.batch_execute(format!("truncate table {}", name).as_str())?;
+        }
+        log::info!("Restore table data: {}", name);
+        pg_restore.run_entries(&name, &[entry.to_string()], &[])
+    })();
+    let mut manifest = manifest.lock().map_err(|e| anyhow!("{}", e))?;
+    match result {
+        Ok(()) => {
+            manifest.complete(step)
+        }
+        Err(e) => {
+            manifest.fail(step, &e)?;
+            Err(e)
+        }
+    }
+}
+
+/// Qualified name of the object of a pg_restore TOC entry of the given type, as in
+/// `4260; 0 16500 TABLE DATA public nodes openstreetmap`
+fn toc_entry_name(entry: &str, entry_type: &str) -> Option<String> {
+    if entry.starts_with(';') {
+        return None;
+    }
+    let (_, rest) = entry.split_once(&format!(" {} ", entry_type))?;
+    let mut fields = rest.split_whitespace();
+    let schema = fields.next()?;
+    let name = fields.next()?;
+    Some(format!("{}.{}", schema, name))
+}
+
+/// Invocations of pg_restore for one apidb dump, appending to the pg_restore logs
+struct PgRestore {
+    connection: ConnectionParameters,
+    password_file: Option<TempPasswordFile>,
+    dump_path: PathBuf,
+    stdout_path: PathBuf,
+    stderr_path: PathBuf,
+}
+
+impl PgRestore {
+    fn command(&self) -> Command {
+        let mut command = Command::new("pg_restore");
+        set_password_file_env(&mut command, &self.password_file);
+        command
+    }
+
+    /// The TOC entries of the dump
+    fn list(&self) -> Result<Vec<String>, anyhow::Error> {
+        let output = self.command()
+            .arg("-l")
+            .arg(&self.dump_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(anyhow!("Failed listing the dump: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(String::from_utf8(output.stdout)?.lines().map(|line| line.to_string()).collect())
+    }
+
+    /// Restore only the given TOC entries
+    fn run_entries(&self, name: &str, entries: &[String], args: &[&str]) -> Result<(), anyhow::Error> {
+        let list_path = self.dump_path.join(format!("pg_restore.{}.list", name));
+        fs::write(&list_path, entries.join("\n") + "\n")?;
+        let list_arg = list_path.display().to_string();
+        let mut all_args = vec!["-L", list_arg.as_str()];
+        all_args.extend_from_slice(args);
+        let result = self.run(&all_args, false);
+        fs::remove_file(&list_path)?;
+        result
+    }
+
+    fn run(&self, args: &[&str], clean: bool) -> Result<(), anyhow::Error> {
+        let stdout = OpenOptions::new().create(true).append(true).open(&self.stdout_path)
+            .map_err(|e| anyhow!("{}: {}", self.stdout_path.display(), e))?;
+        let stderr = OpenOptions::new().create(true).append(true).open(&self.stderr_path)
+            .map_err(|e| anyhow!("{}: {}", self.stderr_path.display(), e))?;
+        let mut command = self.command();
+        command
+            .arg("-h").arg(&self.connection.host)
+            .arg("-p").arg(&self.connection.port)
+            .arg("-U").arg(&self.connection.user)
+            .arg("-d").arg(&self.connection.database)
+            .arg("--no-password")
+            .args(args);
+        if clean {
+            command.arg("--clean").arg("--if-exists");
+        }
+        let status = command
+            .arg(&self.dump_path)
+            .stdout(std::process::Stdio::from(stdout))
+            .stderr(std::process::Stdio::from(stderr))
+            .status()?;
+        metrics::set_pg_restore_exit_status(status.code());
+        match status.code() {
+            Some(0) => {
+                Ok(())
+            }
+            code => {
+                log::error!("Failed loading OSM database, pg_restore {}, exit code: {:?}, see stdout at: {:?}, see stderr at: {:?}",
+                    args.join(" "),
+                    code,
+                    self.stdout_path,
+                    self.stderr_path
+                );
+                Err(
+                    anyhow!(
+                        "pg_restore {} failed with exit code: {:?}, see stdout at: {}, see stderr at: {}",
+                        args.join(" "),
                         code,
-                        stdout_path,
-                        stderr_path
-                    );
-                    Err(anyhow!("Failed loading OSM database"))
-                }
+                        self.stdout_path.display(),
+                        self.stderr_path.display(),
+                    )
+                )
             }
         }
-        Err(_) => {
-            log::error!("Failed loading OSM database");
-            Err(anyhow!("Failed loading OSM database"))
-        }
     }
 }
 
 pub(crate) fn dump(
     jobs: i16,
-    host: String,
-    port: String,
-    database: String,
-    user: String,
-    password: Option<String>,
-    dump_path: &PathBuf,
-    _var_lib_path: &PathBuf,
-    var_log_path: &PathBuf,
-) -> Result<(u64, DateTime<Utc>), anyhow::Error> {
+    database: &Database,
+    dump_path: &Path,
+    work_paths: &WorkPaths,
+) -> Result<(u64, DateTime<Utc>, Option<State>), anyhow::Error> {
     let mut stopwatch = StopWatch::new();
     stopwatch.start();
+    let connection = &database.connection;
     log::info!("Dump OSM, host: {}:{}, user: {:?}, password provided: {}, jobs: {}, dump path: {:?}",
-        host,
-        port,
-        user,
-        match password {Some(_) => "Yes", None => "No"},
+        connection.host,
+        connection.port,
+        connection.user,
+        match connection.password {Some(_) => "Yes", None => "No"},
         jobs,
         dump_path
     );
 
-    let pgpass_path = PathBuf::from("/root/.pgpass");
-
-    write_password_file(&host, &port, &database, &user, &password, &pgpass_path)?;
+    check_password_file(&connection.password);
 
-    let stdout_path = var_log_path.join("pg_dump.log");
-    let stderr_path = var_log_path.join("pg_dump.error.log");
+    let stdout_path = work_paths.var_log_path.join("pg_dump.log");
+    let stderr_path = work_paths.var_log_path.join("pg_dump.error.log");
 
     let (stdout, stderr) = create_redirects(&stdout_path, &stderr_path)?;
 
-    let mut client = create_client(&host, &port, &database, &user, password)?;
-        client.query(format!("begin transaction isolation level repeatable read;").as_str(), &[])?;
-    let result = client.query("select  pg_export_snapshot() as snapshot_name, cast(pg_current_xact_id () as text) as transaction_id, cast(current_timestamp as text) as timestamp;", &[])?;
-    let (snapshot_name, transaction_id, timestamp): (String, u64, DateTime<Utc>) = match result.get(0) {
-        None => {
-            Err(anyhow!("failed"))
-        }
-        Some(row) => {
-            let snapshot_name: String = row.get("snapshot_name");
-            let transaction_id = u64::from_str(row.get("transaction_id"))?;
-            let timestamp_str: String = row.get("timestamp");
-            let timestamp = DateTime::<Utc>::from(DateTime::parse_from_str(timestamp_str.as_str(), "%Y-%m-%d %H:%M:%S%.f%#z")?);
-            Ok((snapshot_name, transaction_id, timestamp))
-        }
-    }?;
+    let mut client = create_client(database)?;
+    let (snapshot_name, transaction_id, timestamp) = export_snapshot(&mut client)?;
+    let replication_state = read_state(&mut client)?;
 
-    let p = Command::new("pg_dump")
-        .arg("-h").arg(host)
-        .arg("-p").arg(port)
-        .arg("-U").arg(user)
+    let password_file = TempPasswordFile::create(connection, &work_paths.var_lib_path)?;
+    let mut command = Command::new("pg_dump");
+    set_password_file_env(&mut command, &password_file);
+    for table in DUMP_TABLES {
+        command.arg("--table").arg(format!("{}.{}", database.schema, table));
+    }
+    let p = command
+        .arg("-h").arg(&connection.host)
+        .arg("-p").arg(&connection.port)
+        .arg("-U").arg(&connection.user)
         .arg("-j").arg(jobs.to_string())
-        .arg("-d").arg(database)
+        .arg("-d").arg(&connection.database)
         .arg("--no-password")
         .arg("--file").arg(dump_path)
         .arg("--format").arg("d")
         .arg("--compress").arg("0")
-        .arg("--table").arg("public.nodes")
-        .arg("--table").arg("public.node_tags")
-        .arg("--table").arg("public.ways")
-        .arg("--table").arg("public.way_tags")
-        .arg("--table").arg("public.way_nodes")
-        .arg("--table").arg("public.relations")
-        .arg("--table").arg("public.relation_tags")
-        .arg("--table").arg("public.relation_members")
-        .arg("--table").arg("public.users")
-        .arg("--table").arg("public.changesets")
         .arg("--snapshot").arg(snapshot_name)
         .stdout(std::process::Stdio::from(stdout))
         .stderr(std::process::Stdio::from(stderr))
@@ -220,13 +407,13 @@ pub(crate) fn dump(
                     Err(anyhow!("Failed dumping OSM database"))
                 }
                 Some(0) => {
-                    let du = benchmark_rs::disk_usage::disk_usage(&dump_path)?;
+                    let du = benchmark_rs::disk_usage::disk_usage(&dump_path.to_path_buf())?;
                     log::info!("Finished dumping OSM database, disk: {}, time: {}",
                         benchmark_rs::disk_usage::to_human(du),
                         stopwatch
                     );
                     client.query(format!("commit").as_str(), &[])?;
-                    Ok((transaction_id, timestamp))
+                    Ok((transaction_id, timestamp, replication_state))
                 }
                 Some(code) => {
                     log::error!("Failed dumping OSM database, error code: {}, see stdout at: {:?}, see stderr at: {:?}, time: {}",
@@ -248,6 +435,26 @@ pub(crate) fn dump(
     }
 }
 
+/// Begin a repeatable read transaction and export its snapshot, returning the snapshot name, the
+/// transaction id and the transaction timestamp. The snapshot can be used by other sessions until
+/// the transaction ends.
+pub(crate) fn export_snapshot(client: &mut Client) -> Result<(String, u64, DateTime<Utc>), anyhow::Error> {
+    client.query(format!("begin transaction isolation level repeatable read;").as_str(), &[])?;
+    let result = client.query("select  pg_export_snapshot() as snapshot_name, cast(pg_current_xact_id () as text) as transaction_id, cast(current_timestamp as text) as timestamp;", &[])?;
+    match result.get(0) {
+        None => {
+            Err(anyhow!("failed"))
+        }
+        Some(row) => {
+            let snapshot_name: String = row.get("snapshot_name");
+            let transaction_id = u64::from_str(row.get("transaction_id"))?;
+            let timestamp_str: String = row.get("timestamp");
+            let timestamp = DateTime::<Utc>::from(DateTime::parse_from_str(timestamp_str.as_str(), "%Y-%m-%d %H:%M:%S%.f%#z")?);
+            Ok((snapshot_name, transaction_id, timestamp))
+        }
+    }
+}
+
 fn create_redirects(
     stdout_path: &PathBuf,
     stderr_path: &PathBuf,
@@ -261,98 +468,170 @@ fn create_redirects(
     Ok((stdout, stderr))
 }
 
-fn write_password_file(
-    host: &String,
-    port: &String,
-    database: &String,
-    user: &String,
-    password: &Option<String>,
-    pgpass_path: &PathBuf,
-) -> Result<(), anyhow::Error> {
-    match password {
+/// Check that the schema is a plain identifier, as it is used unquoted in the connection options
+/// and in the pg_dump table patterns
+fn check_schema(schema: &str) -> Result<(), anyhow::Error> {
+    let mut chars = schema.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid schema name: {}, expected lowercase letters, digits and underscores", schema))
+    }
+}
+
+/// The password file of the database connections, PGPASSFILE or ~/.pgpass as for libpq
+pub(crate) fn pgpass_path() -> Option<PathBuf> {
+    match std::env::var_os("PGPASSFILE") {
+        Some(pgpass_path) => {
+            Some(PathBuf::from(pgpass_path))
+        }
         None => {
-            if std::path::Path::new(pgpass_path).exists() {
-                let permissions = std::fs::metadata(pgpass_path).unwrap().permissions();
-                let mode = permissions.mode() & 0o777_u32;
-                if mode == 0o600 {
-                    log::info!("Found PGPASSFILE at: {:?}, permissions: {:#o}", pgpass_path, mode);
-                } else {
-                    log::warn!("Found PGPASSFILE at: {:?}, wrong permissions: {:#o}. Must be 0o600", pgpass_path, mode);
-                }
-            } else {
-                log::info!("No credentials and no PGPASSFILE file provided. Will succeed on trust connections");
-            }
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".pgpass"))
         }
-        Some(password) => {
-            if std::path::Path::new(pgpass_path).exists() {
-                log::warn!("Overwriting the PGPASSFILE at {:?} with provided credentials", pgpass_path);
-            } else {
-                log::info!("Writing provided credentials to PGPASSFILE at: {:?}", pgpass_path);
-            }
-            let credentials = format!("{}:{}:{}:{}:{}",
-                                      host,
-                                      port,
-                                      database,
-                                      user,
-                                      password
-            );
-            let permissions = Permissions::from_mode(0o600);
-            let pgpass_file = File::create(pgpass_path).or_else(|e| {
-                Err(anyhow!("{}: {}", pgpass_path.display(), e))
-            })?;
-            pgpass_file.set_permissions(permissions).or_else(|e| {
-                Err(anyhow!("{}: {}", pgpass_path.display(), e))
-            })?;
-            let mut writer = BufWriter::new(pgpass_file);
-            writer.write(credentials.as_bytes()).or_else(|e| {
-                Err(anyhow!("{}: {}", pgpass_path.display(), e))
-            })?;
-            writer.flush()?;
+    }
+}
+
+/// Log how pg_dump and pg_restore will authenticate. The password file is never written, the
+/// provided password is passed to the child processes in a [TempPasswordFile].
+fn check_password_file(password: &Option<String>) {
+    match (password, pgpass_path()) {
+        (Some(_), _) => {
+            log::info!("Using provided credentials");
+        }
+        (None, Some(pgpass_path)) if pgpass_path.exists() => {
+            check_password_file_permissions(&pgpass_path);
+        }
+        (None, _) => {
+            log::info!("No credentials and no PGPASSFILE file provided. Will succeed on trust connections");
         }
     }
-    Ok(())
+}
+
+/// Check that the password file has no group or world access, otherwise it is ignored as by libpq
+fn check_password_file_permissions(pgpass_path: &PathBuf) -> bool {
+    let mode = std::fs::metadata(pgpass_path).map(|metadata| metadata.permissions().mode() & 0o777_u32).unwrap_or(0);
+    if mode & 0o077 == 0 {
+        log::info!("Found PGPASSFILE at: {:?}, permissions: {:#o}", pgpass_path, mode);
+        true
+    } else {
+        log::warn!("Ignoring PGPASSFILE at: {:?}, it has group or world access, permissions: {:#o}. Must be 0o600 or less", pgpass_path, mode);
+        false
+    }
+}
+
+/// Password file with the single entry of the provided password, passed to pg_dump and
+/// pg_restore in PGPASSFILE instead of the password file of the operator. The file is created
+/// with 0o600 permissions in the working directory and removed when dropped.
+struct TempPasswordFile {
+    path: PathBuf,
+}
+
+static TEMP_PASSWORD_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl TempPasswordFile {
+    /// Create the password file if a password is provided
+    fn create(connection: &ConnectionParameters, var_lib_path: &Path) -> Result<Option<TempPasswordFile>, anyhow::Error> {
+        let Some(password) = &connection.password else {
+            return Ok(None);
+        };
+        let path = var_lib_path.join(
+            format!("pgpass.{}.{}", std::process::id(), TEMP_PASSWORD_FILE_COUNTER.fetch_add(1, Ordering::Relaxed))
+        );
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        // the password file is removed also if writing it fails
+        let password_file = TempPasswordFile { path };
+        let line = [connection.host.as_str(), connection.port.as_str(), connection.database.as_str(), connection.user.as_str(), password.as_str()]
+            .map(escape_password_field)
+            .join(":");
+        writeln!(file, "{}", line)?;
+        Ok(Some(password_file))
+    }
+}
+
+impl Drop for TempPasswordFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("Failed removing temporary password file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn escape_password_field(field: &str) -> String {
+    field.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+fn set_password_file_env(command: &mut Command, password_file: &Option<TempPasswordFile>) {
+    if let Some(password_file) = password_file {
+        command.env("PGPASSFILE", &password_file.path);
+    }
 }
 
 fn read_password_file(
-    host: &String,
-    port: &String,
-    database: &String,
-    user: &String,
+    host: &str,
+    port: &str,
+    database: &str,
+    user: &str,
     pgpass_path: &PathBuf,
 ) -> Result<Option<String>, anyhow::Error> {
     if pgpass_path.exists() {
-        let permissions = std::fs::metadata(pgpass_path).unwrap().permissions();
-        let mode = permissions.mode() & 0o777_u32;
-        if mode == 0o600 {
-            log::info!("Found PGPASSFILE at: {:?}, permissions: {:#o}", pgpass_path, mode);
-            let pgpass_file = BufReader::new(File::open(pgpass_path)?);
-
-            let server_prefix = format!("{}:{}:{}:{}",
-                                        host,
-                                        port,
-                                        database,
-                                        user
-            );
-            let mut result = None;
-            for line_result in pgpass_file.lines() {
-                let line = line_result?;
-                if line.starts_with(&server_prefix) {
-                    let parts: Vec<String> = line.split(":").map(|s| s.to_string()).collect();
-                    result = parts.last().cloned();
-                }
+        if !check_password_file_permissions(pgpass_path) {
+            return Ok(None);
+        }
+        let pgpass_file = BufReader::new(File::open(pgpass_path)?);
+        for line_result in pgpass_file.lines() {
+            if let Some(password) = match_password_line(&line_result?, host, port, database, user) {
+                return Ok(Some(password));
             }
-            Ok(result)
-        } else {
-            let error_string = format!("Found PGPASSFILE at: {:?}, wrong permissions: {:#o}. Must be 0o600", pgpass_path, mode);
-            log::warn!("{}", error_string);
-            Err(anyhow!(error_string))
         }
+        Ok(None)
     } else {
         log::info!("No credentials and no PGPASSFILE file provided. Will succeed on trust connections");
         Ok(None)
     }
 }
 
+/// Match a hostname:port:database:username:password line of the password file as libpq does.
+/// Each of the first four fields matches literally or with the `*` wildcard, `\:` and `\\`
+/// escape a colon and a backslash, the first matching line wins. The localhost entries also match
+/// Unix-domain socket connections.
+fn match_password_line(line: &str, host: &str, port: &str, database: &str, user: &str) -> Option<String> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let mut fields = Vec::with_capacity(5);
+    let mut field = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { field.push(chars.next().unwrap_or('\\')) }
+            ':' if fields.len() < 4 => { fields.push(std::mem::take(&mut field)) }
+            // an unescaped colon ends the password
+            ':' => { break }
+            _ => { field.push(c) }
+        }
+    }
+    fields.push(field);
+    if fields.len() < 5 {
+        return None;
+    }
+    let host_matches = fields[0] == "*" || fields[0] == host || (fields[0] == "localhost" && host.starts_with('/'));
+    let matches = host_matches
+        && [(&fields[1], port), (&fields[2], database), (&fields[3], user)].iter()
+        .all(|(field, value)| *field == "*" || field == value);
+    if matches {
+        fields.pop()
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // use crate::db::pg::count_objects;
@@ -378,4 +657,34 @@ mod tests {
     //     println!("{nodes}, {ways}, {relations}");
     //     Ok(())
     // }
+
+    use crate::db::pg::{check_schema, escape_password_field, match_password_line};
+
+    #[test]
+    fn test_check_schema() {
+        assert!(check_schema("public").is_ok());
+        assert!(check_schema("osm_staging2").is_ok());
+        assert!(check_schema("").is_err());
+        assert!(check_schema("2osm").is_err());
+        assert!(check_schema("osm' -c x").is_err());
+        assert!(check_schema("Prod").is_err());
+    }
+
+    #[test]
+    fn test_match_password_line() {
+        let line = r"db.example.com:*:openstreetmap:osm:pa\:ss\\word";
+        assert_eq!(match_password_line(line, "db.example.com", "5433", "openstreetmap", "osm"), Some(r"pa:ss\word".to_string()));
+        assert_eq!(match_password_line(line, "db.example.com", "5432", "osm", "osm"), None);
+        assert_eq!(match_password_line(r"*:5432:*:o\:sm:secret:ignored", "host", "5432", "db", "o:sm"), Some("secret".to_string()));
+        assert_eq!(match_password_line("localhost:5432:*:osm:secret", "/var/run/postgresql", "5432", "db", "osm"), Some("secret".to_string()));
+        assert_eq!(match_password_line("#*:*:*:*:secret", "host", "5432", "db", "osm"), None);
+        assert_eq!(match_password_line("*:*:*:secret", "host", "5432", "db", "osm"), None);
+    }
+
+    #[test]
+    fn test_escape_password_field() {
+        let line = ["db.example.com", "5432", "osm", "osm", r"pa:ss\word"].map(escape_password_field).join(":");
+        assert_eq!(line, r"db.example.com:5432:osm:osm:pa\:ss\\word");
+        assert_eq!(match_password_line(&line, "db.example.com", "5432", "osm", "osm"), Some(r"pa:ss\word".to_string()));
+    }
 }
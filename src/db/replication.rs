@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
+use osm_io::osm::model::element::Element;
+use postgres::GenericClient;
+
+use crate::connection::Database;
+use crate::db::changes::{ActionCounts, ChangeApplier, ChangeCounts};
+use crate::db::pg::create_client;
+use crate::db::snapshot::{SnapshotReader, TransactionSnapshot};
+use crate::input::source::ElementSource;
+use crate::replication::directory::{change_path, state_path};
+use crate::replication::state::State;
+use crate::xml::action::Action;
+use crate::xml::change_writer::ChangeWriter;
+
+const STATE_TABLE: &str = "osm_admin_replication_state";
+
+/// Read the replication state of the database, None if it was never recorded
+pub(crate) fn read_state<C: GenericClient>(client: &mut C) -> Result<Option<State>, anyhow::Error> {
+    let rows = client.query(format!("select to_regclass('{STATE_TABLE}')::text as state_table").as_str(), &[])?;
+    let state_table: Option<String> = rows.first().and_then(|row| row.get("state_table"));
+    if state_table.is_none() {
+        return Ok(None);
+    }
+    let rows = client.query(format!("select sequence_number, \"timestamp\" from {STATE_TABLE} where id = 1").as_str(), &[])?;
+    match rows.first() {
+        None => {
+            Ok(None)
+        }
+        Some(row) => {
+            let sequence_number: i64 = row.get("sequence_number");
+            let timestamp: NaiveDateTime = row.get("timestamp");
+            Ok(Some(State::new(sequence_number, timestamp.and_utc())))
+        }
+    }
+}
+
+/// Record the replication state of the database, creating the state table if needed
+pub(crate) fn write_state<C: GenericClient>(client: &mut C, state: &State) -> Result<(), anyhow::Error> {
+    client.batch_execute(
+        format!(
+            "create table if not exists {STATE_TABLE} (\
+            id integer primary key default 1 check (id = 1), \
+            sequence_number bigint not null, \
+            \"timestamp\" timestamp without time zone not null, \
+            updated_at timestamp without time zone not null)"
+        ).as_str()
+    )?;
+    client.execute(
+        format!(
+            "insert into {STATE_TABLE} (id, sequence_number, \"timestamp\", updated_at) values (1, $1, $2, $3) \
+            on conflict (id) do update set sequence_number = excluded.sequence_number, \
+            \"timestamp\" = excluded.\"timestamp\", updated_at = excluded.updated_at"
+        ).as_str(),
+        &[&state.sequence_number(), &state.timestamp().naive_utc(), &Utc::now().naive_utc()],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn save_state(database: &Database, state: &State) -> Result<(), anyhow::Error> {
+    let mut client = create_client(database)?;
+    write_state(&mut client, state)?;
+    log::info!("Saved replication state, sequence number: {}, timestamp: {}", state.sequence_number(), state.timestamp());
+    Ok(())
+}
+
+/// Apply the change files missing from the database in the order of their sequence numbers
+///
+/// Each change file is applied in its own transaction together with the replication state, so
+/// an interrupted sync resumes from the last applied sequence number. Returns the number of applied
+/// change files and the change counts.
+pub(crate) fn sync(
+    database: &Database,
+    replication_path: &Path,
+    latest_state: &State,
+    start_sequence_number: Option<i64>,
+) -> Result<(i64, ChangeCounts), anyhow::Error> {
+    let mut client = create_client(database)?;
+    let first_sequence_number = match read_state(&mut client)? {
+        None => {
+            let start_sequence_number = start_sequence_number.ok_or(
+                anyhow!("No replication state found in the database. The --start-sequence-number must be provided")
+            )?;
+            log::info!("No replication state found in the database, starting from sequence number: {}", start_sequence_number);
+            start_sequence_number
+        }
+        Some(state) => {
+            if start_sequence_number.is_some() {
+                log::warn!("Ignoring start sequence number, the database is at sequence number: {}", state.sequence_number());
+            }
+            log::info!("Database replication state, sequence number: {}, timestamp: {}", state.sequence_number(), state.timestamp());
+            state.sequence_number() + 1
+        }
+    };
+
+    if first_sequence_number > latest_state.sequence_number() {
+        log::info!("Database is up to date with replication sequence number: {}", latest_state.sequence_number());
+        return Ok((0, ChangeCounts::default()));
+    }
+
+    log::info!("Missing sequence numbers: {}..={}", first_sequence_number, latest_state.sequence_number());
+    let mut change_applier = ChangeApplier::new(&mut client)?;
+    let mut applied = 0;
+    for sequence_number in first_sequence_number..=latest_state.sequence_number() {
+        let change_path = change_path(replication_path, sequence_number);
+        if !change_path.exists() {
+            return Err(anyhow!("Missing change file for sequence number {}: {}", sequence_number, change_path.display()));
+        }
+        let state_path = state_path(replication_path, sequence_number);
+        let state = if state_path.exists() {
+            State::from_path(&state_path)?
+        } else if sequence_number == latest_state.sequence_number() {
+            latest_state.clone()
+        } else {
+            return Err(anyhow!("Missing state file for sequence number {}: {}", sequence_number, state_path.display()));
+        };
+
+        let mut transaction = client.transaction()?;
+        change_applier.apply_file(&mut transaction, &change_path)?;
+        write_state(&mut transaction, &state)?;
+        transaction.commit()?;
+        applied += 1;
+        log::info!("Applied sequence number: {}, timestamp: {}", state.sequence_number(), state.timestamp());
+    }
+    Ok((applied, change_applier.counts().clone()))
+}
+
+/// Publish the element versions changed after the previous change file as the next change file of
+/// a replication directory
+///
+/// The changes are read from the history tables in an exported snapshot. They are the element
+/// versions committed after the transaction snapshot of the previous change file, so that a
+/// transaction that commits late is published with the next file rather than lost, or, without a
+/// previous transaction snapshot, the element versions with timestamps after `since`. The changes
+/// are written to the `AAA/BBB/CCC.osc.gz` and
+/// `AAA/BBB/CCC.state.txt` files of the sequence number, and then to `state.txt`. The first version
+/// of an element is a create, an invisible version is a delete and any other version is a
+/// modify. The sequence number follows `previous_sequence_number`, or is the snapshot
+/// transaction id when there is none. Returns the new state and the change counts, or None when
+/// the snapshot is not newer than `since`.
+pub(crate) fn publish_changes(
+    database: &Database,
+    replication_path: &Path,
+    since: DateTime<Utc>,
+    previous_sequence_number: Option<i64>,
+    previous_transaction_snapshot: Option<&String>,
+) -> Result<Option<(State, ChangeCounts)>, anyhow::Error> {
+    let previous_transaction_snapshot = previous_transaction_snapshot
+        .map(|transaction_snapshot| TransactionSnapshot::from_str(transaction_snapshot))
+        .transpose()?;
+    let (reader, transaction_id, timestamp, _) = SnapshotReader::new(database)?;
+    let until = timestamp.trunc_subsecs(0);
+    if until <= since {
+        log::info!("No changes to publish, the snapshot timestamp {} is not after {}", until, since);
+        return Ok(None);
+    }
+    let sequence_number = match previous_sequence_number {
+        None => {
+            log::info!("No previous sequence number, using snapshot transaction id: {}", transaction_id);
+            transaction_id as i64
+        }
+        Some(previous_sequence_number) => {
+            previous_sequence_number + 1
+        }
+    };
+    let state = State::new(sequence_number, until)
+        .with_transaction_snapshot(reader.transaction_snapshot().to_string());
+    match &previous_transaction_snapshot {
+        None => {
+            log::info!("Publish changes after {} until {}, sequence number: {}", since, until, sequence_number);
+        }
+        Some(previous_transaction_snapshot) => {
+            log::info!(
+                "Publish changes committed after transaction snapshot {} until {}, sequence number: {}",
+                previous_transaction_snapshot,
+                reader.transaction_snapshot(),
+                sequence_number
+            );
+        }
+    }
+
+    let change_path = change_path(replication_path, sequence_number);
+    let sequence_dir = change_path.parent().ok_or(anyhow!("Invalid change path: {}", change_path.display()))?;
+    fs::create_dir_all(sequence_dir)
+        .with_context(|| anyhow!("path: {}", sequence_dir.display()))?;
+    let tmp_change_path = change_path.with_extension("tmp.gz");
+    let mut counts = ChangeCounts::default();
+    let mut writer = ChangeWriter::new(&tmp_change_path, &format!("osm-admin-{}", env!("CARGO_PKG_VERSION")))?;
+    for element in reader.changed_elements(since, previous_transaction_snapshot)? {
+        let (action_counts, version, visible): (&mut ActionCounts, i32, bool) = match &element {
+            Element::Node { node } => { (&mut counts.nodes, node.version(), node.visible()) }
+            Element::Way { way } => { (&mut counts.ways, way.version(), way.visible()) }
+            Element::Relation { relation } => { (&mut counts.relations, relation.version(), relation.visible()) }
+            Element::Sentinel => { continue }
+        };
+        let action = if !visible {
+            Action::Delete
+        } else if version == 1 {
+            Action::Create
+        } else {
+            Action::Modify
+        };
+        action_counts.increment(action, true);
+        writer.write(action, &element)?;
+    }
+    reader.check()?;
+    writer.close()?;
+    fs::rename(&tmp_change_path, &change_path)
+        .with_context(|| anyhow!("path: {}", change_path.display()))?;
+    write_state_file(&state_path(replication_path, sequence_number), &state)?;
+    write_state_file(&replication_path.join("state.txt"), &state)?;
+    Ok(Some((state, counts)))
+}
+
+fn write_state_file(path: &Path, state: &State) -> Result<(), anyhow::Error> {
+    let tmp_path = path.with_extension("txt.tmp");
+    fs::write(&tmp_path, state.to_string())
+        .with_context(|| anyhow!("path: {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| anyhow!("path: {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,495 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use chrono::Utc;
+use escape_string::escape;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::Member;
+use osm_io::osm::model::tag::Tag;
+use transient_btree_index::{BtreeConfig, BtreeIndex};
+
+use crate::db::sql::{calculate_tile, to_copy_bool, to_copy_time_millis};
+
+/// Size of the rows buffered for one table before they are written out
+pub(crate) const BATCH_SIZE: usize = 8 * 1024 * 1024;
+/// Number of changesets buffered in memory before they are moved to the on disk index
+const CHANGESET_BUFFER_SIZE: usize = 1_000_000;
+
+/// apidb tables written from the elements
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Table {
+    Nodes,
+    NodeTags,
+    CurrentNodes,
+    CurrentNodeTags,
+    Ways,
+    WayNodes,
+    WayTags,
+    CurrentWays,
+    CurrentWayNodes,
+    CurrentWayTags,
+    Relations,
+    RelationMembers,
+    RelationTags,
+    CurrentRelations,
+    CurrentRelationMembers,
+    CurrentRelationTags,
+    Changesets,
+    ChangesetTags,
+    Users,
+}
+
+impl Table {
+    pub(crate) const ALL: [Table; 19] = [
+        Table::Nodes,
+        Table::NodeTags,
+        Table::CurrentNodes,
+        Table::CurrentNodeTags,
+        Table::Ways,
+        Table::WayNodes,
+        Table::WayTags,
+        Table::CurrentWays,
+        Table::CurrentWayNodes,
+        Table::CurrentWayTags,
+        Table::Relations,
+        Table::RelationMembers,
+        Table::RelationTags,
+        Table::CurrentRelations,
+        Table::CurrentRelationMembers,
+        Table::CurrentRelationTags,
+        Table::Changesets,
+        Table::ChangesetTags,
+        Table::Users,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Table::Nodes => { "nodes" }
+            Table::NodeTags => { "node_tags" }
+            Table::CurrentNodes => { "current_nodes" }
+            Table::CurrentNodeTags => { "current_node_tags" }
+            Table::Ways => { "ways" }
+            Table::WayNodes => { "way_nodes" }
+            Table::WayTags => { "way_tags" }
+            Table::CurrentWays => { "current_ways" }
+            Table::CurrentWayNodes => { "current_way_nodes" }
+            Table::CurrentWayTags => { "current_way_tags" }
+            Table::Relations => { "relations" }
+            Table::RelationMembers => { "relation_members" }
+            Table::RelationTags => { "relation_tags" }
+            Table::CurrentRelations => { "current_relations" }
+            Table::CurrentRelationMembers => { "current_relation_members" }
+            Table::CurrentRelationTags => { "current_relation_tags" }
+            Table::Changesets => { "changesets" }
+            Table::ChangesetTags => { "changeset_tags" }
+            Table::Users => { "users" }
+        }
+    }
+
+    /// Columns in the order of the rows, as in the apidb dump
+    pub(crate) fn columns(&self) -> &'static str {
+        match self {
+            Table::Nodes => { "node_id, latitude, longitude, changeset_id, visible, \"timestamp\", tile, version, redaction_id" }
+            Table::NodeTags => { "node_id, version, k, v" }
+            Table::CurrentNodes => { "id, latitude, longitude, changeset_id, visible, \"timestamp\", tile, version" }
+            Table::CurrentNodeTags => { "node_id, k, v" }
+            Table::Ways => { "way_id, changeset_id, \"timestamp\", version, visible, redaction_id" }
+            Table::WayNodes => { "way_id, node_id, version, sequence_id" }
+            Table::WayTags => { "way_id, k, v, version" }
+            Table::CurrentWays => { "id, changeset_id, \"timestamp\", visible, version" }
+            Table::CurrentWayNodes => { "way_id, node_id, sequence_id" }
+            Table::CurrentWayTags => { "way_id, k, v" }
+            Table::Relations => { "relation_id, changeset_id, \"timestamp\", version, visible, redaction_id" }
+            Table::RelationMembers => { "relation_id, member_type, member_id, member_role, version, sequence_id" }
+            Table::RelationTags => { "relation_id, k, v, version" }
+            Table::CurrentRelations => { "id, changeset_id, \"timestamp\", visible, version" }
+            Table::CurrentRelationMembers => { "relation_id, member_type, member_id, member_role, sequence_id" }
+            Table::CurrentRelationTags => { "relation_id, k, v" }
+            Table::Changesets => { "id, user_id, created_at, min_lat, max_lat, min_lon, max_lon, closed_at, num_changes" }
+            Table::ChangesetTags => { "changeset_id, k, v" }
+            Table::Users => { "email, id, pass_crypt, creation_time, display_name, data_public, description, home_lat, home_lon, home_zoom, pass_salt, email_valid, new_email, creation_ip, languages, status, terms_agreed, consider_pd, auth_uid, preferred_editor, terms_seen, description_format, changesets_count, traces_count, diary_entries_count, image_use_gravatar, auth_provider, home_tile, tou_agreed" }
+        }
+    }
+
+    /// Data file of the table in the directory dump, as in the TOC of the osm-io dump template
+    pub(crate) fn dump_file(&self) -> &'static str {
+        match self {
+            Table::Nodes => { "4260.dat" }
+            Table::NodeTags => { "4259.dat" }
+            Table::CurrentNodes => { "4228.dat" }
+            Table::CurrentNodeTags => { "4227.dat" }
+            Table::Ways => { "4294.dat" }
+            Table::WayNodes => { "4292.dat" }
+            Table::WayTags => { "4293.dat" }
+            Table::CurrentWays => { "4236.dat" }
+            Table::CurrentWayNodes => { "4234.dat" }
+            Table::CurrentWayTags => { "4235.dat" }
+            Table::Relations => { "4279.dat" }
+            Table::RelationMembers => { "4277.dat" }
+            Table::RelationTags => { "4278.dat" }
+            Table::CurrentRelations => { "4232.dat" }
+            Table::CurrentRelationMembers => { "4230.dat" }
+            Table::CurrentRelationTags => { "4231.dat" }
+            Table::Changesets => { "4222.dat" }
+            Table::ChangesetTags => { "4221.dat" }
+            Table::Users => { "4290.dat" }
+        }
+    }
+
+    /// Type of the elements the rows of the table are written from, as in [element_type], or None
+    /// for the changesets and users tables
+    pub(crate) fn element_type(&self) -> Option<u8> {
+        match self {
+            Table::Nodes | Table::NodeTags | Table::CurrentNodes | Table::CurrentNodeTags => { Some(0) }
+            Table::Ways | Table::WayNodes | Table::WayTags | Table::CurrentWays | Table::CurrentWayNodes | Table::CurrentWayTags => { Some(1) }
+            Table::Relations | Table::RelationMembers | Table::RelationTags | Table::CurrentRelations | Table::CurrentRelationMembers | Table::CurrentRelationTags => { Some(2) }
+            Table::Changesets | Table::ChangesetTags | Table::Users => { None }
+        }
+    }
+
+    fn is_current(&self) -> bool {
+        matches!(
+            self,
+            Table::CurrentNodes | Table::CurrentNodeTags | Table::CurrentWays | Table::CurrentWayNodes | Table::CurrentWayTags
+                | Table::CurrentRelations | Table::CurrentRelationMembers | Table::CurrentRelationTags
+        )
+    }
+}
+
+/// Type of the element, 0 for nodes, 1 for ways and 2 for relations, or None for the sentinel
+pub(crate) fn element_type(element: &Element) -> Option<u8> {
+    match element {
+        Element::Node { .. } => { Some(0) }
+        Element::Way { .. } => { Some(1) }
+        Element::Relation { .. } => { Some(2) }
+        Element::Sentinel => { None }
+    }
+}
+
+/// Type and id of the element, or None for the sentinel. The rows of the current tables are complete
+/// for an element when the next element has another key.
+pub(crate) fn element_key(element: &Element) -> Option<(u8, i64)> {
+    match element {
+        Element::Node { node } => { Some((0, node.id())) }
+        Element::Way { way } => { Some((1, way.id())) }
+        Element::Relation { relation } => { Some((2, relation.id())) }
+        Element::Sentinel => { None }
+    }
+}
+
+/// Writer of the rows of one table from the elements of its type, sorted by id and version
+///
+/// The rows of a current_* table are held back until an element with another id is written, and
+/// are written only for the latest version of an element, if it is visible.
+pub(crate) struct TableRows {
+    table: Table,
+    held: Option<(i64, bool, String)>,
+}
+
+impl TableRows {
+    pub(crate) fn new(table: Table) -> TableRows {
+        TableRows {
+            table,
+            held: None,
+        }
+    }
+
+    pub(crate) fn table(&self) -> Table {
+        self.table
+    }
+
+    /// Append the rows of the element to `rows`. Elements of other types are ignored.
+    pub(crate) fn write_element(&mut self, element: &Element, rows: &mut String) -> Result<(), anyhow::Error> {
+        if element_type(element) != self.table.element_type() {
+            return Ok(());
+        }
+        if !self.table.is_current() {
+            return write_rows(self.table, element, rows);
+        }
+        let (id, visible) = match element {
+            Element::Node { node } => { (node.id(), node.visible()) }
+            Element::Way { way } => { (way.id(), way.visible()) }
+            Element::Relation { relation } => { (relation.id(), relation.visible()) }
+            Element::Sentinel => { return Ok(()); }
+        };
+        if matches!(&self.held, Some((held_id, _, _)) if *held_id != id) {
+            self.finish(rows);
+        }
+        let mut held_rows = self.held.take().map(|(_, _, mut held_rows)| {
+            held_rows.clear();
+            held_rows
+        }).unwrap_or_default();
+        write_rows(self.table, element, &mut held_rows)?;
+        self.held = Some((id, visible, held_rows));
+        Ok(())
+    }
+
+    /// Append the held back rows of the last element to `rows`. Must be called only when the next
+    /// element written, if any, has another id.
+    pub(crate) fn finish(&mut self, rows: &mut String) {
+        if let Some((_, visible, held_rows)) = self.held.take() {
+            if visible {
+                rows.push_str(&held_rows);
+            }
+        }
+    }
+}
+
+fn write_rows(table: Table, element: &Element, rows: &mut String) -> Result<(), anyhow::Error> {
+    match element {
+        Element::Node { node } => {
+            match table {
+                Table::Nodes | Table::CurrentNodes => {
+                    write!(
+                        rows,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        node.id(),
+                        node.coordinate().lat7(),
+                        node.coordinate().lon7(),
+                        node.changeset(),
+                        to_copy_bool(node.visible()),
+                        to_copy_time_millis(node.timestamp())?,
+                        calculate_tile(node.coordinate().lat(), node.coordinate().lon()),
+                        node.version(),
+                    )?;
+                    rows.push_str(if table == Table::Nodes { "\t\\N\n" } else { "\n" });
+                }
+                Table::NodeTags => {
+                    write_tags(rows, node.id(), Some(node.version()), true, node.tags())?;
+                }
+                Table::CurrentNodeTags => {
+                    write_tags(rows, node.id(), None, true, node.tags())?;
+                }
+                _ => {}
+            }
+        }
+        Element::Way { way } => {
+            match table {
+                Table::Ways => {
+                    writeln!(rows, "{}\t{}\t{}\t{}\t{}\t\\N", way.id(), way.changeset(), to_copy_time_millis(way.timestamp())?, way.version(), to_copy_bool(way.visible()))?;
+                }
+                Table::CurrentWays => {
+                    writeln!(rows, "{}\t{}\t{}\t{}\t{}", way.id(), way.changeset(), to_copy_time_millis(way.timestamp())?, to_copy_bool(way.visible()), way.version())?;
+                }
+                Table::WayNodes => {
+                    for (i, node_id) in way.refs().iter().enumerate() {
+                        writeln!(rows, "{}\t{}\t{}\t{}", way.id(), node_id, way.version(), i + 1)?;
+                    }
+                }
+                Table::CurrentWayNodes => {
+                    for (i, node_id) in way.refs().iter().enumerate() {
+                        writeln!(rows, "{}\t{}\t{}", way.id(), node_id, i + 1)?;
+                    }
+                }
+                Table::WayTags => {
+                    write_tags(rows, way.id(), Some(way.version()), false, way.tags())?;
+                }
+                Table::CurrentWayTags => {
+                    write_tags(rows, way.id(), None, false, way.tags())?;
+                }
+                _ => {}
+            }
+        }
+        Element::Relation { relation } => {
+            match table {
+                Table::Relations => {
+                    writeln!(rows, "{}\t{}\t{}\t{}\t{}\t\\N", relation.id(), relation.changeset(), to_copy_time_millis(relation.timestamp())?, relation.version(), to_copy_bool(relation.visible()))?;
+                }
+                Table::CurrentRelations => {
+                    writeln!(rows, "{}\t{}\t{}\t{}\t{}", relation.id(), relation.changeset(), to_copy_time_millis(relation.timestamp())?, to_copy_bool(relation.visible()), relation.version())?;
+                }
+                Table::RelationMembers | Table::CurrentRelationMembers => {
+                    for (i, member) in relation.members().iter().enumerate() {
+                        let (member_type, member_data) = match member {
+                            Member::Node { member } => { ("Node", member) }
+                            Member::Way { member } => { ("Way", member) }
+                            Member::Relation { member } => { ("Relation", member) }
+                        };
+                        write!(rows, "{}\t{}\t{}\t{}\t", relation.id(), member_type, member_data.id(), escape(member_data.role()))?;
+                        if table == Table::RelationMembers {
+                            write!(rows, "{}\t", relation.version())?;
+                        }
+                        writeln!(rows, "{}", i + 1)?;
+                    }
+                }
+                Table::RelationTags => {
+                    write_tags(rows, relation.id(), Some(relation.version()), false, relation.tags())?;
+                }
+                Table::CurrentRelationTags => {
+                    write_tags(rows, relation.id(), None, false, relation.tags())?;
+                }
+                _ => {}
+            }
+        }
+        Element::Sentinel => {}
+    }
+    Ok(())
+}
+
+/// Write the tag rows of an element. The version of the history tables precedes the tag in
+/// node_tags and follows it in way_tags and relation_tags.
+fn write_tags(rows: &mut String, id: i64, version: Option<i32>, version_first: bool, tags: &[Tag]) -> Result<(), anyhow::Error> {
+    for tag in tags {
+        let (k, v) = (escape(tag.k()), escape(tag.v()));
+        match (version, version_first) {
+            (None, _) => { writeln!(rows, "{}\t{}\t{}", id, k, v)?; }
+            (Some(version), true) => { writeln!(rows, "{}\t{}\t{}\t{}", id, version, k, v)?; }
+            (Some(version), false) => { writeln!(rows, "{}\t{}\t{}\t{}", id, k, v, version)?; }
+        }
+    }
+    Ok(())
+}
+
+/// Changesets and users of the written elements
+///
+/// The element tables reference a changeset and its user, which are created with placeholder
+/// values as in the apidb dump.
+pub(crate) struct ChangesetUsers {
+    users: HashMap<i64, String>,
+    changeset_users: BtreeIndex<i64, i64>,
+    changeset_users_buffer: HashMap<i64, i64>,
+}
+
+impl ChangesetUsers {
+    pub(crate) fn new() -> Result<ChangesetUsers, anyhow::Error> {
+        Ok(
+            ChangesetUsers {
+                users: HashMap::new(),
+                changeset_users: BtreeIndex::with_capacity(BtreeConfig::default(), 0)?,
+                changeset_users_buffer: HashMap::new(),
+            }
+        )
+    }
+
+    /// Record the changeset and the user of the element, the last seen display name of a user is
+    /// kept
+    pub(crate) fn add(&mut self, element: &Element) -> Result<(), anyhow::Error> {
+        let (changeset, uid, user) = match element {
+            Element::Node { node } => { (node.changeset(), node.uid(), node.user()) }
+            Element::Way { way } => { (way.changeset(), way.uid(), way.user()) }
+            Element::Relation { relation } => { (relation.changeset(), relation.uid(), relation.user()) }
+            Element::Sentinel => { return Ok(()); }
+        };
+        let uid = uid as i64;
+        if self.users.get(&uid) != Some(user) {
+            self.users.insert(uid, user.clone());
+        }
+        self.changeset_users_buffer.insert(changeset, uid);
+        if self.changeset_users_buffer.len() >= CHANGESET_BUFFER_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), anyhow::Error> {
+        for (changeset, uid) in self.changeset_users_buffer.drain() {
+            self.changeset_users.insert(changeset, uid)?;
+        }
+        Ok(())
+    }
+
+    /// Write the rows of the changesets, changeset_tags and users tables
+    pub(crate) fn write_rows(&mut self, mut push_row: impl FnMut(Table, String) -> Result<(), anyhow::Error>) -> Result<(), anyhow::Error> {
+        self.flush()?;
+        let now = Utc::now().naive_utc().format("%Y-%m-%d %H:%M:%S%.6f").to_string();
+        let created_by = format!("osm-admin {}", option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"));
+        let changeset_users: Vec<(i64, i64)> = self.changeset_users.range(..)?
+            .collect::<Result<Vec<(i64, i64)>, transient_btree_index::Error>>()?;
+        for (changeset, uid) in changeset_users {
+            push_row(Table::ChangesetTags, format!("{}\tcreated_by\t{}\n", changeset, escape(&created_by)))?;
+            push_row(Table::ChangesetTags, format!("{}\treplication\ttrue\n", changeset))?;
+            push_row(
+                Table::Changesets,
+                format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n", changeset, uid, now, -900000000, 900000000, -1800000000, 1800000000, now, 0),
+            )?;
+        }
+        let users = unique_display_names(self.users.drain().collect());
+        for (uid, user) in users {
+            let user = escape(&user).to_string();
+            push_row(
+                Table::Users,
+                format!(
+                    "osm-admin-user-{}@example.com\t{}\t00000000000000000000000000000000\t{}\t{}\tt\t{}\t0\t0\t3\t00000000\tf\t\\N\t\\N\t\\N\tpending\t\\N\tf\t\\N\t\\N\tf\tmarkdown\t0\t0\t0\tf\t\\N\t\\N\t\\N\n",
+                    uid, uid, now, user, user,
+                ),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Assign each user a unique display name. Display names are reused after renames, so two uids
+/// can share one, which the unique index of users.display_name rejects. The lowest uid keeps the
+/// name, the others get the placeholder `{name}_{uid}`, with a counter appended if that is taken.
+fn unique_display_names(mut users: Vec<(i64, String)>) -> Vec<(i64, String)> {
+    users.sort();
+    let mut taken: HashSet<String> = users.iter().map(|(_, name)| name.clone()).collect();
+    let mut kept = HashSet::new();
+    users.into_iter()
+        .map(|(uid, name)| {
+            if kept.insert(name.clone()) {
+                return (uid, name);
+            }
+            let placeholder = placeholder_display_names(&name, uid)
+                .find(|placeholder| !taken.contains(placeholder))
+                .unwrap_or_default();
+            taken.insert(placeholder.clone());
+            (uid, placeholder)
+        })
+        .collect()
+}
+
+/// Placeholder display names of a user whose display name is taken, `{name}_{uid}` and then
+/// `{name}_{uid}_{counter}` counting from 1
+pub(crate) fn placeholder_display_names(name: &str, uid: i64) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(format!("{}_{}", name, uid))
+        .chain((1..).map(move |counter| format!("{}_{}_{}", name, uid, counter)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::rows::{Table, TableRows, unique_display_names};
+    use crate::fixtures::{node, way};
+
+    #[test]
+    fn test_unique_display_names() {
+        let users = vec![
+            (7, "mapper".to_string()),
+            (3, "mapper".to_string()),
+            (9, "mapper_7".to_string()),
+            (4, "other".to_string()),
+        ];
+        assert_eq!(
+            unique_display_names(users),
+            vec![
+                (3, "mapper".to_string()),
+                (4, "other".to_string()),
+                (7, "mapper_7_1".to_string()),
+                (9, "mapper_7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_current_rows() -> Result<(), anyhow::Error> {
+        let mut current_tags = TableRows::new(Table::CurrentNodeTags);
+        let mut tags = TableRows::new(Table::NodeTags);
+        let mut current_rows = String::new();
+        let mut rows = String::new();
+        for element in [
+            node(1, 1, true, 0.0, 0.0, &[("name", "a")]),
+            node(1, 2, true, 0.0, 0.0, &[("name", "b")]),
+            node(2, 1, true, 0.0, 0.0, &[("name", "c")]),
+            node(2, 2, false, 0.0, 0.0, &[]),
+            node(3, 1, true, 0.0, 0.0, &[("name", "d")]),
+            way(1, 1, true, vec![1, 3], &[("name", "e")]),
+        ] {
+            current_tags.write_element(&element, &mut current_rows)?;
+            tags.write_element(&element, &mut rows)?;
+        }
+        current_tags.finish(&mut current_rows);
+        assert_eq!(current_rows, "1\tname\tb\n3\tname\td\n");
+        assert_eq!(rows, "1\t1\tname\ta\n1\t2\tname\tb\n2\t1\tname\tc\n3\t1\tname\td\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,651 @@
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Lines};
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use osm_io::osm::model::coordinate::Coordinate;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::node::Node;
+use osm_io::osm::model::relation::{Member, MemberData, Relation};
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::model::way::Way;
+use postgres::Client;
+
+use crate::connection::Database;
+use crate::db::pg::{create_client, export_snapshot};
+use crate::db::replication::read_state;
+use crate::input::source::ElementSource;
+use crate::replication::state::State;
+
+/// Number of elements buffered between the database reader thread and the consumer
+const CHANNEL_CAPACITY: usize = 10000;
+
+fn nodes_query(condition: &str) -> String {
+    format!(
+        "\
+copy (
+    select n.node_id, n.version, n.latitude, n.longitude, (extract(epoch from n.\"timestamp\") * 1000)::bigint,
+        n.changeset_id, coalesce(c.user_id, 0), coalesce(u.display_name, ''), n.visible
+    from nodes n
+        left join changesets c on c.id = n.changeset_id
+        left join users u on u.id = c.user_id
+    where {condition}
+    order by n.node_id, n.version
+) to stdout"
+    )
+}
+
+fn ways_query(condition: &str) -> String {
+    format!(
+        "\
+copy (
+    select w.way_id, w.version, (extract(epoch from w.\"timestamp\") * 1000)::bigint,
+        w.changeset_id, coalesce(c.user_id, 0), coalesce(u.display_name, ''), w.visible
+    from ways w
+        left join changesets c on c.id = w.changeset_id
+        left join users u on u.id = c.user_id
+    where {condition}
+    order by w.way_id, w.version
+) to stdout"
+    )
+}
+
+fn relations_query(condition: &str) -> String {
+    format!(
+        "\
+copy (
+    select r.relation_id, r.version, (extract(epoch from r.\"timestamp\") * 1000)::bigint,
+        r.changeset_id, coalesce(c.user_id, 0), coalesce(u.display_name, ''), r.visible
+    from relations r
+        left join changesets c on c.id = r.changeset_id
+        left join users u on u.id = c.user_id
+    where {condition}
+    order by r.relation_id, r.version
+) to stdout"
+    )
+}
+
+/// Rows of a table of element parts, the tags, way nodes or relation members, of the element
+/// versions read from `table`, ordered by element id and version and then by `order`
+fn parts_query(columns: &str, parts_table: &str, table: &str, id_column: &str, order: &str, versions: &Versions) -> String {
+    let condition = match versions {
+        Versions::All => {
+            "true".to_string()
+        }
+        _ => {
+            format!(
+                "(p.{id_column}, p.version) in (select e.{id_column}, e.version from {table} e where {})",
+                versions.condition("e")
+            )
+        }
+    };
+    format!(
+        "\
+copy (
+    select p.{id_column}, p.version, {columns}
+    from {parts_table} p
+    where {condition}
+    order by p.{id_column}, p.version{order}
+) to stdout"
+    )
+}
+
+/// Reader of the elements directly from the database, without an intermediate dump
+///
+/// The reader holds a repeatable read transaction with an exported snapshot open for its whole
+/// lifetime. Each call to [SnapshotReader::elements] reads nodes, ways and relations with
+/// `COPY ... TO STDOUT` on new connections that import the snapshot, so every pass sees the
+/// same data. The element rows and the rows of their tags, way nodes and relation members are
+/// read as separate streams ordered by id and version, one per connection, and merged. The
+/// elements are ordered by type, id and version by the server and are followed by
+/// [Element::Sentinel] after each type, the same as from the apidb dump reader.
+///
+/// The iterators yield elements, a failure ends the iteration and is returned by
+/// [ElementSource::check].
+pub(crate) struct SnapshotReader {
+    // keeps the snapshot transaction open
+    _client: Client,
+    database: Database,
+    snapshot_name: String,
+    transaction_snapshot: TransactionSnapshot,
+    error: Arc<Mutex<Option<anyhow::Error>>>,
+}
+
+impl SnapshotReader {
+    /// Open the snapshot. Returns the reader with the snapshot transaction id, timestamp and
+    /// the replication state of the database.
+    pub(crate) fn new(database: &Database) -> Result<(SnapshotReader, u64, DateTime<Utc>, Option<State>), anyhow::Error> {
+        let mut client = create_client(database)?;
+        let (snapshot_name, transaction_id, timestamp) = export_snapshot(&mut client)?;
+        let replication_state = read_state(&mut client)?;
+        let rows = client.query("select cast(pg_current_snapshot() as text) as transaction_snapshot", &[])?;
+        let transaction_snapshot = TransactionSnapshot::from_str(
+            rows.first().ok_or(anyhow!("No transaction snapshot"))?.get("transaction_snapshot")
+        )?;
+        log::info!("Exported snapshot: {}, transaction id: {}, transaction snapshot: {}", snapshot_name, transaction_id, transaction_snapshot);
+        Ok(
+            (
+                SnapshotReader {
+                    _client: client,
+                    database: database.clone(),
+                    snapshot_name,
+                    transaction_snapshot,
+                    error: Arc::new(Mutex::new(None)),
+                },
+                transaction_id,
+                timestamp,
+                replication_state,
+            )
+        )
+    }
+
+    pub(crate) fn elements(&self) -> Result<SnapshotElementIterator, anyhow::Error> {
+        self.read(Versions::All)
+    }
+
+    /// The transaction snapshot of the reader, recorded in the replication state to select the
+    /// changes of the next snapshot
+    pub(crate) fn transaction_snapshot(&self) -> &TransactionSnapshot {
+        &self.transaction_snapshot
+    }
+
+    /// The element versions committed after the previous transaction snapshot, or, without one,
+    /// with timestamps after `since`, ordered the same as [SnapshotReader::elements]
+    pub(crate) fn changed_elements(&self, since: DateTime<Utc>, previous: Option<TransactionSnapshot>) -> Result<SnapshotElementIterator, anyhow::Error> {
+        let versions = match previous {
+            None => {
+                Versions::After(since)
+            }
+            Some(previous) => {
+                if previous.xmin > self.transaction_snapshot.xmax {
+                    return Err(anyhow!("The previous transaction snapshot {} is newer than the current {}", previous, self.transaction_snapshot));
+                }
+                Versions::CommittedAfter { previous, current_xmax: self.transaction_snapshot.xmax }
+            }
+        };
+        self.read(versions)
+    }
+
+    fn read(&self, versions: Versions) -> Result<SnapshotElementIterator, anyhow::Error> {
+        let mut clients = [self.snapshot_client()?, self.snapshot_client()?, self.snapshot_client()?];
+        let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+        std::thread::Builder::new()
+            .name("snapshot-reader".to_string())
+            .spawn(move || {
+                if let Err(e) = read_elements(&mut clients, &sender, &versions) {
+                    // the receiver may be gone when the consumer stopped early
+                    let _ = sender.send(Err(e));
+                }
+            })?;
+        Ok(
+            SnapshotElementIterator {
+                receiver,
+                error: self.error.clone(),
+            }
+        )
+    }
+
+    /// A new connection in a transaction that imports the snapshot
+    fn snapshot_client(&self) -> Result<Client, anyhow::Error> {
+        let mut client = create_client(&self.database)?;
+        client.batch_execute(
+            format!(
+                "begin transaction isolation level repeatable read read only; set transaction snapshot '{}';",
+                self.snapshot_name
+            ).as_str()
+        )?;
+        Ok(client)
+    }
+}
+
+impl ElementSource for SnapshotReader {
+    fn elements(&self) -> Result<Box<dyn Iterator<Item=Element>>, anyhow::Error> {
+        Ok(Box::new(SnapshotReader::elements(self)?))
+    }
+
+    fn check(&self) -> Result<(), anyhow::Error> {
+        match self.error.lock().map_err(|e| anyhow!("{}", e))?.take() {
+            None => {
+                Ok(())
+            }
+            Some(e) => {
+                Err(e)
+            }
+        }
+    }
+}
+
+pub(crate) struct SnapshotElementIterator {
+    receiver: Receiver<Result<Element, anyhow::Error>>,
+    error: Arc<Mutex<Option<anyhow::Error>>>,
+}
+
+impl Iterator for SnapshotElementIterator {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.recv() {
+            Ok(Ok(element)) => {
+                Some(element)
+            }
+            Ok(Err(e)) => {
+                log::error!("Failed to read elements from the database: {}", e);
+                if let Ok(mut error) = self.error.lock() {
+                    error.replace(e);
+                }
+                None
+            }
+            Err(_) => {
+                None
+            }
+        }
+    }
+}
+
+/// Rows in the COPY text format, each split into its unescaped columns
+type Rows = Vec<Vec<String>>;
+
+/// Parse an element from its row and the rows of its parts
+type ParseElement = fn(&[String], Vec<Rows>) -> Result<Element, anyhow::Error>;
+
+fn read_elements(
+    clients: &mut [Client; 3],
+    sender: &SyncSender<Result<Element, anyhow::Error>>,
+    versions: &Versions,
+) -> Result<(), anyhow::Error> {
+    let [elements_client, parts_clients @ ..] = clients;
+    for (query, parts_queries, parse) in [
+        (
+            nodes_query(&versions.condition("n")),
+            vec![parts_query("p.k, p.v", "node_tags", "nodes", "node_id", "", versions)],
+            parse_node as ParseElement,
+        ),
+        (
+            ways_query(&versions.condition("w")),
+            vec![
+                parts_query("p.k, p.v", "way_tags", "ways", "way_id", "", versions),
+                parts_query("p.node_id", "way_nodes", "ways", "way_id", ", p.sequence_id", versions),
+            ],
+            parse_way,
+        ),
+        (
+            relations_query(&versions.condition("r")),
+            vec![
+                parts_query("p.k, p.v", "relation_tags", "relations", "relation_id", "", versions),
+                parts_query("p.member_type, p.member_id, p.member_role", "relation_members", "relations", "relation_id", ", p.sequence_id", versions),
+            ],
+            parse_relation,
+        ),
+    ] {
+        let mut parts = parts_clients.iter_mut()
+            .zip(&parts_queries)
+            .map(|(client, query)| Ok(PartRows::new(BufReader::new(client.copy_out(query.as_str())?))))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let reader = BufReader::new(elements_client.copy_out(query.as_str())?);
+        for line in reader.lines() {
+            let row = split_copy_row(&line?);
+            let key = row_key(&row)?;
+            let element_parts = parts.iter_mut()
+                .map(|part| part.take(key))
+                .collect::<Result<Vec<Rows>, anyhow::Error>>()?;
+            if sender.send(Ok(parse(&row, element_parts)?)).is_err() {
+                return Ok(());
+            }
+        }
+        for part in parts {
+            part.finish()?;
+        }
+        if sender.send(Ok(Element::Sentinel)).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Rows of the parts of the elements, read along the element rows in the same id and version order
+struct PartRows<R: BufRead> {
+    lines: Lines<R>,
+    next: Option<Vec<String>>,
+}
+
+impl<R: BufRead> PartRows<R> {
+    fn new(reader: R) -> PartRows<R> {
+        PartRows {
+            lines: reader.lines(),
+            next: None,
+        }
+    }
+
+    /// The rows of the element version, skipping the rows of the element versions before it
+    fn take(&mut self, key: (i64, i64)) -> Result<Rows, anyhow::Error> {
+        let mut rows = Vec::new();
+        loop {
+            let row = match self.next.take() {
+                Some(row) => {
+                    row
+                }
+                None => {
+                    match self.lines.next() {
+                        Some(line) => { split_copy_row(&line?) }
+                        None => { return Ok(rows); }
+                    }
+                }
+            };
+            let row_key = row_key(&row)?;
+            if row_key > key {
+                self.next = Some(row);
+                return Ok(rows);
+            } else if row_key == key {
+                rows.push(row);
+            }
+        }
+    }
+
+    /// Read the remaining rows, so that the connection is ready for the next query
+    fn finish(mut self) -> Result<(), anyhow::Error> {
+        for line in self.lines.by_ref() {
+            line?;
+        }
+        Ok(())
+    }
+}
+
+/// Transaction snapshot in the `xmin:xmax:xip,...` text format of pg_current_snapshot(). The
+/// transactions before xmin are committed or aborted, the transactions from xmax on and the listed
+/// transactions in progress are not visible in the snapshot.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct TransactionSnapshot {
+    text: String,
+    xmin: u64,
+    xmax: u64,
+}
+
+impl FromStr for TransactionSnapshot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid transaction snapshot: {}, expected xmin:xmax:xip,...", s);
+        let mut parts = s.split(':');
+        let xmin = parts.next().and_then(|xmin| u64::from_str(xmin).ok()).ok_or_else(invalid)?;
+        let xmax = parts.next().and_then(|xmax| u64::from_str(xmax).ok()).ok_or_else(invalid)?;
+        let xip = parts.next().ok_or_else(invalid)?;
+        if parts.next().is_some() || xmin > xmax || xip.split(',').any(|xid| !xid.is_empty() && u64::from_str(xid).is_err()) {
+            return Err(invalid());
+        }
+        Ok(TransactionSnapshot { text: s.to_string(), xmin, xmax })
+    }
+}
+
+impl Display for TransactionSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// The element versions read from the snapshot
+enum Versions {
+    All,
+    /// With timestamps after the time
+    After(DateTime<Utc>),
+    /// Inserted by the transactions committed after the previous snapshot, that is visible in the
+    /// current snapshot and not in the previous one. Unlike the element timestamps, this also
+    /// covers transactions that committed after the previous snapshot was taken.
+    CommittedAfter {
+        previous: TransactionSnapshot,
+        current_xmax: u64,
+    },
+}
+
+impl Versions {
+    fn condition(&self, alias: &str) -> String {
+        match self {
+            Versions::All => {
+                "true".to_string()
+            }
+            Versions::After(since) => {
+                format!("{alias}.\"timestamp\" > '{}'", since.format("%Y-%m-%d %H:%M:%S%.f"))
+            }
+            Versions::CommittedAfter { previous, current_xmax } => {
+                // xmin is the 32 bit id of the inserting transaction. Its distance back from the
+                // current xmax gives the 64 bit id, the ids of the rows that may be missing from
+                // the previous snapshot are no more than current xmax - previous xmin back.
+                let distance = format!(
+                    "(({} - {alias}.xmin::text::bigint + 4294967296) % 4294967296)",
+                    current_xmax % 4294967296
+                );
+                format!(
+                    "{distance} <= {} and not pg_visible_in_snapshot(({current_xmax} - {distance})::text::xid8, '{previous}'::pg_snapshot)",
+                    current_xmax - previous.xmin
+                )
+            }
+        }
+    }
+}
+
+/// Reverse the escaping of a value in the COPY text format
+fn unescape_copy_text(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => { unescaped.push('\u{8}') }
+            Some('f') => { unescaped.push('\u{c}') }
+            Some('n') => { unescaped.push('\n') }
+            Some('r') => { unescaped.push('\r') }
+            Some('t') => { unescaped.push('\t') }
+            Some('v') => { unescaped.push('\u{b}') }
+            Some(other) => { unescaped.push(other) }
+            None => { unescaped.push('\\') }
+        }
+    }
+    unescaped
+}
+
+/// Split a row in the COPY text format into its unescaped columns
+fn split_copy_row(line: &str) -> Vec<String> {
+    line.split('\t').map(unescape_copy_text).collect()
+}
+
+/// The element id and version in the first two columns of a row
+fn row_key(row: &[String]) -> Result<(i64, i64), anyhow::Error> {
+    Ok((get_i64(row, 0)?, get_i64(row, 1)?))
+}
+
+fn parse_node(row: &[String], parts: Vec<Rows>) -> Result<Element, anyhow::Error> {
+    let mut parts = parts.into_iter();
+    Ok(
+        Element::Node {
+            node: Node::new(
+                get_i64(row, 0)?,
+                get_i64(row, 1)? as i32,
+                Coordinate::new(
+                    get_i64(row, 2)? as f64 / 10000000.0f64,
+                    get_i64(row, 3)? as f64 / 10000000.0f64,
+                ),
+                get_i64(row, 4)?,
+                get_i64(row, 5)?,
+                get_i64(row, 6)? as i32,
+                get_str(row, 7)?,
+                get_bool(row, 8)?,
+                parse_tags(parts.next().unwrap_or_default())?,
+            )
+        }
+    )
+}
+
+fn parse_way(row: &[String], parts: Vec<Rows>) -> Result<Element, anyhow::Error> {
+    let mut parts = parts.into_iter();
+    let tags = parse_tags(parts.next().unwrap_or_default())?;
+    let refs = parts.next().unwrap_or_default().iter()
+        .map(|way_node| get_i64(way_node, 2))
+        .collect::<Result<Vec<i64>, anyhow::Error>>()?;
+    Ok(
+        Element::Way {
+            way: Way::new(
+                get_i64(row, 0)?,
+                get_i64(row, 1)? as i32,
+                get_i64(row, 2)?,
+                get_i64(row, 3)?,
+                get_i64(row, 4)? as i32,
+                get_str(row, 5)?,
+                get_bool(row, 6)?,
+                refs,
+                tags,
+            )
+        }
+    )
+}
+
+fn parse_relation(row: &[String], parts: Vec<Rows>) -> Result<Element, anyhow::Error> {
+    let mut parts = parts.into_iter();
+    let tags = parse_tags(parts.next().unwrap_or_default())?;
+    let members = parts.next().unwrap_or_default().iter()
+        .map(|member| {
+            let member_data = MemberData::new(get_i64(member, 3)?, get_str(member, 4)?);
+            match get_str(member, 2)?.as_str() {
+                "Node" => { Ok(Member::Node { member: member_data }) }
+                "Way" => { Ok(Member::Way { member: member_data }) }
+                "Relation" => { Ok(Member::Relation { member: member_data }) }
+                _ => { Err(anyhow!("Invalid relation member: {:?}", member)) }
+            }
+        })
+        .collect::<Result<Vec<Member>, anyhow::Error>>()?;
+    Ok(
+        Element::Relation {
+            relation: Relation::new(
+                get_i64(row, 0)?,
+                get_i64(row, 1)? as i32,
+                get_i64(row, 2)?,
+                get_i64(row, 3)?,
+                get_i64(row, 4)? as i32,
+                get_str(row, 5)?,
+                get_bool(row, 6)?,
+                members,
+                tags,
+            )
+        }
+    )
+}
+
+fn parse_tags(tags: Rows) -> Result<Vec<Tag>, anyhow::Error> {
+    tags.iter()
+        .map(|tag| Ok(Tag::new(get_str(tag, 2)?, get_str(tag, 3)?)))
+        .collect()
+}
+
+fn get_str(row: &[String], i: usize) -> Result<String, anyhow::Error> {
+    Ok(row.get(i).ok_or(anyhow!("Expected a column at {} in: {:?}", i, row))?.clone())
+}
+
+fn get_i64(row: &[String], i: usize) -> Result<i64, anyhow::Error> {
+    i64::from_str(&get_str(row, i)?).map_err(|_| anyhow!("Expected an integer at {} in: {:?}", i, row))
+}
+
+fn get_bool(row: &[String], i: usize) -> Result<bool, anyhow::Error> {
+    match get_str(row, i)?.as_str() {
+        "t" => { Ok(true) }
+        "f" => { Ok(false) }
+        _ => { Err(anyhow!("Expected a boolean at {} in: {:?}", i, row)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use osm_io::osm::model::element::Element;
+    use osm_io::osm::model::relation::Member;
+
+    use chrono::DateTime;
+
+    use crate::db::snapshot::{parse_node, parse_relation, parts_query, split_copy_row, PartRows, TransactionSnapshot, Versions};
+
+    #[test]
+    fn test_parse_copy_rows() -> Result<(), anyhow::Error> {
+        let row = split_copy_row("1001\t2\t-190543210\t-1699123456\t1686564000000\t5001\t42\tMapper \\\\M\\\\\tt");
+        let tags = vec![split_copy_row("1001\t2\tname\tNiue\\tIsland")];
+        let node = parse_node(&row, vec![tags])?;
+        match node {
+            Element::Node { node } => {
+                assert_eq!(node.id(), 1001);
+                assert_eq!(node.version(), 2);
+                assert_eq!(node.coordinate().lat7(), -190543210);
+                assert_eq!(node.coordinate().lon7(), -1699123456);
+                assert_eq!(node.user(), "Mapper \\M\\");
+                assert!(node.visible());
+                assert_eq!(node.tags()[0].v(), "Niue\tIsland");
+            }
+            _ => { panic!("Expected a node") }
+        }
+
+        let row = split_copy_row("3001\t1\t1686564002000\t5002\t43\tOther\tf");
+        let members = vec![split_copy_row("3001\t1\tWay\t2001\touter"), split_copy_row("3001\t1\tNode\t1001\t")];
+        let relation = parse_relation(&row, vec![vec![], members])?;
+        match relation {
+            Element::Relation { relation } => {
+                assert!(!relation.visible());
+                assert!(relation.tags().is_empty());
+                assert!(matches!(relation.members()[0], Member::Way { .. }));
+                assert!(matches!(relation.members()[1], Member::Node { .. }));
+                assert_eq!(relation.members().len(), 2);
+            }
+            _ => { panic!("Expected a relation") }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_part_rows() -> Result<(), anyhow::Error> {
+        let lines = "1\t1\ta\n1\t1\tb\n2\t1\tc\n3\t1\td\n3\t2\te\n4\t1\tf\n";
+        let mut parts = PartRows::new(lines.as_bytes());
+        let values = |rows: Vec<Vec<String>>| rows.into_iter().map(|row| row[2].clone()).collect::<Vec<String>>();
+        assert_eq!(values(parts.take((1, 1))?), vec!["a", "b"]);
+        assert!(parts.take((2, 0))?.is_empty());
+        assert_eq!(values(parts.take((3, 1))?), vec!["d"]);
+        assert_eq!(values(parts.take((3, 2))?), vec!["e"]);
+        assert!(parts.take((5, 1))?.is_empty());
+        parts.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parts_query() {
+        assert_eq!(
+            parts_query("p.node_id", "way_nodes", "ways", "way_id", ", p.sequence_id", &Versions::All),
+            "copy (\n    select p.way_id, p.version, p.node_id\n    from way_nodes p\n    where true\n    order by p.way_id, p.version, p.sequence_id\n) to stdout"
+        );
+        let since = DateTime::from_timestamp(1686564002, 0).unwrap();
+        assert!(
+            parts_query("p.k, p.v", "node_tags", "nodes", "node_id", "", &Versions::After(since))
+                .contains("where (p.node_id, p.version) in (select e.node_id, e.version from nodes e where e.\"timestamp\" > '2023-06-12 10:00:02')")
+        );
+    }
+
+    #[test]
+    fn test_versions_condition() -> Result<(), anyhow::Error> {
+        assert_eq!(Versions::All.condition("n"), "true");
+        let since = DateTime::from_timestamp(1686564002, 0).unwrap();
+        assert_eq!(Versions::After(since).condition("w"), "w.\"timestamp\" > '2023-06-12 10:00:02'");
+
+        let previous = TransactionSnapshot::from_str("4294967290:4294967300:4294967295")?;
+        let condition = Versions::CommittedAfter { previous, current_xmax: 4294967310 }.condition("r");
+        assert_eq!(
+            condition,
+            "((14 - r.xmin::text::bigint + 4294967296) % 4294967296) <= 20 \
+            and not pg_visible_in_snapshot((4294967310 - ((14 - r.xmin::text::bigint + 4294967296) % 4294967296))::text::xid8, '4294967290:4294967300:4294967295'::pg_snapshot)"
+        );
+
+        assert_eq!(TransactionSnapshot::from_str("10:12:")?.to_string(), "10:12:");
+        assert!(TransactionSnapshot::from_str("12:10:").is_err());
+        assert!(TransactionSnapshot::from_str("10:12").is_err());
+        assert!(TransactionSnapshot::from_str("10:12:x").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::ops::{Shl, Shr};
+
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDateTime};
+
+/// The quad tile of a point as defined by the apidb schema
+pub(crate) fn calculate_tile(lat: f64, lon: f64) -> i64 {
+    let x = ((lon + 180.0) * 65535.0 / 360.0).round() as u64;
+    let y = ((lat + 90.0) * 65535.0 / 180.0).round() as u64;
+    let mut tile = 0_u64;
+    for i in (0..16).rev() {
+        tile = tile.shl(1) | (x.shr(i) & 1_u64);
+        tile = tile.shl(1) | (y.shr(i) & 1_u64);
+    }
+    tile as i64
+}
+
+pub(crate) fn to_sql_time_millis(t: i64) -> Result<NaiveDateTime, anyhow::Error> {
+    DateTime::from_timestamp_millis(t)
+        .map(|datetime| datetime.naive_utc())
+        .ok_or(anyhow!("Timestamp out of range: {}", t))
+}
+
+/// Timestamp in the COPY text format of a timestamp without time zone column
+pub(crate) fn to_copy_time_millis(t: i64) -> Result<String, anyhow::Error> {
+    Ok(to_sql_time_millis(t)?.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Boolean in the COPY text format
+pub(crate) fn to_copy_bool(v: bool) -> char {
+    if v { 't' } else { 'f' }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::sql::{calculate_tile, to_copy_time_millis};
+
+    #[test]
+    fn test_calculate_tile() {
+        assert_eq!(calculate_tile(-90.0, -180.0), 0);
+        assert_eq!(calculate_tile(90.0, 180.0), 4294967295);
+    }
+
+    #[test]
+    fn test_to_copy_time_millis() -> Result<(), anyhow::Error> {
+        assert_eq!(to_copy_time_millis(1686564002000)?, "2023-06-12 10:00:02");
+        Ok(())
+    }
+}
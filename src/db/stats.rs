@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::connection::Database;
+use crate::db::pg::create_client;
+use crate::verify::stats::{Stats, TypeStats};
+
+/// Element table, id column and tag table of each element type
+const TYPE_TABLES: [(&str, &str, &str); 3] = [
+    ("nodes", "node_id", "node_tags"),
+    ("ways", "way_id", "way_tags"),
+    ("relations", "relation_id", "relation_tags"),
+];
+
+/// Compute the [Stats] of the history tables of the database. The element and tag tables of each
+/// type are aggregated in parallel, each query on its own connection.
+///
+/// The tag hash is computed as in [crate::verify::stats::tag_hash], summed as numeric and reduced
+/// modulo 2^64.
+pub(crate) fn database_stats(database: &Database) -> Result<Stats, anyhow::Error> {
+    let results: Vec<Result<TypeStats, anyhow::Error>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = TYPE_TABLES.iter()
+            .map(|(table, id_column, tag_table)| {
+                let elements = scope.spawn(move || element_stats(database, table, id_column));
+                let tags = scope.spawn(move || tag_stats(database, tag_table, id_column));
+                (elements, tags)
+            })
+            .collect();
+        handles.into_iter()
+            .map(|(elements, tags)| {
+                let (count, max_id, version_sum) = elements.join().map_err(|_| anyhow!("Element stats query panicked"))??;
+                let (tag_count, tag_hash) = tags.join().map_err(|_| anyhow!("Tag stats query panicked"))??;
+                Ok(TypeStats { count, max_id, version_sum, tag_count, tag_hash })
+            })
+            .collect()
+    });
+    let mut results = results.into_iter();
+    let mut next = || results.next().ok_or(anyhow!("Missing stats"))?;
+    Ok(
+        Stats {
+            nodes: next()?,
+            ways: next()?,
+            relations: next()?,
+        }
+    )
+}
+
+fn element_stats(database: &Database, table: &str, id_column: &str) -> Result<(i64, i64, i64), anyhow::Error> {
+    log::info!("Aggregate {}", table);
+    let mut client = create_client(database)?;
+    let query = format!(
+        "select count(*) as count, coalesce(max({id_column}), 0) as max_id, cast(coalesce(sum(version), 0) as text) as version_sum from {table}"
+    );
+    let row = client.query_one(query.as_str(), &[])?;
+    let version_sum: String = row.get("version_sum");
+    Ok((row.get("count"), row.get("max_id"), i64::from_str(&version_sum)?))
+}
+
+fn tag_stats(database: &Database, tag_table: &str, id_column: &str) -> Result<(i64, u64), anyhow::Error> {
+    log::info!("Aggregate {}", tag_table);
+    let mut client = create_client(database)?;
+    let query = format!(
+        "select count(*) as count, \
+         cast(mod(mod(coalesce(sum(('x' || substr(md5({id_column} || ':' || version || ':' || k || '=' || v), 1, 16))::bit(64)::bigint), 0), 18446744073709551616) + 18446744073709551616, 18446744073709551616) as text) as tag_hash \
+         from {tag_table}"
+    );
+    let row = client.query_one(query.as_str(), &[])?;
+    let tag_hash: String = row.get("tag_hash");
+    Ok((row.get("count"), u64::from_str(&tag_hash)?))
+}
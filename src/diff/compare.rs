@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat};
+use json::{array, object, JsonValue};
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::Member;
+use osm_io::osm::model::tag::Tag;
+
+/// Difference of one object between the two element streams
+#[derive(Debug, Clone)]
+pub(crate) enum Change {
+    Added {
+        element: Element,
+    },
+    Removed {
+        element: Element,
+    },
+    Modified {
+        to: Element,
+        differences: JsonValue,
+    },
+}
+
+impl Change {
+    pub(crate) fn element(&self) -> &Element {
+        match self {
+            Change::Added { element } => { element }
+            Change::Removed { element } => { element }
+            Change::Modified { to, .. } => { to }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let (element_type, id) = key_name(self.element());
+        match self {
+            Change::Added { .. } => {
+                object! { type: element_type, id: id, change: "added" }
+            }
+            Change::Removed { .. } => {
+                object! { type: element_type, id: id, change: "removed" }
+            }
+            Change::Modified { differences, .. } => {
+                object! { type: element_type, id: id, change: "modified", differences: differences.clone() }
+            }
+        }
+    }
+}
+
+/// Number of added, removed and modified objects of one type
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct ChangeCounts {
+    pub(crate) added: u64,
+    pub(crate) removed: u64,
+    pub(crate) modified: u64,
+}
+
+impl ChangeCounts {
+    pub(crate) fn to_json(&self) -> JsonValue {
+        object! { added: self.added, removed: self.removed, modified: self.modified }
+    }
+}
+
+/// Lockstep comparison of two element streams ordered by type and id
+///
+/// The streams are expected to contain one version of each object, as produced by
+/// [crate::filter::current::CurrentElementIterator]. [Element::Sentinel] is skipped. An object
+/// that is present only in the `to` stream is added, only in the `from` stream is removed, and
+/// present in both with any difference in metadata, coordinates, tags, way nodes or relation
+/// members is modified.
+pub(crate) struct Diff<A: Iterator<Item=Element>, B: Iterator<Item=Element>> {
+    from: Peekable<A>,
+    to: Peekable<B>,
+    last_from: Option<(u8, i64)>,
+    last_to: Option<(u8, i64)>,
+}
+
+impl<A: Iterator<Item=Element>, B: Iterator<Item=Element>> Diff<A, B> {
+    pub(crate) fn new(from: A, to: B) -> Diff<A, B> {
+        Diff {
+            from: from.peekable(),
+            to: to.peekable(),
+            last_from: None,
+            last_to: None,
+        }
+    }
+
+    fn next_key(elements: &mut Peekable<impl Iterator<Item=Element>>, last: Option<(u8, i64)>, name: &str) -> Result<Option<(u8, i64)>, anyhow::Error> {
+        while let Some(Element::Sentinel) = elements.peek() {
+            elements.next();
+        }
+        match elements.peek() {
+            None => {
+                Ok(None)
+            }
+            Some(element) => {
+                let element_key = key(element);
+                if let Some(last_key) = last {
+                    if element_key <= last_key {
+                        return Err(anyhow!("The {} elements are not sorted by type and id, or contain more than one version: {:?}", name, key_name(element)));
+                    }
+                }
+                Ok(Some(element_key))
+            }
+        }
+    }
+}
+
+impl<A: Iterator<Item=Element>, B: Iterator<Item=Element>> Iterator for Diff<A, B> {
+    type Item = Result<Change, anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let from_key = match Self::next_key(&mut self.from, self.last_from, "from") {
+                Ok(key) => { key }
+                Err(e) => { return Some(Err(e)) }
+            };
+            let to_key = match Self::next_key(&mut self.to, self.last_to, "to") {
+                Ok(key) => { key }
+                Err(e) => { return Some(Err(e)) }
+            };
+            match (from_key, to_key) {
+                (None, None) => {
+                    return None;
+                }
+                (Some(from_key), to_key) if to_key.is_none_or(|to_key| from_key < to_key) => {
+                    self.last_from = Some(from_key);
+                    return self.from.next().map(|element| Ok(Change::Removed { element }));
+                }
+                (from_key, Some(to_key)) if from_key.is_none_or(|from_key| to_key < from_key) => {
+                    self.last_to = Some(to_key);
+                    return self.to.next().map(|element| Ok(Change::Added { element }));
+                }
+                (Some(from_key), Some(to_key)) => {
+                    self.last_from = Some(from_key);
+                    self.last_to = Some(to_key);
+                    let from = self.from.next()?;
+                    let to = self.to.next()?;
+                    let differences = differences(&from, &to);
+                    if !differences.is_empty() {
+                        return Some(Ok(Change::Modified { to, differences }));
+                    }
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+fn key(element: &Element) -> (u8, i64) {
+    match element {
+        Element::Node { node } => { (0, node.id()) }
+        Element::Way { way } => { (1, way.id()) }
+        Element::Relation { relation } => { (2, relation.id()) }
+        Element::Sentinel => { (3, 0) }
+    }
+}
+
+fn key_name(element: &Element) -> (&'static str, i64) {
+    match element {
+        Element::Node { node } => { ("node", node.id()) }
+        Element::Way { way } => { ("way", way.id()) }
+        Element::Relation { relation } => { ("relation", relation.id()) }
+        Element::Sentinel => { ("sentinel", 0) }
+    }
+}
+
+/// Field level differences between two versions of an object, as a JSON object with a
+/// `[from, to]` pair for each differing field, empty when the versions are equal
+pub(crate) fn differences(from: &Element, to: &Element) -> JsonValue {
+    let mut differences = JsonValue::new_object();
+    match (from, to) {
+        (Element::Node { node: from }, Element::Node { node: to }) => {
+            metadata_differences(
+                &mut differences,
+                (from.version(), from.changeset(), from.timestamp(), from.uid(), from.user()),
+                (to.version(), to.changeset(), to.timestamp(), to.uid(), to.user()),
+            );
+            let from_coordinate = (from.coordinate().lat7(), from.coordinate().lon7());
+            let to_coordinate = (to.coordinate().lat7(), to.coordinate().lon7());
+            if from_coordinate != to_coordinate {
+                differences["coordinate"] = array![
+                    array![from.coordinate().lat(), from.coordinate().lon()],
+                    array![to.coordinate().lat(), to.coordinate().lon()]
+                ];
+            }
+            tag_differences(&mut differences, from.tags(), to.tags());
+        }
+        (Element::Way { way: from }, Element::Way { way: to }) => {
+            metadata_differences(
+                &mut differences,
+                (from.version(), from.changeset(), from.timestamp(), from.uid(), from.user()),
+                (to.version(), to.changeset(), to.timestamp(), to.uid(), to.user()),
+            );
+            tag_differences(&mut differences, from.tags(), to.tags());
+            if from.refs() != to.refs() {
+                differences["refs"] = array![from.refs().clone(), to.refs().clone()];
+            }
+        }
+        (Element::Relation { relation: from }, Element::Relation { relation: to }) => {
+            metadata_differences(
+                &mut differences,
+                (from.version(), from.changeset(), from.timestamp(), from.uid(), from.user()),
+                (to.version(), to.changeset(), to.timestamp(), to.uid(), to.user()),
+            );
+            tag_differences(&mut differences, from.tags(), to.tags());
+            let from_members: Vec<JsonValue> = from.members().iter().map(member_json).collect();
+            let to_members: Vec<JsonValue> = to.members().iter().map(member_json).collect();
+            if from_members != to_members {
+                differences["members"] = array![from_members, to_members];
+            }
+        }
+        _ => {}
+    }
+    differences
+}
+
+fn metadata_differences(differences: &mut JsonValue, from: (i32, i64, i64, i32, &str), to: (i32, i64, i64, i32, &str)) {
+    if from.0 != to.0 {
+        differences["version"] = array![from.0, to.0];
+    }
+    if from.1 != to.1 {
+        differences["changeset"] = array![from.1, to.1];
+    }
+    if from.2 != to.2 {
+        differences["timestamp"] = array![format_timestamp(from.2), format_timestamp(to.2)];
+    }
+    if from.3 != to.3 {
+        differences["uid"] = array![from.3, to.3];
+    }
+    if from.4 != to.4 {
+        differences["user"] = array![from.4, to.4];
+    }
+}
+
+fn tag_differences(differences: &mut JsonValue, from: &[Tag], to: &[Tag]) {
+    let from: BTreeMap<&str, &str> = from.iter().map(|tag| (tag.k().as_str(), tag.v().as_str())).collect();
+    let to: BTreeMap<&str, &str> = to.iter().map(|tag| (tag.k().as_str(), tag.v().as_str())).collect();
+    let mut added = JsonValue::new_object();
+    let mut removed = JsonValue::new_object();
+    let mut changed = JsonValue::new_object();
+    for (k, v) in &from {
+        match to.get(k) {
+            None => {
+                removed[*k] = (*v).into();
+            }
+            Some(to_v) if to_v != v => {
+                changed[*k] = array![*v, *to_v];
+            }
+            Some(_) => {}
+        }
+    }
+    for (k, v) in &to {
+        if !from.contains_key(k) {
+            added[*k] = (*v).into();
+        }
+    }
+    if !(added.is_empty() && removed.is_empty() && changed.is_empty()) {
+        differences["tags"] = object! { added: added, removed: removed, changed: changed };
+    }
+}
+
+fn member_json(member: &Member) -> JsonValue {
+    let (member_type, member_data) = match member {
+        Member::Node { member } => { ("node", member) }
+        Member::Way { member } => { ("way", member) }
+        Member::Relation { member } => { ("relation", member) }
+    };
+    object! { type: member_type, ref: member_data.id(), role: member_data.role().as_str() }
+}
+
+fn format_timestamp(millis: i64) -> String {
+    DateTime::from_timestamp_millis(millis)
+        .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or(millis.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::element::Element;
+
+    use crate::diff::compare::{Change, Diff};
+    use crate::fixtures::{node, way};
+
+    #[test]
+    fn test_diff() -> Result<(), anyhow::Error> {
+        let from = vec![
+            node(1, 1, true, 1.0, 10.0, &[("name", "A"), ("amenity", "cafe")]),
+            node(2, 1, true, 2.0, 10.0, &[]),
+            node(3, 1, true, 3.0, 10.0, &[]),
+            Element::Sentinel,
+            way(10, 1, true, vec![1, 2], &[]),
+            Element::Sentinel,
+        ];
+        let to = vec![
+            node(1, 2, true, 1.5, 10.0, &[("name", "B"), ("shop", "bakery")]),
+            node(3, 1, true, 3.0, 10.0, &[]),
+            node(4, 1, true, 4.0, 10.0, &[]),
+            Element::Sentinel,
+            way(10, 1, true, vec![1, 3], &[]),
+            way(11, 1, true, vec![3, 4], &[]),
+            Element::Sentinel,
+        ];
+        let changes = Diff::new(from.into_iter(), to.into_iter()).collect::<Result<Vec<Change>, anyhow::Error>>()?;
+        let summary: Vec<String> = changes.iter()
+            .map(|change| format!("{} {} {}", change.to_json()["change"], change.to_json()["type"], change.to_json()["id"]))
+            .collect();
+        assert_eq!(summary, vec!["modified node 1", "removed node 2", "added node 4", "modified way 10", "added way 11"]);
+
+        let differences = changes[0].to_json()["differences"].clone();
+        assert_eq!(differences["version"].dump(), "[1,2]");
+        assert_eq!(differences["coordinate"].dump(), "[[1,10],[1.5,10]]");
+        assert_eq!(differences["tags"]["added"].dump(), r#"{"shop":"bakery"}"#);
+        assert_eq!(differences["tags"]["removed"].dump(), r#"{"amenity":"cafe"}"#);
+        assert_eq!(differences["tags"]["changed"].dump(), r#"{"name":["A","B"]}"#);
+        assert_eq!(changes[3].to_json()["differences"]["refs"].dump(), "[[1,2],[1,3]]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsorted_diff() {
+        let from = vec![node(2, 1, true, 2.0, 10.0, &[]), node(1, 1, true, 1.0, 10.0, &[])];
+        let result = Diff::new(from.into_iter(), vec![].into_iter()).collect::<Result<Vec<Change>, anyhow::Error>>();
+        assert!(result.is_err());
+    }
+}
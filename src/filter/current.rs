@@ -0,0 +1,94 @@
+use std::iter::Peekable;
+
+use osm_io::osm::model::element::Element;
+
+/// Iterator over the current elements of an element stream ordered by type, id and version
+///
+/// Only the latest version of each element is produced, and only if it is visible, that is
+/// historical versions and deleted elements are dropped. [Element::Sentinel] is passed through.
+pub(crate) struct CurrentElementIterator<I: Iterator<Item=Element>> {
+    elements: Peekable<I>,
+}
+
+impl<I: Iterator<Item=Element>> CurrentElementIterator<I> {
+    pub(crate) fn new(elements: I) -> CurrentElementIterator<I> {
+        CurrentElementIterator {
+            elements: elements.peekable(),
+        }
+    }
+}
+
+impl<I: Iterator<Item=Element>> Iterator for CurrentElementIterator<I> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let element = self.elements.next()?;
+            let superseded = match self.elements.peek() {
+                None => {
+                    false
+                }
+                Some(next_element) => {
+                    Element::same_type(&element, next_element) && id(&element) == id(next_element)
+                }
+            };
+            if !superseded && visible(&element) {
+                return Some(element);
+            }
+        }
+    }
+}
+
+fn id(element: &Element) -> Option<i64> {
+    match element {
+        Element::Node { node } => { Some(node.id()) }
+        Element::Way { way } => { Some(way.id()) }
+        Element::Relation { relation } => { Some(relation.id()) }
+        Element::Sentinel => { None }
+    }
+}
+
+fn visible(element: &Element) -> bool {
+    match element {
+        Element::Node { node } => { node.visible() }
+        Element::Way { way } => { way.visible() }
+        Element::Relation { relation } => { relation.visible() }
+        Element::Sentinel => { true }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::element::Element;
+
+    use crate::filter::current::CurrentElementIterator;
+    use crate::fixtures::{node, way};
+
+    #[test]
+    fn test_current_elements() {
+        let elements = vec![
+            node(1, 1, true, 0.0, 0.0, &[]),
+            node(1, 2, true, 0.0, 0.0, &[]),
+            node(2, 1, true, 0.0, 0.0, &[]),
+            node(2, 2, false, 0.0, 0.0, &[]),
+            node(3, 1, true, 0.0, 0.0, &[]),
+            Element::Sentinel,
+            way(3, 1, true, vec![1, 2], &[]),
+            way(4, 1, true, vec![1, 2], &[]),
+            way(4, 2, true, vec![1, 2], &[]),
+            Element::Sentinel,
+        ];
+        let current: Vec<Element> = CurrentElementIterator::new(elements.into_iter()).collect();
+        assert_eq!(
+            current,
+            vec![
+                node(1, 2, true, 0.0, 0.0, &[]),
+                node(3, 1, true, 0.0, 0.0, &[]),
+                Element::Sentinel,
+                way(3, 1, true, vec![1, 2], &[]),
+                way(4, 2, true, vec![1, 2], &[]),
+                Element::Sentinel,
+            ]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::mem;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::{Member, Relation};
+
+use crate::filter::id_set::IdSet;
+use crate::filter::region::Region;
+
+/// Strategy for selecting the elements of a regional extract
+///
+/// * simple - nodes in the region, ways with at least one node in the region and relations with at
+///   least one of these nodes or ways as a member. Ways crossing the region boundary are incomplete.
+/// * complete-ways - as simple, plus all nodes of the selected ways and all relations that
+///   have a selected relation as a member, recursively.
+/// * smart - as complete-ways, plus all member ways, and their nodes, of the selected
+///   multipolygon relations.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ExtractStrategy {
+    Simple,
+    CompleteWays,
+    Smart,
+}
+
+impl FromStr for ExtractStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "simple" => { Ok(ExtractStrategy::Simple) }
+            "complete-ways" => { Ok(ExtractStrategy::CompleteWays) }
+            "smart" => { Ok(ExtractStrategy::Smart) }
+            _ => { Err(anyhow!("Unknown extract strategy: {}", s)) }
+        }
+    }
+}
+
+/// Ids of the elements selected for a regional extract
+///
+/// The selection is calculated by passes over an element stream ordered by type, id and version,
+/// as produced by the apidb dump reader. Every version of a selected element is selected.
+pub(crate) struct Extract {
+    nodes: IdSet,
+    ways: IdSet,
+    relations: IdSet,
+}
+
+impl Extract {
+    /// Select the elements of a region. `elements` must produce a new stream on each call, it is
+    /// called once for simple and complete-ways strategies and at most twice for the smart strategy.
+    pub(crate) fn new<F, I>(region: &Region, strategy: ExtractStrategy, elements: F) -> Result<Extract, anyhow::Error>
+        where F: Fn() -> Result<I, anyhow::Error>, I: Iterator<Item=Element> {
+        let mut region_node_ids = Vec::new();
+        let mut region_nodes: Option<IdSet> = None;
+        let mut way_node_ids = Vec::new();
+        let mut way_ids = Vec::new();
+        let mut ways: Option<IdSet> = None;
+        let mut relation_ids = Vec::new();
+        // (member, parent) pairs of relations that are members of other relations
+        let mut parent_relations = Vec::new();
+        let mut multipolygon_ways: HashMap<i64, Vec<i64>> = HashMap::new();
+
+        for element in elements()? {
+            match element {
+                Element::Node { node } => {
+                    if region.contains(node.coordinate()) {
+                        region_node_ids.push(node.id());
+                    }
+                }
+                Element::Way { way } => {
+                    let region_nodes = region_nodes.get_or_insert_with(|| IdSet::from_ids(mem::take(&mut region_node_ids)));
+                    if way.refs().iter().any(|id| region_nodes.contains(*id)) {
+                        way_ids.push(way.id());
+                        if strategy != ExtractStrategy::Simple {
+                            way_node_ids.extend(way.refs());
+                        }
+                    }
+                }
+                Element::Relation { relation } => {
+                    let region_nodes = region_nodes.get_or_insert_with(|| IdSet::from_ids(mem::take(&mut region_node_ids)));
+                    let ways = ways.get_or_insert_with(|| IdSet::from_ids(mem::take(&mut way_ids)));
+                    let mut selected = false;
+                    for member in relation.members() {
+                        match member {
+                            Member::Node { member } => {
+                                selected |= region_nodes.contains(member.id());
+                            }
+                            Member::Way { member } => {
+                                selected |= ways.contains(member.id());
+                            }
+                            Member::Relation { member } => {
+                                parent_relations.push((member.id(), relation.id()));
+                            }
+                        }
+                    }
+                    if selected {
+                        relation_ids.push(relation.id());
+                    }
+                    if strategy == ExtractStrategy::Smart && is_multipolygon(&relation) {
+                        multipolygon_ways.entry(relation.id())
+                            .or_default()
+                            .extend(
+                                relation.members().iter().filter_map(|member| match member {
+                                    Member::Way { member } => { Some(member.id()) }
+                                    _ => { None }
+                                })
+                            );
+                    }
+                }
+                Element::Sentinel => {}
+            }
+        }
+
+        let mut nodes = region_nodes.unwrap_or_else(|| IdSet::from_ids(region_node_ids));
+        let mut ways = ways.unwrap_or_else(|| IdSet::from_ids(way_ids));
+        let mut relations = IdSet::from_ids(relation_ids);
+
+        if strategy != ExtractStrategy::Simple {
+            loop {
+                let parents: Vec<i64> = parent_relations.iter()
+                    .filter(|(member, parent)| relations.contains(*member) && !relations.contains(*parent))
+                    .map(|(_, parent)| *parent)
+                    .collect();
+                if parents.is_empty() {
+                    break;
+                }
+                relations.extend(parents);
+            }
+        }
+
+        if strategy == ExtractStrategy::Smart {
+            let extra_ways = IdSet::from_ids(
+                multipolygon_ways.iter()
+                    .filter(|(id, _)| relations.contains(**id))
+                    .flat_map(|(_, member_ways)| member_ways.iter().copied())
+                    .filter(|id| !ways.contains(*id))
+                    .collect()
+            );
+            if !extra_ways.is_empty() {
+                for element in elements()? {
+                    match element {
+                        Element::Way { way } if extra_ways.contains(way.id()) => {
+                            way_node_ids.extend(way.refs());
+                        }
+                        Element::Relation { .. } => {
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                ways.extend(
+                    multipolygon_ways.into_iter()
+                        .filter(|(id, _)| relations.contains(*id))
+                        .flat_map(|(_, member_ways)| member_ways)
+                );
+            }
+        }
+
+        nodes.extend(way_node_ids);
+
+        log::info!(
+            "Extract selected {} nodes, {} ways, {} relations",
+            nodes.len(),
+            ways.len(),
+            relations.len()
+        );
+
+        Ok(
+            Extract {
+                nodes,
+                ways,
+                relations,
+            }
+        )
+    }
+
+    /// Check if the element is selected. [Element::Sentinel] is always selected.
+    pub(crate) fn contains(&self, element: &Element) -> bool {
+        match element {
+            Element::Node { node } => { self.nodes.contains(node.id()) }
+            Element::Way { way } => { self.ways.contains(way.id()) }
+            Element::Relation { relation } => { self.relations.contains(relation.id()) }
+            Element::Sentinel => { true }
+        }
+    }
+}
+
+fn is_multipolygon(relation: &Relation) -> bool {
+    relation.tags().iter().any(|tag| tag.k() == "type" && tag.v() == "multipolygon")
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::bounding_box::BoundingBox;
+    use osm_io::osm::model::element::Element;
+    use osm_io::osm::model::relation::{Member, MemberData};
+
+    use crate::filter::extract::{Extract, ExtractStrategy};
+    use crate::filter::region::Region;
+    use crate::fixtures::{node, relation, way};
+
+    fn elements() -> Vec<Element> {
+        vec![
+            // nodes 1 and 2 are inside the region
+            node(1, 1, true, 0.5, 0.5, &[]),
+            node(2, 1, true, 0.6, 0.6, &[]),
+            node(3, 1, true, 5.0, 5.0, &[]),
+            node(4, 1, true, 6.0, 6.0, &[]),
+            node(5, 1, true, 7.0, 7.0, &[]),
+            Element::Sentinel,
+            way(10, 1, true, vec![1, 3], &[]),
+            way(11, 1, true, vec![4, 5], &[]),
+            Element::Sentinel,
+            relation(20, 1, true, vec![Member::Way { member: MemberData::new(10, "outer".to_string()) }, Member::Way { member: MemberData::new(11, "outer".to_string()) }], &[("type", "multipolygon")]),
+            relation(21, 1, true, vec![Member::Relation { member: MemberData::new(20, "".to_string()) }], &[]),
+            relation(22, 1, true, vec![Member::Node { member: MemberData::new(5, "".to_string()) }], &[]),
+            Element::Sentinel,
+        ]
+    }
+
+    fn select(strategy: ExtractStrategy) -> Result<Vec<Element>, anyhow::Error> {
+        let region = Region::BoundingBox {
+            bounding_box: BoundingBox::new(0.0, 0.0, 1.0, 1.0),
+        };
+        let extract = Extract::new(&region, strategy, || Ok(elements().into_iter()))?;
+        Ok(elements().into_iter().filter(|e| !e.is_sentinel() && extract.contains(e)).collect())
+    }
+
+    #[test]
+    fn test_simple_extract() -> Result<(), anyhow::Error> {
+        assert_eq!(select(ExtractStrategy::Simple)?, vec![node(1, 1, true, 0.5, 0.5, &[]), node(2, 1, true, 0.6, 0.6, &[]), way(10, 1, true, vec![1, 3], &[]), elements()[9].clone()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_complete_ways_extract() -> Result<(), anyhow::Error> {
+        let selected = select(ExtractStrategy::CompleteWays)?;
+        assert_eq!(selected.len(), 6);
+        assert!(selected.contains(&node(3, 1, true, 5.0, 5.0, &[])));
+        assert!(!selected.contains(&way(11, 1, true, vec![4, 5], &[])));
+        assert!(selected.contains(&elements()[10]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_smart_extract() -> Result<(), anyhow::Error> {
+        let selected = select(ExtractStrategy::Smart)?;
+        assert_eq!(selected.len(), 9);
+        assert!(selected.contains(&way(11, 1, true, vec![4, 5], &[])));
+        assert!(selected.contains(&node(5, 1, true, 7.0, 7.0, &[])));
+        assert!(!selected.contains(&elements()[11]));
+        Ok(())
+    }
+}
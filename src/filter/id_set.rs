@@ -0,0 +1,51 @@
+/// Set of element ids backed by a sorted vector
+///
+/// Uses half the memory of a hash set, which matters when selecting the nodes of a large region.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IdSet {
+    ids: Vec<i64>,
+}
+
+impl IdSet {
+    pub(crate) fn from_ids(mut ids: Vec<i64>) -> IdSet {
+        ids.sort_unstable();
+        ids.dedup();
+        IdSet {
+            ids,
+        }
+    }
+
+    pub(crate) fn extend(&mut self, ids: impl IntoIterator<Item=i64>) {
+        self.ids.extend(ids);
+        self.ids.sort_unstable();
+        self.ids.dedup();
+    }
+
+    pub(crate) fn contains(&self, id: i64) -> bool {
+        self.ids.binary_search(&id).is_ok()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::id_set::IdSet;
+
+    #[test]
+    fn test_id_set() {
+        let mut id_set = IdSet::from_ids(vec![5, 3, 3, 1]);
+        assert_eq!(id_set.len(), 3);
+        assert!(id_set.contains(3));
+        assert!(!id_set.contains(4));
+        id_set.extend(vec![4, 5]);
+        assert_eq!(id_set.len(), 4);
+        assert!(id_set.contains(4));
+    }
+}
@@ -0,0 +1,7 @@
+pub(crate) mod current;
+pub(crate) mod extract;
+pub(crate) mod id_set;
+pub(crate) mod region;
+pub(crate) mod selection;
+pub(crate) mod sorted;
+pub(crate) mod tags;
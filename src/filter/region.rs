@@ -0,0 +1,182 @@
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use osm_io::osm::model::bounding_box::BoundingBox;
+use osm_io::osm::model::coordinate::Coordinate;
+
+/// Geographic region of an extract
+#[derive(Debug, Clone)]
+pub(crate) enum Region {
+    BoundingBox {
+        bounding_box: BoundingBox,
+    },
+    Polygon {
+        polygon: Polygon,
+    },
+}
+
+impl Region {
+    pub(crate) fn contains(&self, coordinate: &Coordinate) -> bool {
+        match self {
+            Region::BoundingBox { bounding_box } => {
+                bounding_box_contains(bounding_box, coordinate)
+            }
+            Region::Polygon { polygon } => {
+                polygon.contains(coordinate)
+            }
+        }
+    }
+
+    pub(crate) fn bounding_box(&self) -> BoundingBox {
+        match self {
+            Region::BoundingBox { bounding_box } => {
+                bounding_box.clone()
+            }
+            Region::Polygon { polygon } => {
+                polygon.bounding_box.clone()
+            }
+        }
+    }
+}
+
+/// Multipolygon read from a file in the osmosis polygon filter format
+///
+/// See https://wiki.openstreetmap.org/wiki/Osmosis/Polygon_Filter_File_Format. Rings which names
+/// start with '!' are holes.
+#[derive(Debug, Clone)]
+pub(crate) struct Polygon {
+    outer_rings: Vec<Vec<(f64, f64)>>,
+    inner_rings: Vec<Vec<(f64, f64)>>,
+    bounding_box: BoundingBox,
+}
+
+impl Polygon {
+    pub(crate) fn from_path(path: &PathBuf) -> Result<Polygon, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| anyhow!("path: {}", path.display()))?;
+        Polygon::from_str(&content)
+            .with_context(|| anyhow!("path: {}", path.display()))
+    }
+
+    pub(crate) fn contains(&self, coordinate: &Coordinate) -> bool {
+        bounding_box_contains(&self.bounding_box, coordinate)
+            && self.outer_rings.iter().any(|ring| ring_contains(ring, coordinate))
+            && !self.inner_rings.iter().any(|ring| ring_contains(ring, coordinate))
+    }
+}
+
+impl FromStr for Polygon {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+        // the first line is the name of the polygon
+        lines.next().ok_or(anyhow!("Empty polygon file"))?;
+        let mut outer_rings = Vec::new();
+        let mut inner_rings = Vec::new();
+        let mut bounding_box: Option<BoundingBox> = None;
+        loop {
+            let ring_name = lines.next().ok_or(anyhow!("Missing END of polygon file"))?;
+            if ring_name == "END" {
+                break;
+            }
+            let mut ring = Vec::new();
+            loop {
+                let line = lines.next().ok_or(anyhow!("Missing END of ring {}", ring_name))?;
+                if line == "END" {
+                    break;
+                }
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() != 2 {
+                    return Err(anyhow!("Malformed polygon point: {}", line));
+                }
+                let lon = f64::from_str(parts[0])?;
+                let lat = f64::from_str(parts[1])?;
+                let coordinate = Coordinate::new(lat, lon);
+                match bounding_box.as_mut() {
+                    None => {
+                        bounding_box = Some(BoundingBox::from_point(&coordinate));
+                    }
+                    Some(bounding_box) => {
+                        bounding_box.merge_point(&coordinate);
+                    }
+                }
+                ring.push((lon, lat));
+            }
+            if ring.len() < 3 {
+                return Err(anyhow!("Ring {} must have at least 3 points", ring_name));
+            }
+            if ring_name.starts_with('!') {
+                inner_rings.push(ring);
+            } else {
+                outer_rings.push(ring);
+            }
+        }
+        if outer_rings.is_empty() {
+            return Err(anyhow!("Polygon must have at least one outer ring"));
+        }
+        Ok(
+            Polygon {
+                outer_rings,
+                inner_rings,
+                bounding_box: bounding_box.unwrap(),
+            }
+        )
+    }
+}
+
+fn bounding_box_contains(bounding_box: &BoundingBox, coordinate: &Coordinate) -> bool {
+    coordinate.lon() >= bounding_box.left()
+        && coordinate.lon() <= bounding_box.right()
+        && coordinate.lat() >= bounding_box.bottom()
+        && coordinate.lat() <= bounding_box.top()
+}
+
+/// Ray casting point in polygon test
+fn ring_contains(ring: &[(f64, f64)], coordinate: &Coordinate) -> bool {
+    let (x, y) = (coordinate.lon(), coordinate.lat());
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use osm_io::osm::model::coordinate::Coordinate;
+
+    use crate::filter::region::Polygon;
+
+    const SQUARE_WITH_HOLE: &str = "square\n\
+        1\n   0.0  0.0\n   10.0  0.0\n   10.0  10.0\n   0.0  10.0\nEND\n\
+        !2\n   4.0  4.0\n   6.0  4.0\n   6.0  6.0\n   4.0  6.0\nEND\n\
+        END\n";
+
+    #[test]
+    fn test_polygon_contains() -> Result<(), anyhow::Error> {
+        let polygon = Polygon::from_str(SQUARE_WITH_HOLE)?;
+        assert!(polygon.contains(&Coordinate::new(1.0, 1.0)));
+        assert!(polygon.contains(&Coordinate::new(8.0, 5.0)));
+        assert!(!polygon.contains(&Coordinate::new(5.0, 5.0)));
+        assert!(!polygon.contains(&Coordinate::new(5.0, 11.0)));
+        assert!(!polygon.contains(&Coordinate::new(-1.0, 5.0)));
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_polygon_without_end() {
+        Polygon::from_str("square\n1\n0.0 0.0\n10.0 0.0\n10.0 10.0\nEND\n").unwrap();
+    }
+}
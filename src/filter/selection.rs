@@ -0,0 +1,243 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::Member;
+
+use crate::filter::id_set::IdSet;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ElementType {
+    Node,
+    Way,
+    Relation,
+}
+
+impl ElementType {
+    fn of(element: &Element) -> Option<(ElementType, i64)> {
+        match element {
+            Element::Node { node } => { Some((ElementType::Node, node.id())) }
+            Element::Way { way } => { Some((ElementType::Way, way.id())) }
+            Element::Relation { relation } => { Some((ElementType::Relation, relation.id())) }
+            Element::Sentinel => { None }
+        }
+    }
+}
+
+impl FromStr for ElementType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "node" => { Ok(ElementType::Node) }
+            "way" => { Ok(ElementType::Way) }
+            "relation" => { Ok(ElementType::Relation) }
+            _ => { Err(anyhow!("Unknown element type: {}", s)) }
+        }
+    }
+}
+
+/// Inclusive id range of one element type, `type:id` or `type:first-last`
+#[derive(Debug)]
+struct IdRange {
+    element_type: ElementType,
+    first: i64,
+    last: i64,
+}
+
+impl FromStr for IdRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid id range: {}, expected type:id or type:first-last", s);
+        let (element_type, range) = s.split_once(':').ok_or_else(invalid)?;
+        let element_type = ElementType::from_str(element_type)?;
+        // ids may be negative, so the range separator is searched after the first character
+        let (first, last) = match range.get(1..).and_then(|tail| tail.find('-')) {
+            None => {
+                let id = range.parse::<i64>().map_err(|_| invalid())?;
+                (id, id)
+            }
+            Some(i) => {
+                (
+                    range[..=i].parse::<i64>().map_err(|_| invalid())?,
+                    range[i + 2..].parse::<i64>().map_err(|_| invalid())?,
+                )
+            }
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        Ok(IdRange { element_type, first, last })
+    }
+}
+
+/// Ids of the elements referenced by the directly selected elements
+struct Referenced {
+    nodes: IdSet,
+    ways: IdSet,
+    relations: IdSet,
+}
+
+/// Selection of elements by type and id range
+///
+/// An element is selected if its type is selected and, when id ranges are given for its type, its
+/// id is in one of them. The selected types default to the types of the id ranges, or to all types
+/// when there are no id ranges. Optionally the nodes of the selected ways and the members of the
+/// selected relations, including the nodes of the member ways, are selected as well.
+pub(crate) struct Selection {
+    types: Vec<ElementType>,
+    ranges: Vec<IdRange>,
+    referenced: Option<Referenced>,
+}
+
+impl Selection {
+    /// Parse the types and id ranges. There is no selection if both are empty.
+    pub(crate) fn new(types: &[String], ids: &[String]) -> Result<Option<Selection>, anyhow::Error> {
+        if types.is_empty() && ids.is_empty() {
+            return Ok(None);
+        }
+        let ranges = ids.iter().map(|s| IdRange::from_str(s)).collect::<Result<Vec<_>, _>>()?;
+        let mut types = types.iter().map(|s| ElementType::from_str(s)).collect::<Result<Vec<_>, _>>()?;
+        if types.is_empty() {
+            types = ranges.iter().map(|range| range.element_type).collect();
+        }
+        Ok(
+            Some(
+                Selection {
+                    types,
+                    ranges,
+                    referenced: None,
+                }
+            )
+        )
+    }
+
+    fn matches(&self, element_type: ElementType, id: i64) -> bool {
+        let mut ranges = self.ranges.iter().filter(|range| range.element_type == element_type).peekable();
+        self.types.contains(&element_type)
+            && (ranges.peek().is_none() || ranges.any(|range| range.first <= id && id <= range.last))
+    }
+
+    /// Select the elements referenced by the selected ways and relations. `elements` must produce
+    /// a new stream ordered by type, id and version on each call, it is called at most twice.
+    pub(crate) fn select_referenced<F, I>(&mut self, elements: F) -> Result<(), anyhow::Error>
+        where F: Fn() -> Result<I, anyhow::Error>, I: Iterator<Item=Element> {
+        let mut node_ids = Vec::new();
+        let mut way_ids = Vec::new();
+        let mut relation_ids = Vec::new();
+        for element in elements()? {
+            match element {
+                Element::Way { way } if self.matches(ElementType::Way, way.id()) => {
+                    node_ids.extend(way.refs());
+                }
+                Element::Relation { relation } if self.matches(ElementType::Relation, relation.id()) => {
+                    for member in relation.members() {
+                        match member {
+                            Member::Node { member } => { node_ids.push(member.id()) }
+                            Member::Way { member } => { way_ids.push(member.id()) }
+                            Member::Relation { member } => { relation_ids.push(member.id()) }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let ways = IdSet::from_ids(way_ids);
+        if !ways.is_empty() {
+            for element in elements()? {
+                match element {
+                    Element::Way { way } if ways.contains(way.id()) => {
+                        node_ids.extend(way.refs());
+                    }
+                    Element::Relation { .. } => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let referenced = Referenced {
+            nodes: IdSet::from_ids(node_ids),
+            ways,
+            relations: IdSet::from_ids(relation_ids),
+        };
+        log::info!(
+            "Selection references {} nodes, {} ways, {} relations",
+            referenced.nodes.len(),
+            referenced.ways.len(),
+            referenced.relations.len()
+        );
+        self.referenced = Some(referenced);
+        Ok(())
+    }
+
+    /// Check if the element is selected. [Element::Sentinel] is always selected.
+    pub(crate) fn contains(&self, element: &Element) -> bool {
+        let Some((element_type, id)) = ElementType::of(element) else {
+            return true;
+        };
+        self.matches(element_type, id) || match &self.referenced {
+            None => { false }
+            Some(referenced) => {
+                match element_type {
+                    ElementType::Node => { referenced.nodes.contains(id) }
+                    ElementType::Way => { referenced.ways.contains(id) }
+                    ElementType::Relation => { referenced.relations.contains(id) }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::element::Element;
+    use osm_io::osm::model::relation::{Member, MemberData};
+
+    use crate::filter::selection::Selection;
+    use crate::fixtures::{node, relation, way};
+
+    fn elements() -> Vec<Element> {
+        vec![
+            node(1, 1, true, 0.0, 0.0, &[]),
+            node(2, 1, true, 0.0, 0.0, &[]),
+            node(3, 1, true, 0.0, 0.0, &[]),
+            node(4, 1, true, 0.0, 0.0, &[]),
+            Element::Sentinel,
+            way(10, 1, true, vec![1, 2], &[]),
+            way(11, 1, true, vec![3], &[]),
+            way(12, 1, true, vec![4], &[]),
+            Element::Sentinel,
+            relation(20, 1, true, vec![Member::Way { member: MemberData::new(11, "".to_string()) }], &[]),
+            relation(21, 1, true, vec![], &[]),
+            Element::Sentinel,
+        ]
+    }
+
+    fn select(types: &[&str], ids: &[&str], referenced: bool) -> Result<Vec<Element>, anyhow::Error> {
+        let types: Vec<String> = types.iter().map(|s| s.to_string()).collect();
+        let ids: Vec<String> = ids.iter().map(|s| s.to_string()).collect();
+        let mut selection = Selection::new(&types, &ids)?.unwrap();
+        if referenced {
+            selection.select_referenced(|| Ok(elements().into_iter()))?;
+        }
+        Ok(elements().into_iter().filter(|e| !e.is_sentinel() && selection.contains(e)).collect())
+    }
+
+    #[test]
+    fn test_selection() -> Result<(), anyhow::Error> {
+        assert_eq!(select(&["relation"], &[], false)?, vec![elements()[9].clone(), elements()[10].clone()]);
+        assert_eq!(select(&[], &["way:10-11"], false)?, vec![way(10, 1, true, vec![1, 2], &[]), way(11, 1, true, vec![3], &[])]);
+        assert_eq!(select(&["node"], &["way:12"], false)?.len(), 4);
+        assert_eq!(select(&[], &["way:10", "relation:20"], true)?, vec![node(1, 1, true, 0.0, 0.0, &[]), node(2, 1, true, 0.0, 0.0, &[]), node(3, 1, true, 0.0, 0.0, &[]), way(10, 1, true, vec![1, 2], &[]), way(11, 1, true, vec![3], &[]), elements()[9].clone()]);
+
+        assert!(Selection::new(&[], &[])?.is_none());
+        assert!(Selection::new(&[], &["way:-5--1".to_string()]).is_ok());
+        assert!(Selection::new(&[], &["way:5-1".to_string()]).is_err());
+        assert!(Selection::new(&[], &["area:1".to_string()]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+
+/// Iterator that verifies that elements are ordered by type, id and version
+///
+/// The apidb dump writer derives the current tables from consecutive versions of the same element,
+/// so unsorted input, or input with the negative ids of elements that were never uploaded, would
+/// silently produce a corrupt dump. An error is produced instead of such elements.
+pub(crate) struct SortedElementIterator<I: Iterator<Item=Result<Element, anyhow::Error>>> {
+    elements: I,
+    last_key: Option<(u8, i64, i32)>,
+}
+
+impl<I: Iterator<Item=Result<Element, anyhow::Error>>> SortedElementIterator<I> {
+    pub(crate) fn new(elements: I) -> SortedElementIterator<I> {
+        SortedElementIterator {
+            elements,
+            last_key: None,
+        }
+    }
+
+    fn check(&mut self, element: &Element) -> Result<(), anyhow::Error> {
+        let key = match element {
+            Element::Node { node } => { (0, node.id(), node.version()) }
+            Element::Way { way } => { (1, way.id(), way.version()) }
+            Element::Relation { relation } => { (2, relation.id(), relation.version()) }
+            Element::Sentinel => { return Ok(()); }
+        };
+        let (_, id, version) = key;
+        if id <= 0 {
+            return Err(anyhow!("{} {} has a non positive id, only elements uploaded to the OSM API can be imported", type_name(key.0), id));
+        }
+        if let Some(last_key) = self.last_key {
+            if key <= last_key {
+                return Err(
+                    anyhow!(
+                        "Input is not sorted by type, id and version: {} {} version {} after {} {} version {}. Sort the input, for example with osmium sort",
+                        type_name(key.0), id, version, type_name(last_key.0), last_key.1, last_key.2
+                    )
+                );
+            }
+        }
+        self.last_key = Some(key);
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item=Result<Element, anyhow::Error>>> Iterator for SortedElementIterator<I> {
+    type Item = Result<Element, anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.elements.next()? {
+            Ok(element) => {
+                match self.check(&element) {
+                    Ok(_) => { Some(Ok(element)) }
+                    Err(e) => { Some(Err(e)) }
+                }
+            }
+            Err(e) => {
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn type_name(type_rank: u8) -> &'static str {
+    match type_rank {
+        0 => { "Node" }
+        1 => { "Way" }
+        _ => { "Relation" }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::element::Element;
+
+    use crate::filter::sorted::SortedElementIterator;
+    use crate::fixtures::{node, way};
+
+    #[test]
+    fn test_sorted_elements() {
+        let elements = vec![Ok(node(1, 1, true, 0.0, 0.0, &[])), Ok(node(1, 2, true, 0.0, 0.0, &[])), Ok(node(2, 1, true, 0.0, 0.0, &[])), Ok(way(1, 1, true, vec![1, 2], &[]))];
+        assert!(SortedElementIterator::new(elements.into_iter()).all(|e| e.is_ok()));
+    }
+
+    #[test]
+    fn test_unsorted_elements() {
+        let elements = vec![Ok(node(2, 1, true, 0.0, 0.0, &[])), Ok(way(1, 1, true, vec![1, 2], &[])), Ok(node(1, 1, true, 0.0, 0.0, &[]))];
+        let results: Vec<Result<Element, anyhow::Error>> = SortedElementIterator::new(elements.into_iter()).collect();
+        assert!(results[1].is_ok());
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_negative_id() {
+        let elements = vec![Ok(node(-1, 1, true, 0.0, 0.0, &[]))];
+        assert!(SortedElementIterator::new(elements.into_iter()).all(|e| e.is_err()));
+    }
+}
@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::node::Node;
+use osm_io::osm::model::relation::Relation;
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::model::way::Way;
+use regex::Regex;
+
+use crate::output::writer::ElementWriter;
+
+/// Tag filter expression, `key`, `key=value` or `~regex` matching the key
+#[derive(Debug)]
+enum TagExpression {
+    Key(String),
+    KeyValue(String, String),
+    KeyRegex(Regex),
+}
+
+impl TagExpression {
+    fn matches(&self, tag: &Tag) -> bool {
+        match self {
+            TagExpression::Key(k) => { tag.k() == k }
+            TagExpression::KeyValue(k, v) => { tag.k() == k && tag.v() == v }
+            TagExpression::KeyRegex(regex) => { regex.is_match(tag.k()) }
+        }
+    }
+}
+
+impl FromStr for TagExpression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(regex) = s.strip_prefix('~') {
+            Ok(TagExpression::KeyRegex(Regex::new(regex).map_err(|e| anyhow!("Invalid tag expression: {}, {}", s, e))?))
+        } else if let Some((k, v)) = s.split_once('=') {
+            Ok(TagExpression::KeyValue(k.to_string(), v.to_string()))
+        } else if !s.is_empty() {
+            Ok(TagExpression::Key(s.to_string()))
+        } else {
+            Err(anyhow!("Empty tag expression"))
+        }
+    }
+}
+
+/// Filter of the tags of nodes, ways and relations
+///
+/// When keep expressions are given only the tags matching one of them are kept. Tags matching one
+/// of the drop expressions are then dropped.
+#[derive(Debug)]
+pub(crate) struct TagFilter {
+    keep: Vec<TagExpression>,
+    drop: Vec<TagExpression>,
+}
+
+impl TagFilter {
+    /// Parse the keep and drop expressions. There is no filter if both are empty.
+    pub(crate) fn new(keep: &[String], drop: &[String]) -> Result<Option<TagFilter>, anyhow::Error> {
+        if keep.is_empty() && drop.is_empty() {
+            return Ok(None);
+        }
+        Ok(
+            Some(
+                TagFilter {
+                    keep: keep.iter().map(|s| TagExpression::from_str(s)).collect::<Result<_, _>>()?,
+                    drop: drop.iter().map(|s| TagExpression::from_str(s)).collect::<Result<_, _>>()?,
+                }
+            )
+        )
+    }
+
+    fn retain(&self, tag: &Tag) -> bool {
+        (self.keep.is_empty() || self.keep.iter().any(|e| e.matches(tag)))
+            && !self.drop.iter().any(|e| e.matches(tag))
+    }
+
+    fn filter_tags(&self, tags: Vec<Tag>) -> Vec<Tag> {
+        tags.into_iter().filter(|tag| self.retain(tag)).collect()
+    }
+
+    /// Apply the filter to the tags of the element. Elements without filtered tags are returned
+    /// unchanged.
+    pub(crate) fn apply(&self, element: Element) -> Element {
+        match element {
+            Element::Node { mut node } if !node.tags().iter().all(|tag| self.retain(tag)) => {
+                let tags = self.filter_tags(node.take_tags());
+                let user = node.take_user();
+                Element::Node {
+                    node: Node::new(node.id(), node.version(), node.coordinate().clone(), node.timestamp(), node.changeset(), node.uid(), user, node.visible(), tags)
+                }
+            }
+            Element::Way { mut way } if !way.tags().iter().all(|tag| self.retain(tag)) => {
+                let tags = self.filter_tags(way.take_tags());
+                let user = way.take_user();
+                let refs = way.refs().clone();
+                Element::Way {
+                    way: Way::new(way.id(), way.version(), way.timestamp(), way.changeset(), way.uid(), user, way.visible(), refs, tags)
+                }
+            }
+            Element::Relation { mut relation } if !relation.tags().iter().all(|tag| self.retain(tag)) => {
+                let tags = self.filter_tags(relation.take_tags());
+                let user = relation.take_user();
+                let members = relation.members().clone();
+                Element::Relation {
+                    relation: Relation::new(relation.id(), relation.version(), relation.timestamp(), relation.changeset(), relation.uid(), user, relation.visible(), members, tags)
+                }
+            }
+            element => {
+                element
+            }
+        }
+    }
+}
+
+/// Writer that applies a [TagFilter] to the elements before writing them
+pub(crate) struct TagFilterWriter<'a> {
+    filter: &'a TagFilter,
+    writer: &'a mut dyn ElementWriter,
+}
+
+impl<'a> TagFilterWriter<'a> {
+    pub(crate) fn new(filter: &'a TagFilter, writer: &'a mut dyn ElementWriter) -> TagFilterWriter<'a> {
+        TagFilterWriter { filter, writer }
+    }
+}
+
+impl ElementWriter for TagFilterWriter<'_> {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.write_header()
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        self.writer.write_element(self.filter.apply(element))
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::element::Element;
+    use osm_io::osm::model::tag::Tag;
+    use osm_io::osm::model::way::Way;
+
+    use crate::filter::tags::TagFilter;
+
+    fn tags(element: &Element) -> Vec<String> {
+        match element {
+            Element::Way { way } => {
+                way.tags().iter().map(|tag| format!("{}={}", tag.k(), tag.v())).collect()
+            }
+            _ => {
+                Vec::new()
+            }
+        }
+    }
+
+    #[test]
+    fn test_tag_filter() -> Result<(), anyhow::Error> {
+        let way = Element::Way {
+            way: Way::new(
+                1, 2, 1686567602000, 3, 4, "user".to_string(), true, vec![1, 2],
+                ["highway=residential", "name=Main", "note:en=check", "fixme=name", "source:name=survey", "source=bing"]
+                    .iter()
+                    .map(|s| s.split_once('=').unwrap())
+                    .map(|(k, v)| Tag::new(k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+
+        let drop = TagFilter::new(&[], &["~^note:".to_string(), "fixme".to_string(), "~^source:".to_string()])?.unwrap();
+        assert_eq!(tags(&drop.apply(way.clone())), ["highway=residential", "name=Main", "source=bing"]);
+
+        let keep = TagFilter::new(&["highway".to_string(), "source=bing".to_string()], &["highway=residential".to_string()])?.unwrap();
+        assert_eq!(tags(&keep.apply(way.clone())), ["source=bing"]);
+
+        assert!(TagFilter::new(&[], &[])?.is_none());
+        assert!(TagFilter::new(&["~(".to_string()], &[]).is_err());
+        Ok(())
+    }
+}
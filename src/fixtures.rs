@@ -0,0 +1,33 @@
+//! Element builders of the unit tests. The elements are written in changeset 1 by user 1 `user`
+//! at timestamp 0.
+
+use osm_io::osm::model::coordinate::Coordinate;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::node::Node;
+use osm_io::osm::model::relation::{Member, Relation};
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::model::way::Way;
+
+pub(crate) fn node(id: i64, version: i32, visible: bool, lat: f64, lon: f64, tags: &[(&str, &str)]) -> Element {
+    Element::Node {
+        node: Node::new(id, version, Coordinate::new(lat, lon), 0, 1, 1, "user".to_string(), visible, to_tags(tags)),
+    }
+}
+
+pub(crate) fn way(id: i64, version: i32, visible: bool, refs: Vec<i64>, tags: &[(&str, &str)]) -> Element {
+    Element::Way {
+        way: Way::new(id, version, 0, 1, 1, "user".to_string(), visible, refs, to_tags(tags)),
+    }
+}
+
+pub(crate) fn relation(id: i64, version: i32, visible: bool, members: Vec<Member>, tags: &[(&str, &str)]) -> Element {
+    Element::Relation {
+        relation: Relation::new(id, version, 0, 1, 1, "user".to_string(), visible, members, to_tags(tags)),
+    }
+}
+
+fn to_tags(tags: &[(&str, &str)]) -> Vec<Tag> {
+    tags.iter()
+        .map(|(k, v)| Tag::new(k.to_string(), v.to_string()))
+        .collect()
+}
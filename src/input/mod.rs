@@ -0,0 +1,3 @@
+pub(crate) mod pbf;
+pub(crate) mod source;
+pub(crate) mod stream;
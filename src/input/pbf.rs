@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{Receiver, sync_channel, SyncSender};
+use std::vec::IntoIter;
+
+use anyhow::anyhow;
+use flate2::read::ZlibDecoder;
+use osm_io::osm::model::bounding_box::BoundingBox;
+use osm_io::osm::model::coordinate::Coordinate;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::node::Node;
+use osm_io::osm::model::relation::{Member, MemberData, Relation};
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::model::way::Way;
+use osm_io::osm::pbf::file_info::FileInfo;
+use osmpbf::{BlobDecode, BlobReader, RelMemberType};
+use prost::Message;
+
+use crate::pbf::proto::{Blob, BlobHeader, HeaderBlock};
+
+const SUPPORTED_FEATURES: [&str; 3] = ["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"];
+const MAX_BLOB_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// Streaming reader of OSM PBF data from any [Read], as stdin, that can be read only once
+///
+/// The OSMHeader blob is decoded when the reader is created so that the [FileInfo], including the
+/// osmosis replication fields, is available before the elements are read.
+pub(crate) struct StreamReader {
+    info: FileInfo,
+    blobs: BlobReader<Box<dyn Read + Send>>,
+}
+
+impl StreamReader {
+    pub(crate) fn new(mut input: Box<dyn Read + Send>) -> Result<StreamReader, anyhow::Error> {
+        let header = read_header(&mut input)?;
+        for feature in &header.required_features {
+            if !SUPPORTED_FEATURES.contains(&feature.as_str()) {
+                return Err(anyhow!("Unsupported required feature: {}", feature));
+            }
+        }
+        let info = FileInfo::new(
+            header.bbox.map(|bbox| {
+                BoundingBox::new(
+                    bbox.left as f64 * 1e-9,
+                    bbox.bottom as f64 * 1e-9,
+                    bbox.right as f64 * 1e-9,
+                    bbox.top as f64 * 1e-9,
+                )
+            }),
+            header.required_features,
+            header.optional_features,
+            header.writingprogram,
+            header.source,
+            header.osmosis_replication_timestamp,
+            header.osmosis_replication_sequence_number,
+            header.osmosis_replication_base_url,
+        );
+        Ok(
+            StreamReader {
+                info,
+                blobs: BlobReader::new(input),
+            }
+        )
+    }
+
+    pub(crate) fn info(&self) -> &FileInfo {
+        &self.info
+    }
+
+    /// Iterate the elements of the stream, decoding the OSMData blobs on `jobs` threads
+    pub(crate) fn elements(self, jobs: usize) -> ParallelElementIterator {
+        ParallelElementIterator::new(self.blobs, jobs)
+    }
+}
+
+/// Iterator over the elements of an OSM PBF file or stream, with the blobs decoded in parallel
+///
+/// The blobs are read in order on a reader thread and decoded on a pool of worker threads. The
+/// decoded blocks are reordered by their position in the input, so the elements are produced in
+/// the order of the input, as when decoded sequentially. Header blobs are skipped. The reader
+/// dispatches at most [READ_AHEAD_BLOCKS] blobs per job ahead of the next block in order, so the
+/// blocks decoded ahead of a slow one are bounded.
+pub(crate) struct ParallelElementIterator {
+    receiver: Receiver<(usize, Result<Vec<Element>, anyhow::Error>)>,
+    pending: BTreeMap<usize, Result<Vec<Element>, anyhow::Error>>,
+    next_index: usize,
+    read_ahead: Arc<OrderWindow>,
+    elements: IntoIter<Element>,
+    failed: bool,
+}
+
+/// Number of blobs per job that are read and decoded ahead of the next block in order
+const READ_AHEAD_BLOCKS: usize = 4;
+
+/// The index of the next block in order, shared with the thread that produces the blocks to hold
+/// back the blocks past a window ahead of it
+pub(crate) struct OrderWindow {
+    next_index: Mutex<usize>,
+    advanced: Condvar,
+}
+
+impl OrderWindow {
+    pub(crate) fn new() -> OrderWindow {
+        OrderWindow {
+            next_index: Mutex::new(0),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Wait until the block at `index` is within `window` blocks of the next block in order.
+    /// Returns false if the consumer of the blocks stopped
+    pub(crate) fn wait(&self, index: usize, window: usize) -> bool {
+        let Ok(mut next_index) = self.next_index.lock() else {
+            return false;
+        };
+        while index >= next_index.saturating_add(window) {
+            next_index = match self.advanced.wait(next_index) {
+                Ok(next_index) => { next_index }
+                Err(_) => { return false; }
+            };
+        }
+        *next_index != usize::MAX
+    }
+
+    /// Advance the window to the next block in order, [usize::MAX] when the consumer stopped
+    pub(crate) fn advance(&self, next_index: usize) {
+        if let Ok(mut current) = self.next_index.lock() {
+            *current = next_index;
+            self.advanced.notify_all();
+        }
+    }
+}
+
+impl ParallelElementIterator {
+    pub(crate) fn new<R: Read + Send + 'static>(blobs: BlobReader<R>, jobs: usize) -> ParallelElementIterator {
+        let jobs = jobs.max(1);
+        let (blob_sender, blob_receiver) = sync_channel::<(usize, osmpbf::Blob)>(jobs * 2);
+        let (sender, receiver) = sync_channel(jobs * 4);
+        let blob_receiver = Arc::new(Mutex::new(blob_receiver));
+        let read_ahead = Arc::new(OrderWindow::new());
+        let window = jobs * READ_AHEAD_BLOCKS;
+        for _ in 0..jobs {
+            let blob_receiver = blob_receiver.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || decode_worker(blob_receiver, sender));
+        }
+        let reader_read_ahead = read_ahead.clone();
+        std::thread::spawn(move || {
+            for (index, blob) in blobs.enumerate() {
+                if !reader_read_ahead.wait(index, window) {
+                    break;
+                }
+                let sent = match blob {
+                    Ok(blob) => {
+                        blob_sender.send((index, blob)).is_ok()
+                    }
+                    Err(e) => {
+                        // report the read error in the position of the unreadable blob
+                        let _ = sender.send((index, Err(e.into())));
+                        false
+                    }
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+        ParallelElementIterator {
+            receiver,
+            pending: BTreeMap::new(),
+            next_index: 0,
+            read_ahead,
+            elements: Vec::new().into_iter(),
+            failed: false,
+        }
+    }
+
+    fn next_block(&mut self) -> Option<Result<Vec<Element>, anyhow::Error>> {
+        loop {
+            if let Some(block) = self.pending.remove(&self.next_index) {
+                self.next_index += 1;
+                self.read_ahead.advance(self.next_index);
+                return Some(block);
+            }
+            match self.receiver.recv() {
+                Ok((index, block)) => {
+                    self.pending.insert(index, block);
+                }
+                Err(_) => {
+                    // all blobs were decoded, a gap can only be left by a blob that failed to read
+                    return self.pending.pop_first().map(|(_, block)| block);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ParallelElementIterator {
+    fn drop(&mut self) {
+        // release the reader thread if it waits for the window to advance
+        self.read_ahead.advance(usize::MAX);
+    }
+}
+
+impl Iterator for ParallelElementIterator {
+    type Item = Result<Element, anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(element) = self.elements.next() {
+                return Some(Ok(element));
+            }
+            if self.failed {
+                return None;
+            }
+            match self.next_block()? {
+                Ok(elements) => {
+                    self.elements = elements.into_iter();
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+fn decode_worker(
+    receiver: Arc<Mutex<Receiver<(usize, osmpbf::Blob)>>>,
+    sender: SyncSender<(usize, Result<Vec<Element>, anyhow::Error>)>,
+) {
+    loop {
+        let blob = match receiver.lock() {
+            Ok(receiver) => { receiver.recv() }
+            Err(_) => { return; }
+        };
+        match blob {
+            Ok((index, blob)) => {
+                if sender.send((index, blob_elements(&blob))).is_err() {
+                    // the iterator was dropped
+                    return;
+                }
+            }
+            Err(_) => {
+                // the reader finished
+                return;
+            }
+        }
+    }
+}
+
+fn blob_elements(blob: &osmpbf::Blob) -> Result<Vec<Element>, anyhow::Error> {
+    match blob.decode()? {
+        BlobDecode::OsmData(block) => {
+            block_elements(&block)
+        }
+        BlobDecode::OsmHeader(_) => {
+            Ok(Vec::new())
+        }
+        BlobDecode::Unknown(blob_type) => {
+            log::warn!("Skip unknown blob type: {}", blob_type);
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn block_elements(block: &osmpbf::PrimitiveBlock) -> Result<Vec<Element>, anyhow::Error> {
+    let mut elements = Vec::new();
+    for element in block.elements() {
+        elements.push(
+            match element {
+                osmpbf::Element::Node(node) => {
+                    let info = node.info();
+                    Element::Node {
+                        node: Node::new(
+                            node.id(),
+                            info.version().unwrap_or(0),
+                            Coordinate::new(node.nano_lat() as f64 / 1e9, node.nano_lon() as f64 / 1e9),
+                            info.milli_timestamp().unwrap_or(-1),
+                            info.changeset().unwrap_or(-1),
+                            info.uid().unwrap_or(-1),
+                            info.user().transpose()?.unwrap_or_default().to_string(),
+                            info.visible(),
+                            tags(node.tags()),
+                        )
+                    }
+                }
+                osmpbf::Element::DenseNode(node) => {
+                    let coordinate = Coordinate::new(node.nano_lat() as f64 / 1e9, node.nano_lon() as f64 / 1e9);
+                    let node = match node.info() {
+                        Some(info) => {
+                            Node::new(
+                                node.id(),
+                                info.version(),
+                                coordinate,
+                                info.milli_timestamp(),
+                                info.changeset(),
+                                info.uid(),
+                                info.user()?.to_string(),
+                                info.visible(),
+                                tags(node.tags()),
+                            )
+                        }
+                        None => {
+                            Node::new(node.id(), 0, coordinate, -1, -1, -1, String::new(), true, tags(node.tags()))
+                        }
+                    };
+                    Element::Node { node }
+                }
+                osmpbf::Element::Way(way) => {
+                    let info = way.info();
+                    Element::Way {
+                        way: Way::new(
+                            way.id(),
+                            info.version().unwrap_or(0),
+                            info.milli_timestamp().unwrap_or(-1),
+                            info.changeset().unwrap_or(-1),
+                            info.uid().unwrap_or(-1),
+                            info.user().transpose()?.unwrap_or_default().to_string(),
+                            info.visible(),
+                            way.refs().collect(),
+                            tags(way.tags()),
+                        )
+                    }
+                }
+                osmpbf::Element::Relation(relation) => {
+                    let info = relation.info();
+                    let mut members = Vec::new();
+                    for member in relation.members() {
+                        let member_data = MemberData::new(member.member_id, member.role()?.to_string());
+                        members.push(
+                            match member.member_type {
+                                RelMemberType::Node => { Member::Node { member: member_data } }
+                                RelMemberType::Way => { Member::Way { member: member_data } }
+                                RelMemberType::Relation => { Member::Relation { member: member_data } }
+                            }
+                        );
+                    }
+                    Element::Relation {
+                        relation: Relation::new(
+                            relation.id(),
+                            info.version().unwrap_or(0),
+                            info.milli_timestamp().unwrap_or(-1),
+                            info.changeset().unwrap_or(-1),
+                            info.uid().unwrap_or(-1),
+                            info.user().transpose()?.unwrap_or_default().to_string(),
+                            info.visible(),
+                            members,
+                            tags(relation.tags()),
+                        )
+                    }
+                }
+            }
+        );
+    }
+    Ok(elements)
+}
+
+fn tags<'a>(tags: impl Iterator<Item=(&'a str, &'a str)>) -> Vec<Tag> {
+    tags.map(|(k, v)| Tag::new(k.to_string(), v.to_string())).collect()
+}
+
+/// Read and decode the OSMHeader blob at the start of the stream, leaving the stream at the first
+/// OSMData blob
+fn read_header(input: &mut dyn Read) -> Result<HeaderBlock, anyhow::Error> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_BLOB_HEADER_SIZE {
+        return Err(anyhow!("Input stream is not an OSM PBF stream, blob header size: {}", len));
+    }
+    let blob_header = BlobHeader::decode(read_bytes(input, len)?.as_slice())?;
+    if blob_header.r#type != "OSMHeader" {
+        return Err(anyhow!("Input stream does not start with an OSMHeader blob but with: {}", blob_header.r#type));
+    }
+    let len = blob_header.datasize as usize;
+    if len > MAX_BLOB_SIZE {
+        return Err(anyhow!("OSMHeader blob size exceeds the maximum: {}", len));
+    }
+    let blob = Blob::decode(read_bytes(input, len)?.as_slice())?;
+    let data = match (blob.raw, blob.zlib_data) {
+        (Some(raw), _) => {
+            raw
+        }
+        (None, Some(zlib_data)) => {
+            let mut data = Vec::with_capacity(blob.raw_size.unwrap_or(0) as usize);
+            ZlibDecoder::new(zlib_data.as_slice()).read_to_end(&mut data)?;
+            data
+        }
+        (None, None) => {
+            return Err(anyhow!("Unsupported OSMHeader blob compression"));
+        }
+    };
+    Ok(HeaderBlock::decode(data.as_slice())?)
+}
+
+fn read_bytes(input: &mut dyn Read, len: usize) -> Result<Vec<u8>, anyhow::Error> {
+    let mut buf = vec![0u8; len];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::PathBuf;
+
+    use osm_io::osm::pbf::reader::Reader as PbfReader;
+    use osmpbf::BlobReader;
+
+    use crate::input::pbf::{ParallelElementIterator, StreamReader};
+
+    #[test]
+    fn test_stream_reader() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/niue-230612.osm.pbf");
+        let pbf_reader = PbfReader::new(&input_path)?;
+        let stream_reader = StreamReader::new(Box::new(BufReader::new(File::open(&input_path)?)))?;
+        assert_eq!(format!("{:?}", stream_reader.info()), format!("{:?}", pbf_reader.info()));
+        let elements: Vec<_> = stream_reader.elements(1).collect::<Result<_, anyhow::Error>>()?;
+        let expected: Vec<_> = pbf_reader.elements()?.collect();
+        assert_eq!(elements.len(), expected.len());
+        assert!(elements == expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_element_order() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/niue-230612.osm.pbf");
+        let expected: Vec<_> = PbfReader::new(&input_path)?.elements()?.collect();
+        for jobs in [1, 2, 8] {
+            let elements: Vec<_> = ParallelElementIterator::new(BlobReader::from_path(&input_path)?, jobs)
+                .collect::<Result<_, anyhow::Error>>()?;
+            assert_eq!(elements.len(), expected.len());
+            assert!(elements == expected);
+        }
+        Ok(())
+    }
+}
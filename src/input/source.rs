@@ -0,0 +1,20 @@
+use osm_io::osm::apidb_dump::read::reader::Reader;
+use osm_io::osm::model::element::Element;
+
+/// Source of the exported elements that can be read more than once, either an apidb dump or the
+/// database itself
+pub(crate) trait ElementSource {
+    /// Elements ordered by type, id and version, each type followed by [Element::Sentinel]
+    fn elements(&self) -> Result<Box<dyn Iterator<Item=Element>>, anyhow::Error>;
+
+    /// Error that ended one of the iterations, if any
+    fn check(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+impl ElementSource for Reader {
+    fn elements(&self) -> Result<Box<dyn Iterator<Item=Element>>, anyhow::Error> {
+        Ok(Box::new(Reader::elements(self)?))
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+
+/// Input or output path that stands for stdin or stdout
+pub(crate) const STREAM_PATH: &str = "-";
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+pub(crate) fn is_stream(path: &Path) -> bool {
+    path.as_os_str() == STREAM_PATH
+}
+
+/// Open the standard input for reading a stream in the input format, pbf or osm.
+///
+/// The stream is checked from its first bytes instead of the file magic used for regular files.
+/// gzip and bzip2 compressed input is detected and decompressed on the fly.
+pub(crate) fn open_stdin(input_format: &str) -> Result<Box<dyn BufRead + Send>, anyhow::Error> {
+    let mut stdin = BufReader::new(std::io::stdin());
+    let input: Box<dyn BufRead + Send> = {
+        let head = stdin.fill_buf()?;
+        if head.is_empty() {
+            return Err(anyhow!("Input stream is empty"));
+        } else if head.starts_with(GZIP_MAGIC) {
+            log::info!("Found gzip compressed input stream");
+            Box::new(BufReader::new(MultiGzDecoder::new(stdin)))
+        } else if head.starts_with(BZIP2_MAGIC) {
+            log::info!("Found bzip2 compressed input stream");
+            Box::new(BufReader::new(MultiBzDecoder::new(stdin)))
+        } else {
+            Box::new(stdin)
+        }
+    };
+    check_stream(input, input_format)
+}
+
+/// Check that an OSM XML file, decompressed as it is read for import by its .gz or .bz2 extension,
+/// starts as an XML document.
+pub(crate) fn check_xml_file(path: &Path) -> Result<(), anyhow::Error> {
+    let file = File::open(path)
+        .with_context(|| anyhow!("path: {}", path.display()))?;
+    let input: Box<dyn BufRead + Send> = match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        }
+        Some("bz2") => {
+            Box::new(BufReader::new(MultiBzDecoder::new(file)))
+        }
+        _ => {
+            Box::new(BufReader::new(file))
+        }
+    };
+    check_stream(input, "osm")
+        .with_context(|| anyhow!("path: {}", path.display()))?;
+    Ok(())
+}
+
+fn check_stream(mut input: Box<dyn BufRead + Send>, input_format: &str) -> Result<Box<dyn BufRead + Send>, anyhow::Error> {
+    let head = input.fill_buf()?;
+    match input_format {
+        "osm" => {
+            let head = head.strip_prefix("\u{feff}".as_bytes()).unwrap_or(head);
+            match head.iter().find(|b| !b.is_ascii_whitespace()) {
+                Some(b'<') => {
+                    log::info!("Found OSM XML input stream");
+                }
+                _ => {
+                    return Err(anyhow!("Input stream is not an OSM XML stream"));
+                }
+            }
+        }
+        _ => {
+            // the OSM PBF stream starts with the 4 byte length of the OSMHeader blob header, which
+            // is checked when the header is decoded
+            if head.len() >= 4 && head[0] == 0 && head[1] == 0 {
+                log::info!("Found OSM PBF input stream");
+            } else {
+                return Err(anyhow!("Input stream is not an OSM PBF stream"));
+            }
+        }
+    }
+    Ok(input)
+}
@@ -1,92 +1,350 @@
 extern crate core;
 
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use benchmark_rs::stopwatch::StopWatch;
 use chrono::{DateTime, SecondsFormat, Utc};
 use filemagic::magic;
 use num_format::{Locale, ToFormattedString};
 use osm_io::osm::apidb_dump::read::reader::Reader;
-use osm_io::osm::apidb_dump::write::writer::Writer as ApiDbDumpWriter;
 use osm_io::osm::model::bounding_box::BoundingBox;
 use osm_io::osm::model::element::Element;
-use osm_io::osm::pbf::compression_type::CompressionType;
 use osm_io::osm::pbf::file_info::FileInfo;
 use osm_io::osm::pbf::reader::Reader as PbfReader;
-use osm_io::osm::pbf::writer::Writer;
+use osmpbf::BlobReader;
 
-use crate::db::pg::count_objects;
+use crate::checkpoint::manifest::Manifest;
+use crate::connection::Database;
+use crate::db::pg::{count_objects, PUBLIC_SCHEMA};
+use crate::diff::compare::{Change, ChangeCounts, Diff};
+use crate::db::snapshot::SnapshotReader;
+use crate::filter::current::CurrentElementIterator;
+use crate::filter::extract::{Extract, ExtractStrategy};
+use crate::filter::region::{Polygon, Region};
+use crate::filter::sorted::SortedElementIterator;
+use crate::filter::selection::Selection;
+use crate::filter::tags::{TagFilter, TagFilterWriter};
+use crate::input::pbf::{ParallelElementIterator, StreamReader};
+use crate::input::source::ElementSource;
+use crate::input::stream::{check_xml_file, is_stream, open_stdin, STREAM_PATH};
+use crate::options::{FilterOptions, InputOptions, LoadOptions, OutputOptions, ReadOptions, TagFilterOptions, WorkPaths};
+use crate::db::copy::CopyLoader;
+use crate::db::dump::DumpWriter;
+use crate::output::compression::CompressedFile;
+use crate::output::pbf::Compression;
+use crate::output::writer::{create_writer, ElementWriter};
+use crate::progress::{progress_format, Phase, PhaseProgress, ProgressFormat};
+use crate::replication::state::State;
+use crate::verify::stats::Stats;
+use crate::xml::action::Action;
+use crate::xml::change_writer::ChangeWriter;
 
+pub(crate) mod checkpoint;
+pub mod connection;
 pub(crate) mod db;
+pub(crate) mod diff;
+pub(crate) mod filter;
+#[cfg(test)]
+pub(crate) mod fixtures;
+pub(crate) mod input;
+pub mod metrics;
+pub(crate) mod opl;
+pub mod options;
+pub(crate) mod output;
+pub(crate) mod pbf;
+pub mod progress;
+pub(crate) mod replication;
+pub mod tls;
+pub(crate) mod verify;
+pub(crate) mod xml;
+
+/// Manifest step of the apidb dump generation
+const DUMP_STEP: &str = "dump";
+/// Number of elements written to the apidb dump between progress checkpoints
+const CHECKPOINT_INTERVAL: u64 = 1_000_000;
+/// Number of elements processed between checks whether the metrics are due
+const METRICS_INTERVAL: usize = 100_000;
 
 pub fn import(
-    input_path: PathBuf,
-    _input_format: String,
-    output_path: PathBuf,
+    input: InputOptions,
+    load: LoadOptions,
+    tags: TagFilterOptions,
     jobs: i16,
-    host: String,
-    port: String,
-    database: String,
-    user: String,
-    password: Option<String>,
-    var_lib_path: &PathBuf,
-    var_log_path: &PathBuf,
+    database: Database,
+    work_paths: &WorkPaths,
     verbose: bool,
 ) -> Result<(), anyhow::Error> {
     let mut stopwatch = StopWatch::new();
     stopwatch.start();
 
-    if verbose {
-        log::info!("Verbose flag set, checking file parameters for {}", input_path.display());
-        check_file(&input_path)?;
+    if is_stream(&input.path) {
+        log::info!("Reading {} input from stdin, the stream is checked when opened", input.format);
+    } else if verbose {
+        log::info!("Verbose flag set, checking file parameters for {}", input.path.display());
+        check_file(&input.path, &input.format)?;
+    }
+
+    let tag_filter = TagFilter::new(&tags.keep_tags, &tags.drop_tags)?;
+    if let Some(tag_filter) = &tag_filter {
+        log::info!("Filter tags: {:?}", tag_filter);
+    }
+    if load.load != "copy" && database.schema != PUBLIC_SCHEMA {
+        return Err(anyhow::anyhow!("The apidb dump is restored into the {} schema, load with copy to import into schema: {}", PUBLIC_SCHEMA, database.schema));
+    }
+
+    let replication_state = match (load.load.as_str(), &load.output_path) {
+        ("copy", _) => {
+            if load.resume {
+                return Err(anyhow::anyhow!("Resume is supported only for loading with pg_restore"));
+            }
+            log::info!("Start direct load into OSM DB using COPY, jobs: {}", jobs);
+            let mut writer = CopyLoader::new(jobs, &database)?;
+            let replication_state = write_elements(&input, None, &mut writer, tag_filter.as_ref(), jobs, &mut stopwatch, verbose)?;
+            writer.close()?;
+            log::info!("Finish direct load into OSM DB, time (hours): {}", stopwatch);
+            replication_state
+        }
+        (_, Some(output_path)) => {
+            let mut manifest = Manifest::open(output_path, &input.path, load.resume)?;
+            if manifest.is_complete(DUMP_STEP) {
+                log::info!("Skip completed apidb dump generation, elements written: {}", manifest.elements_written());
+            } else {
+                log::info!("Start apidb dump generation");
+                manifest.run(DUMP_STEP, |manifest| {
+                    let replication_state = {
+                        let mut writer = DumpWriter::new(output_path, Some(&mut *manifest), CHECKPOINT_INTERVAL)?;
+                        let replication_state = write_elements(&input, Some(output_path), &mut writer, tag_filter.as_ref(), jobs, &mut stopwatch, verbose)?;
+                        writer.close()?;
+                        replication_state
+                    };
+                    manifest.set_replication_state(replication_state)
+                })?;
+                log::info!("Finish apidb dump generation, time (hours): {}", stopwatch);
+            }
+
+            stopwatch.reset();
+            stopwatch.start();
+            log::info!("Start load into OSM DB");
+            let progress = PhaseProgress::start(Phase::Restore);
+            {
+                let _ticker = progress.ticker(Some(output_path.as_path()));
+                db::pg::restore(jobs, &database.connection, output_path, work_paths, &mut manifest)?;
+            }
+            progress.finish(None, None);
+            log::info!("Finish load into OSM DB, time (hours): {}", stopwatch);
+            manifest.replication_state().cloned()
+        }
+        (_, None) => {
+            return Err(anyhow::anyhow!("Output directory is required for loading with pg_restore"));
+        }
+    };
+
+    match replication_state {
+        Some(state) => {
+            db::replication::save_state(&database, &state)?;
+        }
+        None => {
+            log::info!("No osmosis replication sequence number and timestamp in input, replication state not saved");
+        }
+    }
+    Ok(())
+}
+
+/// Write the elements of the input file, or of stdin when the input path is `-`, to the apidb dump
+/// or database, returning the replication state from the file header, if present. PBF blobs are
+/// decoded on `jobs` threads. The tag filter, if given, is applied before the elements are written.
+fn write_elements(
+    input: &InputOptions,
+    output_path: Option<&PathBuf>,
+    writer: &mut dyn ElementWriter,
+    tag_filter: Option<&TagFilter>,
+    jobs: i16,
+    stopwatch: &mut StopWatch,
+    verbose: bool,
+) -> Result<Option<State>, anyhow::Error> {
+    let mut tag_filter_writer;
+    let writer: &mut dyn ElementWriter = match tag_filter {
+        Some(tag_filter) => {
+            tag_filter_writer = TagFilterWriter::new(tag_filter, writer);
+            &mut tag_filter_writer
+        }
+        None => {
+            writer
+        }
+    };
+    match (input.format.as_str(), is_stream(&input.path)) {
+        ("osm", _) => {
+            write_xml_elements(&input.path, output_path, writer, stopwatch, verbose)?;
+            Ok(None)
+        }
+        (_, false) => {
+            write_pbf_elements(&input.path, output_path, writer, jobs, stopwatch, verbose)
+        }
+        (_, true) => {
+            write_pbf_stream_elements(output_path, writer, jobs, stopwatch, verbose)
+        }
     }
+}
 
-    log::info!("Start apidb dump generation");
-    let pbf_reader = PbfReader::new(&input_path)?;
-    let mut objects = 0 as i64;
+/// Write the elements of an OSM PBF file to the apidb dump or database, returning the replication
+/// state from the file header, if present
+fn write_pbf_elements(
+    input_path: &PathBuf,
+    output_path: Option<&PathBuf>,
+    writer: &mut dyn ElementWriter,
+    jobs: i16,
+    stopwatch: &mut StopWatch,
+    verbose: bool,
+) -> Result<Option<State>, anyhow::Error> {
+    let pbf_reader = PbfReader::new(input_path)?;
+    let mut objects = 0i64;
     if verbose {
         log::info!("Verbose flag set, counting objects");
+        let counting = PhaseProgress::start(Phase::Counting);
         let (nodes, ways, relations) = pbf_reader.count_objects()?;
+        counting.finish_counting(nodes, ways, relations);
         log::info!("Finished counting objects, time: {}", stopwatch);
         stopwatch.reset();
         stopwatch.start();
         objects = nodes + ways + relations;
-        print_verbose_info(&input_path, &pbf_reader, objects, nodes, ways, relations)
+        print_verbose_info(input_path, pbf_reader.info(), objects, nodes, ways, relations)
     }
-    let mut apidb_dump_writer = ApiDbDumpWriter::new(output_path.clone(), 0)?;
-    for (i, element) in pbf_reader.elements()?.enumerate() {
-        if verbose && i % 10000000 == 0 && i != 0 {
-            print_progress(&output_path, &stopwatch, objects, i)?;
+    log::info!("Decode PBF blobs, jobs: {}", jobs);
+    let elements = ParallelElementIterator::new(BlobReader::from_path(input_path)?, jobs as usize);
+    write_element_stream(elements, output_path, writer, stopwatch, verbose, objects)?;
+    replication_state(pbf_reader.info())
+}
+
+/// Write the elements of an OSM PBF stream from stdin to the apidb dump or database, returning the
+/// replication state from the stream header, if present. The stream can be read only once, so the
+/// objects are not counted in advance and the progress is reported without a percentage.
+fn write_pbf_stream_elements(
+    output_path: Option<&PathBuf>,
+    writer: &mut dyn ElementWriter,
+    jobs: i16,
+    stopwatch: &mut StopWatch,
+    verbose: bool,
+) -> Result<Option<State>, anyhow::Error> {
+    let stream_reader = StreamReader::new(open_stdin("pbf")?)?;
+    let state = replication_state(stream_reader.info())?;
+    if verbose {
+        print_verbose_info(&PathBuf::from(STREAM_PATH), stream_reader.info(), 0, 0, 0, 0)
+    }
+    write_element_stream(stream_reader.elements(jobs as usize), output_path, writer, stopwatch, verbose, 0)?;
+    Ok(state)
+}
+
+fn replication_state(info: &FileInfo) -> Result<Option<State>, anyhow::Error> {
+    match (info.osmosis_replication_sequence_number(), info.osmosis_replication_timestamp()) {
+        (Some(sequence_number), Some(timestamp)) => {
+            let timestamp = DateTime::from_timestamp(*timestamp, 0)
+                .ok_or(anyhow::anyhow!("Invalid osmosis replication timestamp: {}", timestamp))?;
+            Ok(Some(State::new(*sequence_number, timestamp)))
+        }
+        _ => {
+            Ok(None)
         }
-        apidb_dump_writer.write_element(element)?;
     }
-    apidb_dump_writer.close()?;
-    print_progress(&output_path, &stopwatch, objects, objects as usize)?;
+}
 
-    log::info!("Finish apidb dump generation, time (hours): {}", stopwatch);
+/// Write the elements of an OSM XML file, or of an OSM XML stream from stdin, to the apidb dump or
+/// database. The elements must be sorted by type, id and version.
+fn write_xml_elements(
+    input_path: &PathBuf,
+    output_path: Option<&PathBuf>,
+    writer: &mut dyn ElementWriter,
+    stopwatch: &mut StopWatch,
+    verbose: bool,
+) -> Result<(), anyhow::Error> {
+    let mut objects = 0i64;
+    if verbose && !is_stream(input_path) {
+        log::info!("Verbose flag set, counting objects");
+        let counting = PhaseProgress::start(Phase::Counting);
+        let (mut nodes, mut ways, mut relations) = (0i64, 0i64, 0i64);
+        for result in xml::reader::Reader::from_path(input_path)? {
+            match result?.1 {
+                Element::Node { .. } => { nodes += 1 }
+                Element::Way { .. } => { ways += 1 }
+                Element::Relation { .. } => { relations += 1 }
+                Element::Sentinel => {}
+            }
+        }
+        counting.finish_counting(nodes, ways, relations);
+        log::info!("Finished counting objects, time: {}", stopwatch);
+        stopwatch.reset();
+        stopwatch.start();
+        objects = nodes + ways + relations;
+        log::info!("Processing: {}", input_path.display());
+        log::info!("Nodes: {}", nodes.to_formatted_string(&Locale::en));
+        log::info!("Ways: {}", ways.to_formatted_string(&Locale::en));
+        log::info!("Relations: {}", relations.to_formatted_string(&Locale::en));
+        log::info!("Total OSM objects: {}", objects.to_formatted_string(&Locale::en));
+    }
+    let reader = if is_stream(input_path) {
+        xml::reader::Reader::new(open_stdin("osm")?)
+    } else {
+        xml::reader::Reader::from_path(input_path)?
+    };
+    let elements = SortedElementIterator::new(
+        reader.map(|result| result.map(|(_, element)| element))
+    );
+    write_element_stream(elements, output_path, writer, stopwatch, verbose, objects)
+}
 
-    stopwatch.reset();
-    stopwatch.start();
-    log::info!("Start load into OSM DB");
-    db::pg::restore(jobs, host, port, database, user, password, &output_path, var_lib_path, var_log_path)?;
-    log::info!("Finish load into OSM DB, time (hours): {}", stopwatch);
+/// Write the elements to the apidb dump or database, reporting the progress against the number of
+/// objects counted in advance, if any.
+fn write_element_stream(
+    elements: impl Iterator<Item=Result<Element, anyhow::Error>>,
+    output_path: Option<&PathBuf>,
+    writer: &mut dyn ElementWriter,
+    stopwatch: &mut StopWatch,
+    verbose: bool,
+    objects: i64,
+) -> Result<(), anyhow::Error> {
+    let progress = PhaseProgress::start(if output_path.is_some() { Phase::DumpGeneration } else { Phase::CopyLoad });
+    let mut elements_written = 0;
+    for (i, element) in elements.enumerate() {
+        if verbose && i % 10000000 == 0 && i != 0 {
+            print_progress(&progress, output_path, stopwatch, objects, i)?;
+        }
+        let element = element?;
+        metrics::count_element(&element);
+        writer.write_element(element)?;
+        if i % METRICS_INTERVAL == 0 {
+            metrics::update(output_path, false);
+        }
+        elements_written = i + 1;
+    }
+    print_progress(&progress, output_path, stopwatch, objects, elements_written)?;
+    progress.finish(Some(elements_written as u64), output_path);
     Ok(())
 }
 
-fn check_file(path: &PathBuf) -> Result<(), anyhow::Error> {
+fn check_file(path: &PathBuf, input_format: &str) -> Result<(), anyhow::Error> {
     if path.exists() {
         let md = fs::metadata(&path)?;
         if md.is_file() {
             if md.len() > 0 {
                 let magic = magic!()?;
-                match magic.file(&path)?.as_str() {
-                    "OpenStreetMap Protocolbuffer Binary Format" => {
+                let file_type = magic.file(&path)?;
+                match (input_format, file_type.as_str()) {
+                    ("pbf", "OpenStreetMap Protocolbuffer Binary Format") => {
                         log::info!("Found OSM PBF file, path: {}, size: {}", path.display(), md.len());
                         Ok(())
                     }
+                    ("osm", t) if is_xml_file_type(path, t) => {
+                        check_xml_file(path)?;
+                        log::info!("Found OSM XML file, path: {}, size: {}, type: {}", path.display(), md.len(), file_type);
+                        Ok(())
+                    }
+                    ("osm", _) => {
+                        Err(anyhow::anyhow!("Path {}, does not point to a OSM XML file matching its extension but to a {}", path.display(), file_type))
+                    }
                     _ => {
-                        Err(anyhow::anyhow!("Path {}, does not point to a OSM PBF file but to a {}", path.display(), magic.file(&path)?))
+                        Err(anyhow::anyhow!("Path {}, does not point to a OSM PBF file but to a {}", path.display(), file_type))
                     }
                 }
             } else {
@@ -101,19 +359,47 @@ fn check_file(path: &PathBuf) -> Result<(), anyhow::Error> {
     }
 }
 
-fn print_progress(output_path: &PathBuf, stopwatch: &StopWatch, objects: i64, i: usize) -> Result<(), anyhow::Error> {
-    let du = benchmark_rs::disk_usage::disk_usage(&output_path);
-    log::info!("Processed {} objects, {:.2}%, disk: {}, time: {}",
-                    i.to_formatted_string(&Locale::en),
-                    i as f64/ objects as f64 * 100 as f64,
-                    benchmark_rs::disk_usage::to_human(du?),
-                    stopwatch
-                );
+/// Whether the file type matches the extension, compressed data for .gz and .bz2 and XML otherwise
+fn is_xml_file_type(path: &Path, file_type: &str) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => file_type.starts_with("gzip compressed data"),
+        Some("bz2") => file_type.starts_with("bzip2 compressed data"),
+        _ => file_type.starts_with("XML") || file_type.starts_with("OpenStreetMap XML"),
+    }
+}
+
+fn print_progress(progress: &PhaseProgress, output_path: Option<&PathBuf>, stopwatch: &StopWatch, objects: i64, i: usize) -> Result<(), anyhow::Error> {
+    if progress_format() == ProgressFormat::Json {
+        progress.progress(i as u64, (objects > 0).then_some(objects as u64), output_path);
+        return Ok(());
+    }
+    let percentage = if objects > 0 {
+        format!(", {:.2}%", i as f64 / objects as f64 * 100 as f64)
+    } else {
+        String::new()
+    };
+    match output_path {
+        Some(output_path) => {
+            let du = benchmark_rs::disk_usage::disk_usage(&output_path);
+            log::info!("Processed {} objects{}, disk: {}, time: {}",
+                            i.to_formatted_string(&Locale::en),
+                            percentage,
+                            benchmark_rs::disk_usage::to_human(du?),
+                            stopwatch
+                        );
+        }
+        None => {
+            log::info!("Processed {} objects{}, time: {}",
+                            i.to_formatted_string(&Locale::en),
+                            percentage,
+                            stopwatch
+                        );
+        }
+    }
     Ok(())
 }
 
-fn print_verbose_info(input_path: &PathBuf, pbf_reader: &PbfReader, objects: i64, nodes: i64, ways: i64, relations: i64) {
-    let info = pbf_reader.info();
+fn print_verbose_info(input_path: &PathBuf, info: &FileInfo, objects: i64, nodes: i64, ways: i64, relations: i64) {
     log::info!("Processing: {}", input_path.display());
     for feature in info.required_features() {
         log::info!("Required feature: {}", feature);
@@ -122,64 +408,164 @@ fn print_verbose_info(input_path: &PathBuf, pbf_reader: &PbfReader, objects: i64
         log::info!("Optional feature: {}", feature);
     }
     log::info!("Input generated by: {}", info.writingprogram().as_ref().unwrap_or(&"unknown".to_string()));
-    log::info!("Nodes: {}", nodes.to_formatted_string(&Locale::en));
-    log::info!("Ways: {}", ways.to_formatted_string(&Locale::en));
-    log::info!("Relations: {}", relations.to_formatted_string(&Locale::en));
-    log::info!("Total OSM objects: {}", objects.to_formatted_string(&Locale::en));
+    if objects > 0 {
+        log::info!("Nodes: {}", nodes.to_formatted_string(&Locale::en));
+        log::info!("Ways: {}", ways.to_formatted_string(&Locale::en));
+        log::info!("Relations: {}", relations.to_formatted_string(&Locale::en));
+        log::info!("Total OSM objects: {}", objects.to_formatted_string(&Locale::en));
+    }
 }
 
 pub fn export(
-    dump_path: &PathBuf,
-    output_path: &PathBuf,
-    _output_format: String,
-    bounding_box: Option<BoundingBox>,
-    calc_bounding_box: bool,
-    osmosis_replication_timestamp: Option<i64>,
-    osmosis_replication_sequence_number: Option<i64>,
-    osmosis_replication_base_url: Option<String>,
+    read: ReadOptions,
+    output: OutputOptions,
+    filter: FilterOptions,
     jobs: i16,
-    host: String,
-    port: String,
-    database: String,
-    user: String,
-    password: Option<String>,
-    _var_lib_path: &PathBuf,
-    var_log_path: &PathBuf,
+    database: Database,
+    work_paths: &WorkPaths,
     verbose: bool,
 ) -> Result<(), anyhow::Error> {
+    let ReadOptions { read, dump_path } = read;
+    let OutputOptions {
+        path: output_path,
+        format: output_format,
+        compression,
+        compression_level,
+        bounding_box,
+        calc_bounding_box,
+        osmosis_replication_timestamp,
+        osmosis_replication_sequence_number,
+        osmosis_replication_base_url,
+    } = output;
+    let FilterOptions {
+        tags,
+        select_types,
+        select_ids,
+        select_referenced,
+        current_only,
+        extract_bounding_box,
+        extract_polygon_path,
+        extract_strategy,
+    } = filter;
     let mut stopwatch = StopWatch::new();
     stopwatch.start();
-    let (dump_transaction_id, dump_timestamp) = db::pg::dump(
-        jobs,
-        host.clone(),
-        port.clone(),
-        database.clone(),
-        user.clone(),
-        password.clone(),
-        &dump_path,
-        _var_lib_path,
-        var_log_path,
-    )?;
+    let region = match (extract_bounding_box, extract_polygon_path) {
+        (Some(bounding_box), None) => {
+            Some(Region::BoundingBox { bounding_box })
+        }
+        (None, Some(polygon_path)) => {
+            Some(Region::Polygon { polygon: Polygon::from_path(&polygon_path)? })
+        }
+        (None, None) => {
+            None
+        }
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!("Extract bounding box and extract polygon are mutually exclusive"));
+        }
+    };
+    let extract_strategy = ExtractStrategy::from_str(&extract_strategy)?;
+    if output_format != "pbf" && (compression != "zlib" || compression_level.is_some()) {
+        log::warn!("Compression options apply only to PBF output, ignored for {}", output_format);
+    }
+    let compression = Compression::new(&compression, compression_level)?;
+    let tag_filter = TagFilter::new(&tags.keep_tags, &tags.drop_tags)?;
+    if let Some(tag_filter) = &tag_filter {
+        log::info!("Filter tags: {:?}", tag_filter);
+    }
+    if read != "copy" && database.schema != PUBLIC_SCHEMA {
+        return Err(anyhow::anyhow!("The apidb dump is read from the {} schema, read with copy to export schema: {}", PUBLIC_SCHEMA, database.schema));
+    }
+    let mut selection = Selection::new(&select_types, &select_ids)?;
+    if selection.is_none() && select_referenced {
+        log::warn!("--referenced applies only with --types or --ids, ignored");
+    }
+
+    let (reader, dump_transaction_id, dump_timestamp, replication_state): (Box<dyn ElementSource>, u64, DateTime<Utc>, Option<State>) = match read.as_str() {
+        "pg-dump" => {
+            let dump_path = dump_path.as_ref().ok_or(anyhow::anyhow!("Dump path is required unless reading with copy"))?;
+            let progress = PhaseProgress::start(Phase::Dump);
+            let (dump_transaction_id, dump_timestamp, replication_state) = {
+                let _ticker = progress.ticker(Some(dump_path.as_path()));
+                db::pg::dump(jobs, &database, dump_path, work_paths)?
+            };
+            progress.finish(None, Some(dump_path));
 
-    let mut create_reader_stopwatch = StopWatch::new();
-    create_reader_stopwatch.start();
-    log::info!("Create apidb reader. Will sort tables");
-    let reader = Reader::new(dump_path.clone(), dump_path.clone())?;
-    log::info!("Finished creating apidb reader, time: {}", create_reader_stopwatch);
+            let mut create_reader_stopwatch = StopWatch::new();
+            create_reader_stopwatch.start();
+            log::info!("Create apidb reader. Will sort tables");
+            let progress = PhaseProgress::start(Phase::Sorting);
+            let reader = {
+                let _ticker = progress.ticker(Some(dump_path.as_path()));
+                Reader::new(dump_path.clone(), dump_path.clone())?
+            };
+            progress.finish(None, Some(dump_path));
+            log::info!("Finished creating apidb reader, time: {}", create_reader_stopwatch);
+            (Box::new(reader), dump_transaction_id, dump_timestamp, replication_state)
+        }
+        "copy" => {
+            log::info!("Read directly from the database with COPY");
+            let (reader, transaction_id, timestamp, replication_state) = SnapshotReader::new(&database)?;
+            (Box::new(reader), transaction_id, timestamp, replication_state)
+        }
+        _ => {
+            return Err(anyhow::anyhow!("Unsupported read mode: {}", read));
+        }
+    };
+    let reader = reader.as_ref();
 
     let (selected_osmosis_replication_timestamp, selected_osmosis_replication_sequence_number) = select_replication_params(
         osmosis_replication_timestamp,
         osmosis_replication_sequence_number,
+        replication_state,
         dump_timestamp,
         dump_transaction_id,
     );
 
+    let extract = match &region {
+        None => {
+            None
+        }
+        Some(region) => {
+            let mut extract_stopwatch = StopWatch::new();
+            extract_stopwatch.start();
+            log::info!("Select elements of the extract, strategy: {:?}", extract_strategy);
+            let extract = Extract::new(region, extract_strategy, || export_elements(reader, current_only, None, None))?;
+            reader.check()?;
+            log::info!("Finished selecting elements of the extract, time: {}", extract_stopwatch);
+            Some(extract)
+        }
+    };
+
+    if let (Some(selection), true) = (selection.as_mut(), select_referenced) {
+        let mut select_stopwatch = StopWatch::new();
+        select_stopwatch.start();
+        log::info!("Select elements referenced by the selection");
+        selection.select_referenced(|| export_elements(reader, current_only, extract.as_ref(), None))?;
+        reader.check()?;
+        log::info!("Finished selecting referenced elements, time: {}", select_stopwatch);
+    }
+
+    let bounding_box = if calc_bounding_box {
+        let calculated_bounding_box = calculate_bounding_box(export_elements(reader, current_only, extract.as_ref(), selection.as_ref())?);
+        reader.check()?;
+        calculated_bounding_box
+    } else {
+        bounding_box.or(region.as_ref().map(|region| region.bounding_box()))
+    };
+
+    let mut required_features = ["OsmSchema-V0.6", "DenseNodes"].map(|s| s.to_string()).to_vec();
+    if current_only {
+        log::info!("Current only flag set, historical versions and deleted elements will be dropped");
+    } else {
+        required_features.push("HistoricalInformation".to_string());
+    }
+
     let info = FileInfo::new(
-        calculate_bounding_box(calc_bounding_box, bounding_box, &reader)?,
-        ["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"].map(|s| s.to_string()).to_vec(),
+        bounding_box,
+        required_features,
         ["Sort.Type_then_ID"].map(|s| s.to_string()).to_vec(),
         Some(format!("osm-admin-{}", option_env!("CARGO_PKG_VERSION").unwrap())),
-        Some("from-apidb-dump".to_string()),
+        Some(if read == "copy" { "from-apidb" } else { "from-apidb-dump" }.to_string()),
         selected_osmosis_replication_timestamp,
         selected_osmosis_replication_sequence_number,
         osmosis_replication_base_url,
@@ -187,146 +573,446 @@ pub fn export(
 
     let mut objects = 0;
     if verbose {
-        let (nodes, ways, relations) = count_objects(host, port, database, user, password)?;
+        let counting = PhaseProgress::start(Phase::Counting);
+        let (nodes, ways, relations) = count_objects(&database)?;
+        counting.finish_counting(nodes, ways, relations);
         objects = nodes + ways + relations;
-        let du = benchmark_rs::disk_usage::disk_usage(&dump_path)?;
-        log::info!("Sorted dump disk usage: {}", benchmark_rs::disk_usage::to_human(du));
+        if let (Some(dump_path), "pg-dump") = (&dump_path, read.as_str()) {
+            let du = benchmark_rs::disk_usage::disk_usage(dump_path)?;
+            log::info!("Sorted dump disk usage: {}", benchmark_rs::disk_usage::to_human(du));
+        }
         log::info!("Nodes: {}", nodes.to_formatted_string(&Locale::en));
         log::info!("Ways: {}", ways.to_formatted_string(&Locale::en));
         log::info!("Relations: {}", relations.to_formatted_string(&Locale::en));
         log::info!("Total OSM objects: {}", objects.to_formatted_string(&Locale::en));
     }
 
-    let mut writer = Writer::from_file_info(
-        output_path.clone(),
-        info,
-        CompressionType::Zlib,
-    )?;
+    let mut writer = create_writer(&output_path, &output_format, info, compression, jobs as usize)?;
+    let mut tag_filter_writer;
+    let writer: &mut dyn ElementWriter = match &tag_filter {
+        Some(tag_filter) => {
+            tag_filter_writer = TagFilterWriter::new(tag_filter, writer.as_mut());
+            &mut tag_filter_writer
+        }
+        None => {
+            writer.as_mut()
+        }
+    };
+    // the disk usage of the output is not reported when writing to stdout
+    let progress_path = if is_stream(&output_path) { None } else { Some(&output_path) };
 
-    let mut generate_pbf_stopwatch = StopWatch::new();
-    generate_pbf_stopwatch.start();
+    let mut generate_output_stopwatch = StopWatch::new();
+    generate_output_stopwatch.start();
+    let progress = PhaseProgress::start(Phase::Write);
+    let mut elements_written = 0;
     writer.write_header()?;
-    for (i, element) in reader.elements()?.enumerate() {
+    for (i, element) in export_elements(reader, current_only, extract.as_ref(), selection.as_ref())?.enumerate() {
+        metrics::count_element(&element);
         writer.write_element(element)?;
+        if i % METRICS_INTERVAL == 0 {
+            metrics::update(progress_path, false);
+        }
         if verbose && i % 10000000 == 0 && i != 0 {
-            print_progress(&output_path, &generate_pbf_stopwatch, objects, i)?;
+            print_progress(&progress, progress_path, &generate_output_stopwatch, objects, i)?;
         }
+        elements_written = i + 1;
     }
+    reader.check()?;
     writer.close()?;
-    print_progress(&output_path, &generate_pbf_stopwatch, objects, objects as usize)?;
+    print_progress(&progress, progress_path, &generate_output_stopwatch, objects, elements_written)?;
+    progress.finish(Some(elements_written as u64), progress_path);
 
     log::info!("Osm export time: {}", stopwatch);
     Ok(())
 }
 
+pub fn apply_changes(input_paths: Vec<PathBuf>, database: Database) -> Result<(), anyhow::Error> {
+    let mut stopwatch = StopWatch::new();
+    stopwatch.start();
+    log::info!("Start applying changes");
+    let counts = db::changes::apply_changes(&database, &input_paths)?;
+    log::info!("Nodes, {}", counts.nodes);
+    log::info!("Ways, {}", counts.ways);
+    log::info!("Relations, {}", counts.relations);
+    log::info!("Finish applying changes, time: {}", stopwatch);
+    Ok(())
+}
+
+pub fn sync(replication_path: PathBuf, start_sequence_number: Option<i64>, database: Database) -> Result<(), anyhow::Error> {
+    let mut stopwatch = StopWatch::new();
+    stopwatch.start();
+    let latest_state = State::from_path(&replication_path.join("state.txt"))?;
+    log::info!(
+        "Start sync from: {}, latest sequence number: {}, timestamp: {}",
+        replication_path.display(),
+        latest_state.sequence_number(),
+        latest_state.timestamp().to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    let (applied, counts) = db::replication::sync(&database, &replication_path, &latest_state, start_sequence_number)?;
+    log::info!("Applied change files: {}", applied);
+    log::info!("Nodes, {}", counts.nodes);
+    log::info!("Ways, {}", counts.ways);
+    log::info!("Relations, {}", counts.relations);
+    log::info!("Finish sync, time: {}", stopwatch);
+    Ok(())
+}
+
+pub fn changes(
+    replication_path: PathBuf,
+    since: Option<String>,
+    database: Database,
+) -> Result<(), anyhow::Error> {
+    let mut stopwatch = StopWatch::new();
+    stopwatch.start();
+    let latest_state_path = replication_path.join("state.txt");
+    let latest_state = if latest_state_path.exists() {
+        Some(State::from_path(&latest_state_path)?)
+    } else {
+        None
+    };
+    let (since_timestamp, previous_sequence_number, previous_transaction_snapshot) = match (since, &latest_state) {
+        (None, None) => {
+            return Err(anyhow::anyhow!("No state.txt found in {}, --since must be provided", replication_path.display()));
+        }
+        (None, Some(latest_state)) => {
+            (*latest_state.timestamp(), Some(latest_state.sequence_number()), latest_state.transaction_snapshot().cloned())
+        }
+        (Some(since), _) => {
+            match i64::from_str(&since) {
+                Ok(sequence_number) => {
+                    let state = State::from_path(&replication::directory::state_path(&replication_path, sequence_number))?;
+                    (*state.timestamp(), Some(sequence_number), state.transaction_snapshot().cloned())
+                }
+                Err(_) => {
+                    let timestamp = DateTime::<Utc>::from(DateTime::parse_from_rfc3339(&since)?);
+                    (timestamp, latest_state.as_ref().map(|state| state.sequence_number()), None)
+                }
+            }
+        }
+    };
+    if previous_transaction_snapshot.is_none() {
+        log::warn!("No previous transaction snapshot, publishing the changes by timestamp, late commits before it may be missing");
+    }
+    if let (Some(latest_state), Some(previous_sequence_number)) = (&latest_state, previous_sequence_number) {
+        if previous_sequence_number != latest_state.sequence_number() {
+            log::warn!(
+                "Publishing after sequence number {} will overwrite the change files after it, the latest sequence number is {}",
+                previous_sequence_number,
+                latest_state.sequence_number()
+            );
+        }
+    }
+
+    log::info!("Start publishing changes since: {}", since_timestamp.to_rfc3339_opts(SecondsFormat::Secs, true));
+    let published = db::replication::publish_changes(
+        &database,
+        &replication_path,
+        since_timestamp,
+        previous_sequence_number,
+        previous_transaction_snapshot.as_ref(),
+    )?;
+    if let Some((state, counts)) = published {
+        log::info!("Published sequence number: {}, timestamp: {}", state.sequence_number(), state.timestamp().to_rfc3339_opts(SecondsFormat::Secs, true));
+        log::info!("Nodes, {}", counts.nodes);
+        log::info!("Ways, {}", counts.ways);
+        log::info!("Relations, {}", counts.relations);
+    }
+    log::info!("Finish publishing changes, time: {}", stopwatch);
+    Ok(())
+}
+
+pub fn diff(
+    from_path: PathBuf,
+    to_path: PathBuf,
+    report_path: Option<PathBuf>,
+    osc_path: Option<PathBuf>,
+) -> Result<(), anyhow::Error> {
+    let mut stopwatch = StopWatch::new();
+    stopwatch.start();
+    log::info!("Start diff from: {} to: {}", from_path.display(), to_path.display());
+    let from_reader = PbfReader::new(&from_path)?;
+    let to_reader = PbfReader::new(&to_path)?;
+    let changes = Diff::new(
+        CurrentElementIterator::new(from_reader.elements()?),
+        CurrentElementIterator::new(to_reader.elements()?),
+    );
+
+    let mut report = match &report_path {
+        None => { None }
+        Some(report_path) => { Some(CompressedFile::create(report_path)?) }
+    };
+    let mut change_writer = match &osc_path {
+        None => { None }
+        Some(osc_path) => {
+            Some(ChangeWriter::new(osc_path, &format!("osm-admin-{}", env!("CARGO_PKG_VERSION")))?)
+        }
+    };
+    let (mut nodes, mut ways, mut relations) = (ChangeCounts::default(), ChangeCounts::default(), ChangeCounts::default());
+    for change in changes {
+        let change = change?;
+        let counts = match change.element() {
+            Element::Node { .. } => { &mut nodes }
+            Element::Way { .. } => { &mut ways }
+            _ => { &mut relations }
+        };
+        let action = match change {
+            Change::Added { .. } => {
+                counts.added += 1;
+                Action::Create
+            }
+            Change::Removed { .. } => {
+                counts.removed += 1;
+                Action::Delete
+            }
+            Change::Modified { .. } => {
+                counts.modified += 1;
+                Action::Modify
+            }
+        };
+        if let Some(report) = report.as_mut() {
+            writeln!(report, "{}", change.to_json().dump())?;
+        }
+        if let Some(change_writer) = change_writer.as_mut() {
+            change_writer.write(action, change.element())?;
+        }
+    }
+    if let Some(report) = report {
+        report.finish()?;
+    }
+    if let Some(mut change_writer) = change_writer {
+        change_writer.close()?;
+    }
+
+    let summary = json::object! {
+        from: from_path.display().to_string(),
+        to: to_path.display().to_string(),
+        nodes: nodes.to_json(),
+        ways: ways.to_json(),
+        relations: relations.to_json(),
+    };
+    println!("{}", summary.pretty(2));
+    log::info!("Finish diff, time: {}", stopwatch);
+    Ok(())
+}
+
+pub fn verify(
+    input: InputOptions,
+    report_path: Option<PathBuf>,
+    database: Database,
+) -> Result<(), anyhow::Error> {
+    let InputOptions { path: input_path, format: input_format } = input;
+    let mut stopwatch = StopWatch::new();
+    stopwatch.start();
+    log::info!("Start computing input stats: {}", input_path.display());
+    let input_stats = match input_format.as_str() {
+        "osm" => {
+            Stats::from_elements(xml::reader::Reader::from_path(&input_path)?.map(|result| result.map(|(_, element)| element)))?
+        }
+        _ => {
+            Stats::from_elements(PbfReader::new(&input_path)?.elements()?.map(Ok))?
+        }
+    };
+    log::info!("Finish computing input stats, time: {}", stopwatch);
+
+    stopwatch.reset();
+    stopwatch.start();
+    log::info!("Start computing database stats");
+    let database_stats = db::stats::database_stats(&database)?;
+    log::info!("Finish computing database stats, time: {}", stopwatch);
+
+    let (report, matches) = verify::stats::report(
+        &input_path.display().to_string(),
+        &database.describe(),
+        &input_stats,
+        &database_stats,
+    );
+    match report_path {
+        None => {
+            println!("{}", report.pretty(2));
+        }
+        Some(report_path) => {
+            fs::write(&report_path, report.pretty(2))?;
+            log::info!("Verification report written to: {}", report_path.display());
+        }
+    }
+    if matches {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Verification failed, the database does not match the input"))
+    }
+}
+
 fn select_replication_params(
     osmosis_replication_timestamp: Option<i64>,
     osmosis_replication_sequence_number: Option<i64>,
+    replication_state: Option<State>,
     dump_timestamp: DateTime<Utc>,
     dump_transaction_id: u64,
 ) -> (Option<i64>, Option<i64>) {
-    let timestamp = match osmosis_replication_timestamp {
-        None => {
+    let timestamp = match (osmosis_replication_timestamp, &replication_state) {
+        (None, Some(state)) => {
+            log::info!(
+                "No osmosis_replication_timestamp provided, using database replication timestamp: {}",
+                state.timestamp().to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+            Some(state.timestamp().timestamp())
+        }
+        (None, None) => {
             log::info!(
                 "No osmosis_replication_timestamp provided, using dump timestamp: {}",
                 dump_timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
             );
             Some(dump_timestamp.timestamp_millis() / 1000)
         }
-        Some(timestamp) => {
+        (Some(timestamp), _) => {
             Some(timestamp)
         }
     };
-    let sequence_number = match osmosis_replication_sequence_number {
-        None => {
+    let sequence_number = match (osmosis_replication_sequence_number, &replication_state) {
+        (None, Some(state)) => {
+            log::info!(
+                "No osmosis_replication_sequence_number provided, using database replication sequence number: {}",
+                state.sequence_number()
+            );
+            Some(state.sequence_number())
+        }
+        (None, None) => {
             log::info!(
                 "No osmosis_replication_sequence_number provided, using dump transaction id: {}",
                 dump_transaction_id
             );
             Some(dump_transaction_id as i64)
         }
-        Some(sequence_number) => {
+        (Some(sequence_number), _) => {
             Some(sequence_number)
         }
     };
     (timestamp, sequence_number)
 }
 
-fn calculate_bounding_box(calc_bounding_box: bool, bounding_box_opt: Option<BoundingBox>, reader: &Reader) -> Result<Option<BoundingBox>, anyhow::Error> {
-    if calc_bounding_box {
-        let mut calculated_bounding_box = None;
-        for element in reader.elements()? {
-            match element {
-                Element::Node { node } => {
-                    if node.visible() {
-                        if calculated_bounding_box.is_none() {
-                            calculated_bounding_box = Some(
-                                BoundingBox::new(
-                                    node.coordinate().lon(),
-                                    node.coordinate().lat(),
-                                    node.coordinate().lon(),
-                                    node.coordinate().lat(),
-                                )
+/// Elements of the source, in export order, optionally limited to the current versions and to an extract
+fn export_elements<'a>(reader: &dyn ElementSource, current_only: bool, extract: Option<&'a Extract>, selection: Option<&'a Selection>) -> Result<Box<dyn Iterator<Item=Element> + 'a>, anyhow::Error> {
+    let mut elements: Box<dyn Iterator<Item=Element>> = if current_only {
+        Box::new(CurrentElementIterator::new(reader.elements()?))
+    } else {
+        reader.elements()?
+    };
+    if let Some(extract) = extract {
+        elements = Box::new(elements.filter(|element| extract.contains(element)));
+    }
+    if let Some(selection) = selection {
+        elements = Box::new(elements.filter(|element| selection.contains(element)));
+    }
+    Ok(elements)
+}
+
+fn calculate_bounding_box(elements: impl Iterator<Item=Element>) -> Option<BoundingBox> {
+    let mut calculated_bounding_box: Option<BoundingBox> = None;
+    for element in elements {
+        match element {
+            Element::Node { node } => {
+                if node.visible() {
+                    if calculated_bounding_box.is_none() {
+                        calculated_bounding_box = Some(
+                            BoundingBox::new(
+                                node.coordinate().lon(),
+                                node.coordinate().lat(),
+                                node.coordinate().lon(),
+                                node.coordinate().lat(),
                             )
-                        } else {
-                            calculated_bounding_box.as_mut().unwrap().merge_point(node.coordinate());
-                        }
+                        )
+                    } else {
+                        calculated_bounding_box.as_mut().unwrap().merge_point(node.coordinate());
                     }
                 }
-                Element::Way { .. } => {
-                    break;
-                }
-                Element::Relation { .. } => {
-                    break;
-                }
-                Element::Sentinel => {
-                    break;
-                }
+            }
+            Element::Way { .. } => {
+                break;
+            }
+            Element::Relation { .. } => {
+                break;
+            }
+            Element::Sentinel => {
+                break;
             }
         }
-        Ok(calculated_bounding_box)
-    } else {
-        Ok(bounding_box_opt)
     }
+    calculated_bounding_box
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
     use std::path::PathBuf;
 
+    use flate2::write::GzEncoder;
+
     use crate::check_file;
 
     #[test]
     #[should_panic]
     fn test_check_non_existent_file() {
         let path = PathBuf::from("./tests/fixtures/non-existent");
-        check_file(&path).expect("non existent file");
+        check_file(&path, "pbf").expect("non existent file");
     }
 
     #[test]
     #[should_panic]
     fn test_check_empty_file() {
         let path = PathBuf::from("./tests/fixtures/empty");
-        check_file(&path).expect("empty file");
+        check_file(&path, "pbf").expect("empty file");
     }
 
     #[test]
     #[should_panic]
     fn test_check_directory() {
         let path = PathBuf::from("./tests/fixtures/");
-        check_file(&path).expect("directory");
+        check_file(&path, "pbf").expect("directory");
     }
 
     #[test]
     #[should_panic]
     fn test_check_text() {
         let path = PathBuf::from("./tests/fixtures/text");
-        check_file(&path).expect("text file");
+        check_file(&path, "pbf").expect("text file");
     }
 
     #[test]
     fn test_check_good_file() {
         let path = PathBuf::from("./tests/fixtures/niue-230109.osm.pbf");
-        check_file(&path).unwrap();
+        check_file(&path, "pbf").unwrap();
+    }
+
+    #[test]
+    fn test_check_xml_file() {
+        check_file(&PathBuf::from("./tests/fixtures/small.osm"), "osm").unwrap();
+        check_file(&PathBuf::from("./tests/fixtures/small.osm.bz2"), "osm").unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_text_as_xml() {
+        let path = PathBuf::from("./tests/fixtures/text");
+        check_file(&path, "osm").expect("text file");
+    }
+
+    #[test]
+    fn test_check_xml_file_extension() -> Result<(), anyhow::Error> {
+        let plain_path = std::env::temp_dir().join("osm-admin-test-check-xml-plain.osm.gz");
+        fs::copy("./tests/fixtures/small.osm", &plain_path)?;
+        let plain = check_file(&plain_path, "osm");
+        fs::remove_file(&plain_path)?;
+        assert!(plain.is_err());
+
+        let compressed_path = std::env::temp_dir().join("osm-admin-test-check-xml-compressed.osm.gz");
+        let mut encoder = GzEncoder::new(File::create(&compressed_path)?, flate2::Compression::default());
+        encoder.write_all(&fs::read("./tests/fixtures/text")?)?;
+        encoder.finish()?;
+        let compressed = check_file(&compressed_path, "osm");
+        fs::remove_file(&compressed_path)?;
+        assert!(compressed.is_err());
+        Ok(())
     }
 }
\ No newline at end of file
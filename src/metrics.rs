@@ -0,0 +1,191 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+
+use crate::progress::Phase;
+
+/// Minimal interval between periodic writes of the metrics file
+const WRITE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Metrics of a long running import or export, written periodically to a file in the Prometheus
+/// text format for the node-exporter textfile collector
+struct Metrics {
+    path: PathBuf,
+    started: Instant,
+    nodes: AtomicU64,
+    ways: AtomicU64,
+    relations: AtomicU64,
+    state: Mutex<State>,
+}
+
+struct State {
+    phase: Option<Phase>,
+    phase_started: Instant,
+    output_bytes: Option<u64>,
+    pg_restore_exit_status: Option<i32>,
+    written: Option<Instant>,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Write the metrics to the file, e.g. /var/lib/node_exporter/textfile_collector/osm_admin.prom.
+/// Can be set once, before the import or export is started.
+pub fn set_metrics_file(path: PathBuf) -> Result<(), anyhow::Error> {
+    let now = Instant::now();
+    METRICS.set(
+        Metrics {
+            path,
+            started: now,
+            nodes: AtomicU64::new(0),
+            ways: AtomicU64::new(0),
+            relations: AtomicU64::new(0),
+            state: Mutex::new(
+                State {
+                    phase: None,
+                    phase_started: now,
+                    output_bytes: None,
+                    pg_restore_exit_status: None,
+                    written: None,
+                }
+            ),
+        }
+    ).map_err(|_| anyhow!("Metrics file is already set"))
+}
+
+/// Count the element as written to the dump, database or output file
+pub(crate) fn count_element(element: &Element) {
+    if let Some(metrics) = METRICS.get() {
+        let counter = match element {
+            Element::Node { .. } => { &metrics.nodes }
+            Element::Way { .. } => { &metrics.ways }
+            Element::Relation { .. } => { &metrics.relations }
+            Element::Sentinel => { return; }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Enter the phase and write the metrics
+pub(crate) fn set_phase(phase: Phase) {
+    if let Some(metrics) = METRICS.get() {
+        let mut state = metrics.state.lock().unwrap();
+        state.phase = Some(phase);
+        state.phase_started = Instant::now();
+        state.output_bytes = None;
+        metrics.write(&mut state);
+    }
+}
+
+pub(crate) fn set_pg_restore_exit_status(code: Option<i32>) {
+    if let Some(metrics) = METRICS.get() {
+        let mut state = metrics.state.lock().unwrap();
+        // a pg_restore killed by a signal has no exit code
+        state.pg_restore_exit_status = Some(code.unwrap_or(-1));
+        metrics.write(&mut state);
+    }
+}
+
+/// Write the metrics with the disk usage of the output path, if any. Unless forced, the metrics
+/// are written at most once per [WRITE_INTERVAL].
+pub(crate) fn update(output_path: Option<&PathBuf>, force: bool) {
+    if let Some(metrics) = METRICS.get() {
+        let mut state = metrics.state.lock().unwrap();
+        if force || state.written.is_none_or(|written| written.elapsed() >= WRITE_INTERVAL) {
+            if let Some(output_path) = output_path {
+                state.output_bytes = benchmark_rs::disk_usage::disk_usage(output_path).ok();
+            }
+            metrics.write(&mut state);
+        }
+    }
+}
+
+impl Metrics {
+    /// Write the metrics to a temporary file renamed over the metrics file, so that the collector
+    /// never reads a partial file. Failures are logged, metrics never fail the import or export.
+    fn write(&self, state: &mut State) {
+        let tmp_path = self.path.with_extension("prom.tmp");
+        let result = fs::write(&tmp_path, self.render(state))
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        if let Err(e) = result {
+            log::warn!("Failed writing metrics to {}: {}", self.path.display(), e);
+        }
+        state.written = Some(Instant::now());
+    }
+
+    fn render(&self, state: &State) -> String {
+        let mut text = String::new();
+        metric(&mut text, "osm_admin_elements_processed_total", "counter", "Elements written to the dump, database or output file by type");
+        for (element_type, counter) in [("node", &self.nodes), ("way", &self.ways), ("relation", &self.relations)] {
+            writeln!(text, "osm_admin_elements_processed_total{{type=\"{}\"}} {}", element_type, counter.load(Ordering::Relaxed)).unwrap();
+        }
+        metric(&mut text, "osm_admin_phase", "gauge", "Current phase of the import or export");
+        for phase in Phase::ALL {
+            writeln!(text, "osm_admin_phase{{phase=\"{}\"}} {}", phase.name(), (state.phase == Some(phase)) as u8).unwrap();
+        }
+        metric(&mut text, "osm_admin_phase_elapsed_seconds", "gauge", "Elapsed time of the current phase");
+        writeln!(text, "osm_admin_phase_elapsed_seconds {:.3}", state.phase_started.elapsed().as_secs_f64()).unwrap();
+        metric(&mut text, "osm_admin_elapsed_seconds", "gauge", "Elapsed time of the import or export");
+        writeln!(text, "osm_admin_elapsed_seconds {:.3}", self.started.elapsed().as_secs_f64()).unwrap();
+        if let Some(output_bytes) = state.output_bytes {
+            metric(&mut text, "osm_admin_output_bytes", "gauge", "Disk usage of the output of the current phase");
+            writeln!(text, "osm_admin_output_bytes {}", output_bytes).unwrap();
+        }
+        if let Some(code) = state.pg_restore_exit_status {
+            metric(&mut text, "osm_admin_pg_restore_exit_status", "gauge", "Exit status of the last pg_restore invocation");
+            writeln!(text, "osm_admin_pg_restore_exit_status {}", code).unwrap();
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        metric(&mut text, "osm_admin_last_update_timestamp_seconds", "gauge", "Time of the last update of the metrics, for detecting stalls");
+        writeln!(text, "osm_admin_last_update_timestamp_seconds {}", now.as_secs()).unwrap();
+        text
+    }
+}
+
+fn metric(text: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, metric_type).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use crate::metrics::{Metrics, State};
+    use crate::progress::Phase;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics {
+            path: PathBuf::from("osm_admin.prom"),
+            started: Instant::now(),
+            nodes: AtomicU64::new(3),
+            ways: AtomicU64::new(2),
+            relations: AtomicU64::new(1),
+            state: Mutex::new(
+                State {
+                    phase: Some(Phase::Restore),
+                    phase_started: Instant::now(),
+                    output_bytes: Some(1024),
+                    pg_restore_exit_status: Some(1),
+                    written: None,
+                }
+            ),
+        };
+        let text = metrics.render(&metrics.state.lock().unwrap());
+        assert!(text.contains("osm_admin_elements_processed_total{type=\"way\"} 2\n"));
+        assert!(text.contains("osm_admin_phase{phase=\"restore\"} 1\n"));
+        assert!(text.contains("osm_admin_phase{phase=\"dump\"} 0\n"));
+        assert!(text.contains("osm_admin_output_bytes 1024\n"));
+        assert!(text.contains("osm_admin_pg_restore_exit_status 1\n"));
+        assert!(text.contains("# TYPE osm_admin_elapsed_seconds gauge\n"));
+    }
+}
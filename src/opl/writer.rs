@@ -0,0 +1,209 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat};
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::Member;
+use osm_io::osm::model::tag::Tag;
+
+use crate::output::compression::CompressedFile;
+use crate::output::writer::ElementWriter;
+
+/// Writer of OPL files
+///
+/// See https://osmcode.org/opl-file-format/. OPL has no header, so the file metadata is not written.
+pub(crate) struct Writer {
+    file: Option<CompressedFile>,
+}
+
+impl Writer {
+    pub(crate) fn new(path: &PathBuf) -> Result<Writer, anyhow::Error> {
+        Ok(
+            Writer {
+                file: Some(CompressedFile::create(path)?),
+            }
+        )
+    }
+}
+
+impl ElementWriter for Writer {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        if let Some(line) = format_element(&element)? {
+            let file = self.file.as_mut().ok_or(anyhow!("Writer is closed"))?;
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        self.file.take().ok_or(anyhow!("Writer is closed"))?.finish()
+    }
+}
+
+/// Format an element as an OPL line. [Element::Sentinel] has no representation.
+pub(crate) fn format_element(element: &Element) -> Result<Option<String>, anyhow::Error> {
+    match element {
+        Element::Node { node } => {
+            let (x, y) = if node.visible() {
+                (format!("{:.7}", node.coordinate().lon()), format!("{:.7}", node.coordinate().lat()))
+            } else {
+                (String::new(), String::new())
+            };
+            Ok(
+                Some(
+                    format!(
+                        "n{} {} T{} x{} y{}",
+                        node.id(),
+                        attributes(node.version(), node.visible(), node.changeset(), node.timestamp(), node.uid(), node.user())?,
+                        format_tags(node.tags()),
+                        x,
+                        y,
+                    )
+                )
+            )
+        }
+        Element::Way { way } => {
+            let refs: Vec<String> = way.refs().iter().map(|r| format!("n{}", r)).collect();
+            Ok(
+                Some(
+                    format!(
+                        "w{} {} T{} N{}",
+                        way.id(),
+                        attributes(way.version(), way.visible(), way.changeset(), way.timestamp(), way.uid(), way.user())?,
+                        format_tags(way.tags()),
+                        refs.join(","),
+                    )
+                )
+            )
+        }
+        Element::Relation { relation } => {
+            let members: Vec<String> = relation.members().iter()
+                .map(|member| match member {
+                    Member::Node { member } => { format!("n{}@{}", member.id(), escape(member.role())) }
+                    Member::Way { member } => { format!("w{}@{}", member.id(), escape(member.role())) }
+                    Member::Relation { member } => { format!("r{}@{}", member.id(), escape(member.role())) }
+                })
+                .collect();
+            Ok(
+                Some(
+                    format!(
+                        "r{} {} T{} M{}",
+                        relation.id(),
+                        attributes(relation.version(), relation.visible(), relation.changeset(), relation.timestamp(), relation.uid(), relation.user())?,
+                        format_tags(relation.tags()),
+                        members.join(","),
+                    )
+                )
+            )
+        }
+        Element::Sentinel => {
+            Ok(None)
+        }
+    }
+}
+
+fn attributes(version: i32, visible: bool, changeset: i64, timestamp: i64, uid: i32, user: &str) -> Result<String, anyhow::Error> {
+    let timestamp = DateTime::from_timestamp_millis(timestamp)
+        .ok_or(anyhow!("Invalid timestamp: {}", timestamp))?
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+    Ok(
+        format!(
+            "v{} d{} c{} t{} i{} u{}",
+            version,
+            if visible { "V" } else { "D" },
+            changeset,
+            timestamp,
+            uid,
+            escape(user),
+        )
+    )
+}
+
+fn format_tags(tags: &[Tag]) -> String {
+    tags.iter()
+        .map(|tag| format!("{}={}", escape(tag.k()), escape(tag.v())))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Escape the characters that are not allowed literally in OPL strings as %<hex code point>%
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        let code = c as u32;
+        let literal = (0x21..=0x24).contains(&code)
+            || (0x26..=0x2b).contains(&code)
+            || (0x2d..=0x3c).contains(&code)
+            || (0x3e..=0x3f).contains(&code)
+            || (0x41..=0x7e).contains(&code)
+            || (0xa1..=0xac).contains(&code)
+            || (0xae..=0x5ff).contains(&code);
+        if literal {
+            escaped.push(c);
+        } else {
+            escaped.push_str(&format!("%{:x}%", code));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use osm_io::osm::model::coordinate::Coordinate;
+    use osm_io::osm::model::element::Element;
+    use osm_io::osm::model::node::Node;
+    use osm_io::osm::model::relation::{Member, MemberData, Relation};
+    use osm_io::osm::model::tag::Tag;
+
+    use crate::opl::writer::format_element;
+
+    #[test]
+    fn test_format_node() -> Result<(), anyhow::Error> {
+        let node = Element::Node {
+            node: Node::new(
+                1001,
+                2,
+                Coordinate::new(-19.054321, -169.9123456),
+                1686564000000,
+                5001,
+                42,
+                "Mapper & Co".to_string(),
+                true,
+                vec![Tag::new("name".to_string(), "Talofa, Niue=1".to_string())],
+            ),
+        };
+        assert_eq!(
+            format_element(&node)?.unwrap(),
+            "n1001 v2 dV c5001 t2023-06-12T10:00:00Z i42 uMapper%20%&%20%Co Tname=Talofa%2c%%20%Niue%3d%1 x-169.9123456 y-19.0543210"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_deleted_relation() -> Result<(), anyhow::Error> {
+        let relation = Element::Relation {
+            relation: Relation::new(
+                3001,
+                3,
+                1686564002000,
+                5002,
+                43,
+                "Other".to_string(),
+                false,
+                vec![Member::Way { member: MemberData::new(2001, "outer".to_string()) }, Member::Node { member: MemberData::new(1001, "".to_string()) }],
+                vec![],
+            ),
+        };
+        assert_eq!(
+            format_element(&relation)?.unwrap(),
+            "r3001 v3 dD c5002 t2023-06-12T10:00:02Z i43 uOther T Mw2001@outer,n1001@"
+        );
+        assert!(format_element(&Element::Sentinel)?.is_none());
+        Ok(())
+    }
+}
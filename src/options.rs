@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use osm_io::osm::model::bounding_box::BoundingBox;
+
+/// Input file of an import or verification, `-` for stdin, and its format, pbf or osm
+#[derive(Debug, Clone)]
+pub struct InputOptions {
+    pub path: PathBuf,
+    pub format: String,
+}
+
+/// Load method of an import, pg-restore or copy, with the output directory of the apidb dump
+/// restored with pg_restore and whether an interrupted import is resumed
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub load: String,
+    pub output_path: Option<PathBuf>,
+    pub resume: bool,
+}
+
+/// Read method of an export, pg-dump or copy, with the directory of the dump read with pg-dump
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    pub read: String,
+    pub dump_path: Option<PathBuf>,
+}
+
+/// Output file of an export, `-` for stdout, its format and compression and the header fields
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub path: PathBuf,
+    pub format: String,
+    pub compression: String,
+    pub compression_level: Option<i32>,
+    pub bounding_box: Option<BoundingBox>,
+    pub calc_bounding_box: bool,
+    pub osmosis_replication_timestamp: Option<i64>,
+    pub osmosis_replication_sequence_number: Option<i64>,
+    pub osmosis_replication_base_url: Option<String>,
+}
+
+/// Tags kept or dropped before the elements are written, see --keep-tags and --drop-tags
+#[derive(Debug, Clone, Default)]
+pub struct TagFilterOptions {
+    pub keep_tags: Vec<String>,
+    pub drop_tags: Vec<String>,
+}
+
+/// Elements and tags of an export: the tag filter, the selection by type and id, the current
+/// versions only and the extract of a bounding box or polygon
+#[derive(Debug, Clone)]
+pub struct FilterOptions {
+    pub tags: TagFilterOptions,
+    pub select_types: Vec<String>,
+    pub select_ids: Vec<String>,
+    pub select_referenced: bool,
+    pub current_only: bool,
+    pub extract_bounding_box: Option<BoundingBox>,
+    pub extract_polygon_path: Option<PathBuf>,
+    pub extract_strategy: String,
+}
+
+/// Directories of pg_dump and pg_restore: the temporary password file and the logs
+#[derive(Debug, Clone)]
+pub struct WorkPaths {
+    pub var_lib_path: PathBuf,
+    pub var_log_path: PathBuf,
+}
@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use bzip2::write::BzEncoder;
+use flate2::write::GzEncoder;
+
+use crate::input::stream::is_stream;
+
+/// Output file compressed according to the path extension, .gz for gzip, .bz2 for bzip2, and
+/// uncompressed otherwise. The path `-` stands for stdout, which is written uncompressed.
+pub(crate) enum CompressedFile {
+    Uncompressed {
+        writer: BufWriter<Box<dyn Write>>,
+    },
+    Gzip {
+        writer: GzEncoder<BufWriter<Box<dyn Write>>>,
+    },
+    Bzip2 {
+        writer: BzEncoder<BufWriter<Box<dyn Write>>>,
+    },
+}
+
+impl CompressedFile {
+    pub(crate) fn create(path: &PathBuf) -> Result<CompressedFile, anyhow::Error> {
+        if is_stream(path) {
+            return Ok(CompressedFile::Uncompressed { writer: BufWriter::new(Box::new(std::io::stdout())) });
+        }
+        let file: BufWriter<Box<dyn Write>> = BufWriter::new(
+            Box::new(
+                File::create(path)
+                    .with_context(|| anyhow!("path: {}", path.display()))?
+            )
+        );
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => {
+                Ok(CompressedFile::Gzip { writer: GzEncoder::new(file, flate2::Compression::default()) })
+            }
+            Some("bz2") => {
+                Ok(CompressedFile::Bzip2 { writer: BzEncoder::new(file, bzip2::Compression::default()) })
+            }
+            _ => {
+                Ok(CompressedFile::Uncompressed { writer: file })
+            }
+        }
+    }
+
+    /// Write the compression trailer, if any, and flush the file
+    pub(crate) fn finish(self) -> Result<(), anyhow::Error> {
+        let mut file = match self {
+            CompressedFile::Uncompressed { writer } => { writer }
+            CompressedFile::Gzip { writer } => { writer.finish()? }
+            CompressedFile::Bzip2 { writer } => { writer.finish()? }
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Write for CompressedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            CompressedFile::Uncompressed { writer } => { writer.write(buf) }
+            CompressedFile::Gzip { writer } => { writer.write(buf) }
+            CompressedFile::Bzip2 { writer } => { writer.write(buf) }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            CompressedFile::Uncompressed { writer } => { writer.flush() }
+            CompressedFile::Gzip { writer } => { writer.flush() }
+            CompressedFile::Bzip2 { writer } => { writer.flush() }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub(crate) mod compression;
+pub(crate) mod pbf;
+pub(crate) mod writer;
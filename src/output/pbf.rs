@@ -0,0 +1,513 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, sync_channel, SyncSender};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, Context};
+use flate2::write::ZlibEncoder;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::Member;
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::pbf::file_info::FileInfo;
+use prost::Message;
+
+use crate::input::pbf::OrderWindow;
+use crate::input::stream::is_stream;
+use crate::output::writer::ElementWriter;
+use crate::pbf::proto::{Blob, BlobHeader, DenseInfo, DenseNodes, HeaderBBox, HeaderBlock, Info, PrimitiveBlock, PrimitiveGroup, Relation, StringTable, Way};
+
+/// Maximum number of elements in a primitive block, as written by osmium
+const BLOCK_SIZE: usize = 8000;
+const GRANULARITY: i32 = 100;
+const DATE_GRANULARITY: i32 = 1000;
+/// Number of blocks per job that are encoded ahead of the next blob written in order
+const ENCODE_AHEAD_BLOCKS: usize = 4;
+
+/// Compression of the blobs of a PBF file
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Zlib { level: u32 },
+    Zstd { level: i32 },
+    Lz4,
+}
+
+impl Compression {
+    /// Compression with the codec, one of none, zlib, zstd or lz4, and the level, or the default
+    /// level of the codec. lz4 has no levels.
+    pub(crate) fn new(codec: &str, level: Option<i32>) -> Result<Compression, anyhow::Error> {
+        match (codec, level) {
+            ("none", None) => { Ok(Compression::None) }
+            ("zlib", None) => { Ok(Compression::Zlib { level: flate2::Compression::default().level() }) }
+            ("zlib", Some(level)) if (0..=9).contains(&level) => { Ok(Compression::Zlib { level: level as u32 }) }
+            ("zstd", None) => { Ok(Compression::Zstd { level: zstd::DEFAULT_COMPRESSION_LEVEL }) }
+            ("zstd", Some(level)) if zstd::compression_level_range().contains(&level) => { Ok(Compression::Zstd { level }) }
+            ("lz4", None) => { Ok(Compression::Lz4) }
+            ("none" | "lz4", Some(level)) => { Err(anyhow!("Compression {} has no levels, level: {}", codec, level)) }
+            ("zlib" | "zstd", Some(level)) => { Err(anyhow!("Invalid {} compression level: {}", codec, level)) }
+            _ => { Err(anyhow!("Unknown compression: {}", codec)) }
+        }
+    }
+
+    /// Optional header feature announcing blobs compressed with a codec that is not supported by
+    /// all readers
+    pub(crate) fn optional_feature(&self) -> Option<&'static str> {
+        match self {
+            Compression::Zstd { .. } => { Some("Blob.zstd_data") }
+            Compression::Lz4 => { Some("Blob.lz4_data") }
+            Compression::None | Compression::Zlib { .. } => { None }
+        }
+    }
+
+    /// The header is compressed with zlib when the data blobs use a codec not supported by all
+    /// readers, so that such readers can read the header and report the optional feature.
+    fn header(&self) -> Compression {
+        match self {
+            Compression::Zstd { .. } | Compression::Lz4 => { Compression::Zlib { level: flate2::Compression::default().level() } }
+            compression => { *compression }
+        }
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Blob, anyhow::Error> {
+        let mut blob = Blob {
+            raw_size: Some(data.len() as i32),
+            ..Default::default()
+        };
+        match self {
+            Compression::None => {
+                blob.raw_size = None;
+                blob.raw = Some(data);
+            }
+            Compression::Zlib { level } => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(*level));
+                encoder.write_all(&data)?;
+                blob.zlib_data = Some(encoder.finish()?);
+            }
+            Compression::Zstd { level } => {
+                blob.zstd_data = Some(zstd::bulk::compress(&data, *level)?);
+            }
+            Compression::Lz4 => {
+                blob.lz4_data = Some(lz4_flex::block::compress(&data));
+            }
+        }
+        Ok(blob)
+    }
+}
+
+type ElementBlock = (usize, Vec<Element>);
+type EncodedBlob = (usize, Result<Vec<u8>, anyhow::Error>);
+
+/// Writer of OSM PBF files that encodes and compresses the primitive blocks in parallel
+///
+/// The elements are batched into blocks of up to [BLOCK_SIZE] elements of the same type, in the
+/// order they are written. The blocks are encoded and compressed on a pool of worker threads and
+/// written in the original order by a writer thread, so the Sort.Type_then_ID order of the input
+/// is kept. At most [ENCODE_AHEAD_BLOCKS] blocks per job are dispatched ahead of the next blob
+/// written, so the blobs encoded ahead of a slow one are bounded.
+pub(crate) struct ParallelPbfWriter {
+    info: FileInfo,
+    compression: Compression,
+    block: Vec<Element>,
+    next_index: usize,
+    written: Arc<OrderWindow>,
+    window: usize,
+    sender: Option<SyncSender<ElementBlock>>,
+    blob_sender: Option<SyncSender<EncodedBlob>>,
+    workers: Vec<JoinHandle<()>>,
+    writer: Option<JoinHandle<Result<(), anyhow::Error>>>,
+}
+
+impl ParallelPbfWriter {
+    /// Create a writer to the output path, or to stdout when the path is `-`, encoding and
+    /// compressing the blocks on `jobs` threads. The optional feature of the compression, if any,
+    /// is added to the header.
+    pub(crate) fn new(output_path: &PathBuf, mut info: FileInfo, compression: Compression, jobs: usize) -> Result<ParallelPbfWriter, anyhow::Error> {
+        if let Some(feature) = compression.optional_feature() {
+            let mut optional_features = info.optional_features().clone();
+            optional_features.push(feature.to_string());
+            info.with_optional_features(&optional_features);
+        }
+        let output: Box<dyn Write + Send> = if is_stream(output_path) {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(
+                File::create(output_path)
+                    .with_context(|| anyhow!("path: {}", output_path.display()))?
+            )
+        };
+        let jobs = jobs.max(1);
+        let (sender, receiver) = sync_channel::<ElementBlock>(jobs * 2);
+        let (blob_sender, blob_receiver) = sync_channel::<EncodedBlob>(jobs * 4);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let history = info.required("HistoricalInformation");
+        let workers = (0..jobs)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let blob_sender = blob_sender.clone();
+                std::thread::spawn(move || encode_worker(receiver, blob_sender, history, compression))
+            })
+            .collect();
+        let written = Arc::new(OrderWindow::new());
+        let writer_written = written.clone();
+        let writer = std::thread::spawn(move || write_blobs(BufWriter::new(output), blob_receiver, writer_written));
+        Ok(
+            ParallelPbfWriter {
+                info,
+                compression,
+                block: Vec::with_capacity(BLOCK_SIZE),
+                next_index: 1,
+                written,
+                window: jobs * ENCODE_AHEAD_BLOCKS,
+                sender: Some(sender),
+                blob_sender: Some(blob_sender),
+                workers,
+                writer: Some(writer),
+            }
+        )
+    }
+
+    fn flush_block(&mut self) -> Result<(), anyhow::Error> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_SIZE));
+        let sender = self.sender.as_ref().ok_or(anyhow!("Writer is closed"))?;
+        if !self.written.wait(self.next_index, self.window) || sender.send((self.next_index, block)).is_err() {
+            // the workers are gone because the writer thread stopped, report the reason
+            self.join()?;
+            return Err(anyhow!("PBF encoding workers stopped unexpectedly"));
+        }
+        self.next_index += 1;
+        Ok(())
+    }
+
+    fn join(&mut self) -> Result<(), anyhow::Error> {
+        self.sender = None;
+        self.blob_sender = None;
+        for worker in self.workers.drain(..) {
+            worker.join().map_err(|_| anyhow!("PBF encoding worker panicked"))?;
+        }
+        match self.writer.take() {
+            Some(writer) => {
+                writer.join().map_err(|_| anyhow!("PBF writer panicked"))?
+            }
+            None => {
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ElementWriter for ParallelPbfWriter {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        let header = encode_header(&self.info, self.compression)?;
+        let blob_sender = self.blob_sender.as_ref().ok_or(anyhow!("Writer is closed"))?;
+        blob_sender.send((0, Ok(header)))
+            .map_err(|_| anyhow!("PBF writer stopped unexpectedly"))
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        if let Element::Sentinel = element {
+            return Ok(());
+        }
+        let same_type = self.block.first()
+            .map(|first| std::mem::discriminant(first) == std::mem::discriminant(&element))
+            .unwrap_or(true);
+        if !same_type || self.block.len() >= BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        self.block.push(element);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        self.flush_block()?;
+        self.join()
+    }
+}
+
+fn encode_worker(receiver: Arc<Mutex<Receiver<ElementBlock>>>, sender: SyncSender<EncodedBlob>, history: bool, compression: Compression) {
+    loop {
+        let block = match receiver.lock() {
+            Ok(receiver) => { receiver.recv() }
+            Err(_) => { return; }
+        };
+        match block {
+            Ok((index, elements)) => {
+                let blob = encode_block(elements, history).and_then(|block| frame_blob("OSMData", block, compression));
+                if sender.send((index, blob)).is_err() {
+                    // the writer thread stopped
+                    return;
+                }
+            }
+            Err(_) => {
+                // the writer was closed
+                return;
+            }
+        }
+    }
+}
+
+/// Write the framed blobs in the order of their index, the header first, advancing the window of
+/// the blocks dispatched for encoding
+fn write_blobs(output: BufWriter<Box<dyn Write + Send>>, receiver: Receiver<EncodedBlob>, written: Arc<OrderWindow>) -> Result<(), anyhow::Error> {
+    let result = write_ordered_blobs(output, receiver, &written);
+    // release the writer if it waits for the window after a failure
+    written.advance(usize::MAX);
+    result
+}
+
+fn write_ordered_blobs(mut output: BufWriter<Box<dyn Write + Send>>, receiver: Receiver<EncodedBlob>, written: &OrderWindow) -> Result<(), anyhow::Error> {
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    for (index, blob) in receiver {
+        pending.insert(index, blob);
+        while let Some(blob) = pending.remove(&next_index) {
+            output.write_all(&blob?)?;
+            next_index += 1;
+            written.advance(next_index);
+        }
+    }
+    if !pending.is_empty() {
+        return Err(anyhow!("PBF blocks missing from index {}", next_index));
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn encode_header(info: &FileInfo, compression: Compression) -> Result<Vec<u8>, anyhow::Error> {
+    let header = HeaderBlock {
+        bbox: info.bounding_box().as_ref().map(|bounding_box| {
+            HeaderBBox {
+                left: (bounding_box.left() * 1e9).round() as i64,
+                right: (bounding_box.right() * 1e9).round() as i64,
+                top: (bounding_box.top() * 1e9).round() as i64,
+                bottom: (bounding_box.bottom() * 1e9).round() as i64,
+            }
+        }),
+        required_features: info.required_features().clone(),
+        optional_features: info.optional_features().clone(),
+        writingprogram: info.writingprogram().clone(),
+        source: info.source().clone(),
+        osmosis_replication_timestamp: *info.osmosis_replication_timestamp(),
+        osmosis_replication_sequence_number: *info.osmosis_replication_sequence_number(),
+        osmosis_replication_base_url: info.osmosis_replication_base_url().clone(),
+    };
+    frame_blob("OSMHeader", header.encode_to_vec(), compression.header())
+}
+
+/// Compress the block and prepend the blob header and its length
+fn frame_blob(blob_type: &str, data: Vec<u8>, compression: Compression) -> Result<Vec<u8>, anyhow::Error> {
+    let blob = compression.compress(data)?.encode_to_vec();
+    let blob_header = BlobHeader {
+        r#type: blob_type.to_string(),
+        datasize: blob.len() as i32,
+    }.encode_to_vec();
+    let mut framed = Vec::with_capacity(4 + blob_header.len() + blob.len());
+    framed.extend_from_slice(&(blob_header.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&blob_header);
+    framed.extend_from_slice(&blob);
+    Ok(framed)
+}
+
+/// String table of a block. The string at index 0 is reserved as the delimiter of the keys and
+/// values of dense nodes.
+struct StringTableBuilder {
+    strings: Vec<Vec<u8>>,
+    index: HashMap<String, u32>,
+}
+
+impl StringTableBuilder {
+    fn new() -> StringTableBuilder {
+        StringTableBuilder {
+            strings: vec![Vec::new()],
+            index: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        match self.index.get(s) {
+            Some(i) => { *i }
+            None => {
+                let i = self.strings.len() as u32;
+                self.strings.push(s.as_bytes().to_vec());
+                self.index.insert(s.to_string(), i);
+                i
+            }
+        }
+    }
+
+    fn build(self) -> StringTable {
+        StringTable { s: self.strings }
+    }
+}
+
+/// Encode a block of elements of the same type as a primitive block with a single group
+fn encode_block(elements: Vec<Element>, history: bool) -> Result<Vec<u8>, anyhow::Error> {
+    let mut strings = StringTableBuilder::new();
+    let mut group = PrimitiveGroup::default();
+    let mut dense = DenseNodes::default();
+    let mut dense_info = DenseInfo::default();
+    let mut last = [0_i64; 7];
+    for element in elements {
+        match element {
+            Element::Node { node } => {
+                let user_sid = strings.add(node.user()) as i64;
+                let values = [
+                    node.id(),
+                    node.coordinate().lat7(),
+                    node.coordinate().lon7(),
+                    node.timestamp() / DATE_GRANULARITY as i64,
+                    node.changeset(),
+                    node.uid() as i64,
+                    user_sid,
+                ];
+                let deltas: Vec<i64> = values.iter().zip(last.iter()).map(|(value, last)| value - last).collect();
+                last = values;
+                dense.id.push(deltas[0]);
+                dense.lat.push(deltas[1]);
+                dense.lon.push(deltas[2]);
+                dense_info.version.push(node.version());
+                dense_info.timestamp.push(deltas[3]);
+                dense_info.changeset.push(deltas[4]);
+                dense_info.uid.push(deltas[5] as i32);
+                dense_info.user_sid.push(deltas[6] as i32);
+                if history {
+                    dense_info.visible.push(node.visible());
+                }
+                for tag in node.tags() {
+                    dense.keys_vals.push(strings.add(tag.k()) as i32);
+                    dense.keys_vals.push(strings.add(tag.v()) as i32);
+                }
+                dense.keys_vals.push(0);
+            }
+            Element::Way { way } => {
+                let (keys, vals) = encode_tags(way.tags(), &mut strings);
+                let info = encode_info(way.version(), way.timestamp(), way.changeset(), way.uid(), way.user(), history.then_some(way.visible()), &mut strings);
+                let mut last_ref = 0;
+                let refs = way.refs().iter()
+                    .map(|r| {
+                        let delta = r - last_ref;
+                        last_ref = *r;
+                        delta
+                    })
+                    .collect();
+                group.ways.push(Way { id: way.id(), keys, vals, info: Some(info), refs });
+            }
+            Element::Relation { relation } => {
+                let (keys, vals) = encode_tags(relation.tags(), &mut strings);
+                let info = encode_info(relation.version(), relation.timestamp(), relation.changeset(), relation.uid(), relation.user(), history.then_some(relation.visible()), &mut strings);
+                let mut encoded = Relation { id: relation.id(), keys, vals, info: Some(info), ..Default::default() };
+                let mut last_id = 0;
+                for member in relation.members() {
+                    let (member_type, data) = match member {
+                        Member::Node { member } => { (0, member) }
+                        Member::Way { member } => { (1, member) }
+                        Member::Relation { member } => { (2, member) }
+                    };
+                    encoded.roles_sid.push(strings.add(data.role()) as i32);
+                    encoded.memids.push(data.id() - last_id);
+                    encoded.types.push(member_type);
+                    last_id = data.id();
+                }
+                group.relations.push(encoded);
+            }
+            Element::Sentinel => {}
+        }
+    }
+    if !dense.id.is_empty() {
+        dense.denseinfo = Some(dense_info);
+        group.dense = Some(dense);
+    }
+    let block = PrimitiveBlock {
+        stringtable: strings.build(),
+        primitivegroup: vec![group],
+        granularity: Some(GRANULARITY),
+        date_granularity: Some(DATE_GRANULARITY),
+    };
+    Ok(block.encode_to_vec())
+}
+
+fn encode_tags(tags: &[Tag], strings: &mut StringTableBuilder) -> (Vec<u32>, Vec<u32>) {
+    tags.iter()
+        .map(|tag| (strings.add(tag.k()), strings.add(tag.v())))
+        .unzip()
+}
+
+fn encode_info(version: i32, timestamp: i64, changeset: i64, uid: i32, user: &str, visible: Option<bool>, strings: &mut StringTableBuilder) -> Info {
+    Info {
+        version: Some(version),
+        timestamp: Some(timestamp / DATE_GRANULARITY as i64),
+        changeset: Some(changeset),
+        uid: Some(uid),
+        user_sid: Some(strings.add(user)),
+        visible,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use flate2::read::ZlibDecoder;
+    use osm_io::osm::pbf::reader::Reader as PbfReader;
+    use prost::Message;
+
+    use crate::output::pbf::{Compression, frame_blob, ParallelPbfWriter};
+    use crate::output::writer::ElementWriter;
+    use crate::pbf::proto::{Blob, BlobHeader};
+
+    #[test]
+    fn test_parallel_write_read() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/niue-230612.osm.pbf");
+        let output_path = std::env::temp_dir().join("osm-admin-test-parallel-write-read.osm.pbf");
+        let reader = PbfReader::new(&input_path)?;
+        let mut writer = ParallelPbfWriter::new(&output_path, reader.info().clone(), Compression::Zlib { level: 9 }, 4)?;
+        writer.write_header()?;
+        for element in reader.elements()? {
+            writer.write_element(element)?;
+        }
+        writer.close()?;
+
+        let written = PbfReader::new(&output_path)?;
+        assert_eq!(format!("{:?}", written.info()), format!("{:?}", reader.info()));
+        let elements: Vec<_> = written.elements()?.collect();
+        let expected: Vec<_> = reader.elements()?.collect();
+        fs::remove_file(&output_path)?;
+        assert_eq!(elements.len(), expected.len());
+        assert!(elements == expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression() -> Result<(), anyhow::Error> {
+        let data = b"osm-admin ".repeat(1000);
+        for compression in [Compression::new("none", None)?, Compression::new("zlib", Some(1))?, Compression::new("zstd", Some(19))?, Compression::new("lz4", None)?] {
+            let framed = frame_blob("OSMData", data.clone(), compression)?;
+            let header_len = u32::from_be_bytes(framed[0..4].try_into()?) as usize;
+            let blob_header = BlobHeader::decode(&framed[4..4 + header_len])?;
+            assert_eq!(blob_header.r#type, "OSMData");
+            assert_eq!(blob_header.datasize as usize, framed.len() - 4 - header_len);
+            let blob = Blob::decode(&framed[4 + header_len..])?;
+            let decompressed = match compression {
+                Compression::None => { blob.raw.unwrap() }
+                Compression::Zlib { .. } => {
+                    let mut decompressed = Vec::new();
+                    ZlibDecoder::new(blob.zlib_data.unwrap().as_slice()).read_to_end(&mut decompressed)?;
+                    decompressed
+                }
+                Compression::Zstd { .. } => { zstd::bulk::decompress(&blob.zstd_data.unwrap(), data.len())? }
+                Compression::Lz4 => { lz4_flex::block::decompress(&blob.lz4_data.unwrap(), blob.raw_size.unwrap() as usize)? }
+            };
+            assert_eq!(decompressed, data);
+        }
+        assert!(Compression::new("zlib", Some(10)).is_err());
+        assert!(Compression::new("lz4", Some(1)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use osm_io::osm::apidb_dump::write::writer::Writer as ApiDbDumpWriter;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::pbf::file_info::FileInfo;
+
+use crate::output::pbf::{Compression, ParallelPbfWriter};
+
+/// Writer of an element stream, either to a file in one of the export output formats or to the
+/// database on import
+pub(crate) trait ElementWriter {
+    fn write_header(&mut self) -> Result<(), anyhow::Error>;
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error>;
+    fn close(&mut self) -> Result<(), anyhow::Error>;
+}
+
+impl ElementWriter for ApiDbDumpWriter {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        ApiDbDumpWriter::write_element(self, element)
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        ApiDbDumpWriter::close(self)
+    }
+}
+
+/// Create a writer for the output format, one of pbf, osm (OSM XML) or opl. PBF blocks are encoded
+/// and compressed with `compression` on `jobs` threads. OSM XML and OPL files are compressed when the path ends with
+/// .gz or .bz2. The output path `-` writes to stdout.
+pub(crate) fn create_writer(output_path: &PathBuf, output_format: &str, info: FileInfo, compression: Compression, jobs: usize) -> Result<Box<dyn ElementWriter>, anyhow::Error> {
+    match output_format {
+        "pbf" => {
+            Ok(Box::new(ParallelPbfWriter::new(output_path, info, compression, jobs)?))
+        }
+        "osm" => {
+            Ok(Box::new(crate::xml::writer::Writer::new(output_path, info)?))
+        }
+        "opl" => {
+            Ok(Box::new(crate::opl::writer::Writer::new(output_path)?))
+        }
+        _ => {
+            Err(anyhow!("Unsupported output format: {}", output_format))
+        }
+    }
+}
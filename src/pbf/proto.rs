@@ -0,0 +1,166 @@
+//! The subset of the OSM PBF fileformat.proto and osmformat.proto messages used to read the header
+//! of a stream and to encode blocks. Changesets and the deprecated lzma and bzip2 compression
+//! formats are omitted. The osmosis replication fields of the header are not exposed by osmpbf.
+
+use prost::Message;
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct BlobHeader {
+    #[prost(string, required, tag = "1")]
+    pub(crate) r#type: String,
+    #[prost(int32, required, tag = "3")]
+    pub(crate) datasize: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Blob {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub(crate) raw: Option<Vec<u8>>,
+    #[prost(int32, optional, tag = "2")]
+    pub(crate) raw_size: Option<i32>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    pub(crate) zlib_data: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "6")]
+    pub(crate) lz4_data: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "7")]
+    pub(crate) zstd_data: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct HeaderBlock {
+    #[prost(message, optional, tag = "1")]
+    pub(crate) bbox: Option<HeaderBBox>,
+    #[prost(string, repeated, tag = "4")]
+    pub(crate) required_features: Vec<String>,
+    #[prost(string, repeated, tag = "5")]
+    pub(crate) optional_features: Vec<String>,
+    #[prost(string, optional, tag = "16")]
+    pub(crate) writingprogram: Option<String>,
+    #[prost(string, optional, tag = "17")]
+    pub(crate) source: Option<String>,
+    #[prost(int64, optional, tag = "32")]
+    pub(crate) osmosis_replication_timestamp: Option<i64>,
+    #[prost(int64, optional, tag = "33")]
+    pub(crate) osmosis_replication_sequence_number: Option<i64>,
+    #[prost(string, optional, tag = "34")]
+    pub(crate) osmosis_replication_base_url: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct HeaderBBox {
+    #[prost(sint64, required, tag = "1")]
+    pub(crate) left: i64,
+    #[prost(sint64, required, tag = "2")]
+    pub(crate) right: i64,
+    #[prost(sint64, required, tag = "3")]
+    pub(crate) top: i64,
+    #[prost(sint64, required, tag = "4")]
+    pub(crate) bottom: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct PrimitiveBlock {
+    #[prost(message, required, tag = "1")]
+    pub(crate) stringtable: StringTable,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) primitivegroup: Vec<PrimitiveGroup>,
+    #[prost(int32, optional, tag = "17")]
+    pub(crate) granularity: Option<i32>,
+    #[prost(int32, optional, tag = "18")]
+    pub(crate) date_granularity: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct PrimitiveGroup {
+    #[prost(message, optional, tag = "2")]
+    pub(crate) dense: Option<DenseNodes>,
+    #[prost(message, repeated, tag = "3")]
+    pub(crate) ways: Vec<Way>,
+    #[prost(message, repeated, tag = "4")]
+    pub(crate) relations: Vec<Relation>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct StringTable {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub(crate) s: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Info {
+    #[prost(int32, optional, tag = "1")]
+    pub(crate) version: Option<i32>,
+    #[prost(int64, optional, tag = "2")]
+    pub(crate) timestamp: Option<i64>,
+    #[prost(int64, optional, tag = "3")]
+    pub(crate) changeset: Option<i64>,
+    #[prost(int32, optional, tag = "4")]
+    pub(crate) uid: Option<i32>,
+    #[prost(uint32, optional, tag = "5")]
+    pub(crate) user_sid: Option<u32>,
+    #[prost(bool, optional, tag = "6")]
+    pub(crate) visible: Option<bool>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct DenseInfo {
+    #[prost(int32, repeated, tag = "1")]
+    pub(crate) version: Vec<i32>,
+    #[prost(sint64, repeated, tag = "2")]
+    pub(crate) timestamp: Vec<i64>,
+    #[prost(sint64, repeated, tag = "3")]
+    pub(crate) changeset: Vec<i64>,
+    #[prost(sint32, repeated, tag = "4")]
+    pub(crate) uid: Vec<i32>,
+    #[prost(sint32, repeated, tag = "5")]
+    pub(crate) user_sid: Vec<i32>,
+    #[prost(bool, repeated, tag = "6")]
+    pub(crate) visible: Vec<bool>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct DenseNodes {
+    #[prost(sint64, repeated, tag = "1")]
+    pub(crate) id: Vec<i64>,
+    #[prost(message, optional, tag = "5")]
+    pub(crate) denseinfo: Option<DenseInfo>,
+    #[prost(sint64, repeated, tag = "8")]
+    pub(crate) lat: Vec<i64>,
+    #[prost(sint64, repeated, tag = "9")]
+    pub(crate) lon: Vec<i64>,
+    #[prost(int32, repeated, tag = "10")]
+    pub(crate) keys_vals: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Way {
+    #[prost(int64, required, tag = "1")]
+    pub(crate) id: i64,
+    #[prost(uint32, repeated, tag = "2")]
+    pub(crate) keys: Vec<u32>,
+    #[prost(uint32, repeated, tag = "3")]
+    pub(crate) vals: Vec<u32>,
+    #[prost(message, optional, tag = "4")]
+    pub(crate) info: Option<Info>,
+    #[prost(sint64, repeated, tag = "8")]
+    pub(crate) refs: Vec<i64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Relation {
+    #[prost(int64, required, tag = "1")]
+    pub(crate) id: i64,
+    #[prost(uint32, repeated, tag = "2")]
+    pub(crate) keys: Vec<u32>,
+    #[prost(uint32, repeated, tag = "3")]
+    pub(crate) vals: Vec<u32>,
+    #[prost(message, optional, tag = "4")]
+    pub(crate) info: Option<Info>,
+    #[prost(int32, repeated, tag = "8")]
+    pub(crate) roles_sid: Vec<i32>,
+    #[prost(sint64, repeated, tag = "9")]
+    pub(crate) memids: Vec<i64>,
+    /// The member types, 0 for node, 1 for way and 2 for relation
+    #[prost(int32, repeated, tag = "10")]
+    pub(crate) types: Vec<i32>,
+}
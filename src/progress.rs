@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::OnceLock;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use chrono::{SecondsFormat, Utc};
+use json::{object, JsonValue};
+
+use crate::metrics;
+
+/// Interval between the updates of a [Ticker]
+const TICK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Format of the progress reports
+///
+/// * text - human readable log lines
+/// * json - one JSON event per line on stderr, for orchestration systems
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ProgressFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => { Ok(ProgressFormat::Text) }
+            "json" => { Ok(ProgressFormat::Json) }
+            _ => { Err(anyhow!("Unknown progress format: {}", s)) }
+        }
+    }
+}
+
+static PROGRESS_FORMAT: OnceLock<ProgressFormat> = OnceLock::new();
+
+/// Set the format of the progress reports. Can be set once, before any progress is reported,
+/// the default is text.
+pub fn set_progress_format(format: ProgressFormat) -> Result<(), anyhow::Error> {
+    PROGRESS_FORMAT.set(format)
+        .map_err(|_| anyhow!("Progress format is already set"))
+}
+
+pub(crate) fn progress_format() -> ProgressFormat {
+    *PROGRESS_FORMAT.get().unwrap_or(&ProgressFormat::Text)
+}
+
+/// Phase of an import or export reported in the progress events
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Phase {
+    Counting,
+    DumpGeneration,
+    CopyLoad,
+    Restore,
+    Dump,
+    Sorting,
+    Write,
+}
+
+impl Phase {
+    pub(crate) const ALL: [Phase; 7] = [
+        Phase::Counting,
+        Phase::DumpGeneration,
+        Phase::CopyLoad,
+        Phase::Restore,
+        Phase::Dump,
+        Phase::Sorting,
+        Phase::Write,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Phase::Counting => { "counting" }
+            Phase::DumpGeneration => { "dump-generation" }
+            Phase::CopyLoad => { "copy-load" }
+            Phase::Restore => { "restore" }
+            Phase::Dump => { "dump" }
+            Phase::Sorting => { "sorting" }
+            Phase::Write => { "write" }
+        }
+    }
+}
+
+/// Progress of one phase, emitting start, progress and finish events in the json progress format.
+/// Nothing is emitted in the text format, where the progress is logged by the callers. The phase
+/// is also reported in the metrics, if enabled.
+pub(crate) struct PhaseProgress {
+    phase: Phase,
+    started: Instant,
+}
+
+impl PhaseProgress {
+    pub(crate) fn start(phase: Phase) -> PhaseProgress {
+        metrics::set_phase(phase);
+        let progress = PhaseProgress {
+            phase,
+            started: Instant::now(),
+        };
+        progress.emit(|| event("start", phase, Duration::ZERO, None, None, None));
+        progress
+    }
+
+    /// Report the number of processed objects out of the total, if known, and the disk usage of
+    /// the output path, if any
+    pub(crate) fn progress(&self, processed: u64, total: Option<u64>, output_path: Option<&PathBuf>) {
+        self.emit(|| event("progress", self.phase, self.started.elapsed(), Some(processed), total, disk_usage(output_path)));
+    }
+
+    pub(crate) fn finish(&self, processed: Option<u64>, output_path: Option<&PathBuf>) {
+        metrics::update(output_path, true);
+        self.emit(|| event("finish", self.phase, self.started.elapsed(), processed, processed, disk_usage(output_path)));
+    }
+
+    /// Emit progress events with the elapsed time and the disk usage of the output path, if any,
+    /// and update the metrics, on a background thread until the returned ticker is dropped. For
+    /// phases run by external processes, such as pg_restore or pg_dump, which report no progress
+    /// of their own.
+    pub(crate) fn ticker(&self, output_path: Option<&Path>) -> Ticker {
+        let output_path = output_path.map(|path| path.to_path_buf());
+        let progress = PhaseProgress {
+            phase: self.phase,
+            started: self.started,
+        };
+        let (sender, receiver) = channel::<()>();
+        let handle = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(TICK_INTERVAL) {
+                progress.emit(|| event("progress", progress.phase, progress.started.elapsed(), None, None, disk_usage(output_path.as_ref())));
+                metrics::update(output_path.as_ref(), false);
+            }
+        });
+        Ticker {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Finish the counting phase with the per type counts
+    pub(crate) fn finish_counting(&self, nodes: i64, ways: i64, relations: i64) {
+        metrics::update(None, true);
+        self.emit(|| {
+            let mut value = event("finish", self.phase, self.started.elapsed(), None, None, None);
+            value["nodes"] = nodes.into();
+            value["ways"] = ways.into();
+            value["relations"] = relations.into();
+            value["total"] = (nodes + ways + relations).into();
+            value
+        });
+    }
+
+    fn emit(&self, event: impl FnOnce() -> JsonValue) {
+        if progress_format() == ProgressFormat::Json {
+            eprintln!("{}", event().dump());
+        }
+    }
+}
+
+/// Background updates of a phase, stopped when dropped
+pub(crate) struct Ticker {
+    sender: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn disk_usage(path: Option<&PathBuf>) -> Option<u64> {
+    path.and_then(|path| benchmark_rs::disk_usage::disk_usage(path).ok())
+}
+
+/// Progress event with the rate in objects per second and, when the total is known, the
+/// percentage and the estimated time to finish the phase
+fn event(
+    event: &str,
+    phase: Phase,
+    elapsed: Duration,
+    processed: Option<u64>,
+    total: Option<u64>,
+    disk_bytes: Option<u64>,
+) -> JsonValue {
+    let elapsed_seconds = elapsed.as_secs_f64();
+    let mut value = object! {
+        event: event,
+        phase: phase.name(),
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        elapsed_seconds: (elapsed_seconds * 1000.0).round() / 1000.0,
+    };
+    if let Some(processed) = processed {
+        value["processed"] = processed.into();
+        let rate = if elapsed_seconds > 0.0 { processed as f64 / elapsed_seconds } else { 0.0 };
+        value["rate"] = rate.round().into();
+        if let Some(total) = total.filter(|total| *total > 0) {
+            value["total"] = total.into();
+            value["percentage"] = ((processed as f64 / total as f64 * 10000.0).round() / 100.0).into();
+            if rate > 0.0 {
+                value["eta_seconds"] = (total.saturating_sub(processed) as f64 / rate).round().into();
+            }
+        }
+    }
+    if let Some(disk_bytes) = disk_bytes {
+        value["disk_bytes"] = disk_bytes.into();
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::progress::{event, Phase, PhaseProgress, TICK_INTERVAL};
+
+    #[test]
+    fn test_event() {
+        let value = event("progress", Phase::DumpGeneration, Duration::from_secs(10), Some(250), Some(1000), Some(4096));
+        assert_eq!(value["event"], "progress");
+        assert_eq!(value["phase"], "dump-generation");
+        assert_eq!(value["rate"], 25);
+        assert_eq!(value["percentage"], 25);
+        assert_eq!(value["eta_seconds"], 30);
+        assert_eq!(value["disk_bytes"], 4096);
+        assert_eq!(value["elapsed_seconds"], 10);
+
+        let value = event("progress", Phase::Write, Duration::from_secs(10), Some(250), None, None);
+        assert!(value["percentage"].is_null());
+        assert!(value["eta_seconds"].is_null());
+        assert!(value["disk_bytes"].is_null());
+
+        let value = event("start", Phase::Counting, Duration::ZERO, None, None, None);
+        assert!(value["processed"].is_null());
+        assert!(value["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_ticker_stops_when_dropped() {
+        let progress = PhaseProgress::start(Phase::Restore);
+        let started = Instant::now();
+        let ticker = progress.ticker(None);
+        drop(ticker);
+        assert!(started.elapsed() < TICK_INTERVAL);
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+
+/// Path of the osmChange file of a sequence number in an osmosis replication directory,
+/// as in 005/520/914.osc.gz for sequence number 5520914
+pub(crate) fn change_path(replication_path: &Path, sequence_number: i64) -> PathBuf {
+    sequence_path(replication_path, sequence_number, "osc.gz")
+}
+
+/// Path of the state file of a sequence number in an osmosis replication directory,
+/// as in 005/520/914.state.txt for sequence number 5520914
+pub(crate) fn state_path(replication_path: &Path, sequence_number: i64) -> PathBuf {
+    sequence_path(replication_path, sequence_number, "state.txt")
+}
+
+fn sequence_path(replication_path: &Path, sequence_number: i64, extension: &str) -> PathBuf {
+    let digits = format!("{:09}", sequence_number);
+    replication_path
+        .join(&digits[0..3])
+        .join(&digits[3..6])
+        .join(format!("{}.{}", &digits[6..9], extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::replication::directory::{change_path, state_path};
+
+    #[test]
+    fn test_sequence_paths() {
+        let replication_path = PathBuf::from("/var/lib/osm/replication/minute");
+        assert_eq!(
+            change_path(&replication_path, 5520914),
+            PathBuf::from("/var/lib/osm/replication/minute/005/520/914.osc.gz")
+        );
+        assert_eq!(
+            state_path(&replication_path, 42),
+            PathBuf::from("/var/lib/osm/replication/minute/000/000/042.state.txt")
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// Replication state as found in the osmosis state.txt files
+///
+/// The states of the published changes also record the database transaction snapshot of the
+/// change file, the changes of the next file are the ones committed after it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct State {
+    sequence_number: i64,
+    timestamp: DateTime<Utc>,
+    transaction_snapshot: Option<String>,
+}
+
+impl State {
+    pub(crate) fn new(sequence_number: i64, timestamp: DateTime<Utc>) -> State {
+        State {
+            sequence_number,
+            timestamp,
+            transaction_snapshot: None,
+        }
+    }
+
+    pub(crate) fn with_transaction_snapshot(mut self, transaction_snapshot: String) -> State {
+        self.transaction_snapshot = Some(transaction_snapshot);
+        self
+    }
+
+    pub(crate) fn from_path(path: &PathBuf) -> Result<State, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| anyhow!("path: {}", path.display()))?;
+        State::from_str(&content)
+            .with_context(|| anyhow!("path: {}", path.display()))
+    }
+
+    pub(crate) fn sequence_number(&self) -> i64 {
+        self.sequence_number
+    }
+
+    pub(crate) fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
+    pub(crate) fn transaction_snapshot(&self) -> Option<&String> {
+        self.transaction_snapshot.as_ref()
+    }
+}
+
+impl FromStr for State {
+    type Err = anyhow::Error;
+
+    /// Parse the java properties format of state.txt, as in:
+    /// ```text
+    /// #Mon Jun 12 10:01:03 UTC 2023
+    /// sequenceNumber=5520914
+    /// timestamp=2023-06-12T10\:00\:02Z
+    /// ```
+    /// and the txnSnapshot of the published changes
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sequence_number = None;
+        let mut timestamp = None;
+        let mut transaction_snapshot = None;
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or(anyhow!("Malformed replication state line: {}", line))?;
+            let value = value.trim().replace('\\', "");
+            match key.trim() {
+                "sequenceNumber" => {
+                    sequence_number = Some(i64::from_str(&value)?);
+                }
+                "timestamp" => {
+                    timestamp = Some(DateTime::<Utc>::from(DateTime::parse_from_rfc3339(&value)?));
+                }
+                "txnSnapshot" => {
+                    transaction_snapshot = Some(value);
+                }
+                _ => {}
+            }
+        }
+        let state = State::new(
+            sequence_number.ok_or(anyhow!("Missing sequenceNumber in replication state"))?,
+            timestamp.ok_or(anyhow!("Missing timestamp in replication state"))?,
+        );
+        match transaction_snapshot {
+            None => { Ok(state) }
+            Some(transaction_snapshot) => { Ok(state.with_transaction_snapshot(transaction_snapshot)) }
+        }
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "#{}", Utc::now().format("%a %b %d %H:%M:%S UTC %Y"))?;
+        writeln!(f, "sequenceNumber={}", self.sequence_number)?;
+        writeln!(f, "timestamp={}", self.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true).replace(':', "\\:"))?;
+        if let Some(transaction_snapshot) = &self.transaction_snapshot {
+            writeln!(f, "txnSnapshot={}", transaction_snapshot.replace(':', "\\:"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::replication::state::State;
+
+    #[test]
+    fn test_parse_state() -> Result<(), anyhow::Error> {
+        let state = State::from_str("#Mon Jun 12 10:01:03 UTC 2023\nsequenceNumber=5520914\ntimestamp=2023-06-12T10\\:00\\:02Z\n")?;
+        assert_eq!(state.sequence_number(), 5520914);
+        assert_eq!(state.timestamp().timestamp(), 1686564002);
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_round_trip() -> Result<(), anyhow::Error> {
+        let state = State::from_str("sequenceNumber=42\ntimestamp=2023-06-12T10\\:00\\:02Z")?;
+        assert_eq!(State::from_str(&state.to_string())?, state);
+
+        let state = state.with_transaction_snapshot("1000:1005:1001,1003".to_string());
+        assert!(state.to_string().contains("txnSnapshot=1000\\:1005\\:1001,1003\n"));
+        assert_eq!(State::from_str(&state.to_string())?, state);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_missing_sequence_number() {
+        State::from_str("timestamp=2023-06-12T10\\:00\\:02Z").unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+
+/// SSL mode of the database connections, as the libpq sslmode
+///
+/// * disable - never use TLS
+/// * allow, prefer - use TLS if the server supports it, without verifying the server certificate
+/// * require - always use TLS. The server certificate is verified against the root certificate,
+///   as in verify-ca, only when a root certificate is available
+/// * verify-ca - always use TLS and verify that the server certificate is signed by the root
+///   certificate
+/// * verify-full - as verify-ca, and verify that the server host name matches the certificate
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl FromStr for SslMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => { Ok(SslMode::Disable) }
+            "allow" => { Ok(SslMode::Allow) }
+            "prefer" => { Ok(SslMode::Prefer) }
+            "require" => { Ok(SslMode::Require) }
+            "verify-ca" => { Ok(SslMode::VerifyCa) }
+            "verify-full" => { Ok(SslMode::VerifyFull) }
+            _ => { Err(anyhow!("Unknown SSL mode: {}", s)) }
+        }
+    }
+}
+
+impl SslMode {
+    /// The SSL mode of the in-process client, which only knows disable, prefer and require. The
+    /// certificate verification is done by the connector.
+    pub fn client_ssl_mode(&self) -> postgres::config::SslMode {
+        match self {
+            SslMode::Disable => { postgres::config::SslMode::Disable }
+            SslMode::Allow | SslMode::Prefer => { postgres::config::SslMode::Prefer }
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => { postgres::config::SslMode::Require }
+        }
+    }
+}
+
+/// The SSL mode of the database connections, PGSSLMODE or prefer as for libpq. The SSL settings
+/// are read from the same environment variables as pg_dump and pg_restore read them, so that the
+/// in-process client and the child processes connect alike.
+pub fn ssl_mode() -> Result<SslMode, anyhow::Error> {
+    match std::env::var("PGSSLMODE") {
+        Ok(ssl_mode) => { SslMode::from_str(&ssl_mode) }
+        Err(_) => { Ok(SslMode::Prefer) }
+    }
+}
+
+/// The file from the environment variable, or the default file in ~/.postgresql if it exists, as
+/// for libpq
+fn ssl_file(var: &str, default_name: &str) -> Option<PathBuf> {
+    match std::env::var_os(var) {
+        Some(path) => {
+            Some(PathBuf::from(path))
+        }
+        None => {
+            std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".postgresql").join(default_name))
+                .filter(|path| path.exists())
+        }
+    }
+}
+
+/// TLS connector of the in-process client for the SSL mode, with the root certificate of
+/// PGSSLROOTCERT and the client certificate and key of PGSSLCERT and PGSSLKEY
+pub fn connector() -> Result<MakeTlsConnector, anyhow::Error> {
+    let ssl_mode = ssl_mode()?;
+    let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+
+    let root_cert = ssl_file("PGSSLROOTCERT", "root.crt");
+    let verify = match (ssl_mode, &root_cert) {
+        (SslMode::VerifyCa | SslMode::VerifyFull, None) => {
+            return Err(anyhow!("SSL modes verify-ca and verify-full require a root certificate, provide --sslrootcert or ~/.postgresql/root.crt"));
+        }
+        (SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull, Some(root_cert)) => {
+            builder.set_ca_file(root_cert)
+                .map_err(|e| anyhow!("Failed loading root certificate {:?}: {}", root_cert, e))?;
+            true
+        }
+        _ => { false }
+    };
+    if verify {
+        builder.set_verify(SslVerifyMode::PEER);
+    } else {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+
+    if let Some(cert) = ssl_file("PGSSLCERT", "postgresql.crt") {
+        let key = ssl_file("PGSSLKEY", "postgresql.key")
+            .ok_or_else(|| anyhow!("Client certificate {:?} requires a key, provide --sslkey or ~/.postgresql/postgresql.key", cert))?;
+        builder.set_certificate_chain_file(&cert)
+            .map_err(|e| anyhow!("Failed loading client certificate {:?}: {}", cert, e))?;
+        builder.set_private_key_file(&key, SslFiletype::PEM)
+            .map_err(|e| anyhow!("Failed loading client key {:?}: {}", key, e))?;
+        builder.check_private_key()
+            .map_err(|e| anyhow!("Client key {:?} does not match the certificate {:?}: {}", key, cert, e))?;
+    }
+
+    let mut connector = MakeTlsConnector::new(builder.build());
+    if ssl_mode != SslMode::VerifyFull {
+        connector.set_callback(|config, _| {
+            config.set_verify_hostname(false);
+            Ok(())
+        });
+    }
+    Ok(connector)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::tls::SslMode;
+
+    #[test]
+    fn test_ssl_mode() {
+        assert_eq!(SslMode::from_str("verify-full").unwrap(), SslMode::VerifyFull);
+        assert!(matches!(SslMode::from_str("allow").unwrap().client_ssl_mode(), postgres::config::SslMode::Prefer));
+        assert!(matches!(SslMode::from_str("verify-ca").unwrap().client_ssl_mode(), postgres::config::SslMode::Require));
+        assert!(SslMode::from_str("verify").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use json::{object, JsonValue};
+use md5::{Digest, Md5};
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::tag::Tag;
+
+/// Summary of one element type, computed the same way from an input file and from the database
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct TypeStats {
+    pub(crate) count: i64,
+    pub(crate) max_id: i64,
+    pub(crate) version_sum: i64,
+    pub(crate) tag_count: i64,
+    pub(crate) tag_hash: u64,
+}
+
+impl TypeStats {
+    fn add(&mut self, id: i64, version: i32, tags: &[Tag]) {
+        self.count += 1;
+        self.max_id = self.max_id.max(id);
+        self.version_sum += version as i64;
+        for tag in tags {
+            self.tag_count += 1;
+            self.tag_hash = self.tag_hash.wrapping_add(tag_hash(id, version, tag.k(), tag.v()));
+        }
+    }
+
+    /// Names of the fields that differ from the other stats
+    pub(crate) fn mismatches(&self, other: &TypeStats) -> Vec<&'static str> {
+        let mut mismatches = Vec::new();
+        if self.count != other.count {
+            mismatches.push("count");
+        }
+        if self.max_id != other.max_id {
+            mismatches.push("max_id");
+        }
+        if self.version_sum != other.version_sum {
+            mismatches.push("version_sum");
+        }
+        if self.tag_count != other.tag_count {
+            mismatches.push("tag_count");
+        }
+        if self.tag_hash != other.tag_hash {
+            mismatches.push("tag_hash");
+        }
+        mismatches
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            count: self.count,
+            max_id: self.max_id,
+            version_sum: self.version_sum,
+            tag_count: self.tag_count,
+            tag_hash: format!("{:016x}", self.tag_hash),
+        }
+    }
+}
+
+/// Summary of the nodes, ways and relations
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct Stats {
+    pub(crate) nodes: TypeStats,
+    pub(crate) ways: TypeStats,
+    pub(crate) relations: TypeStats,
+}
+
+impl Stats {
+    pub(crate) fn from_elements(elements: impl Iterator<Item=Result<Element, anyhow::Error>>) -> Result<Stats, anyhow::Error> {
+        let mut stats = Stats::default();
+        for element in elements {
+            match element? {
+                Element::Node { node } => {
+                    stats.nodes.add(node.id(), node.version(), node.tags());
+                }
+                Element::Way { way } => {
+                    stats.ways.add(way.id(), way.version(), way.tags());
+                }
+                Element::Relation { relation } => {
+                    stats.relations.add(relation.id(), relation.version(), relation.tags());
+                }
+                Element::Sentinel => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Hash of one tag of an element version, the first 8 bytes of the MD5 digest of
+/// `<id>:<version>:<key>=<value>` as a big endian integer. The tag hash of a type is the sum of
+/// the hashes of all its tags, modulo 2^64, so it does not depend on the order of the elements.
+/// [crate::db::stats] computes the same sum in SQL.
+pub(crate) fn tag_hash(id: i64, version: i32, k: &str, v: &str) -> u64 {
+    let digest = Md5::digest(format!("{}:{}:{}={}", id, version, k, v).as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[0..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Verification report comparing the stats of the input with the stats of the database.
+/// Returns the report and true if all the stats match.
+pub(crate) fn report(input: &str, database: &str, input_stats: &Stats, database_stats: &Stats) -> (JsonValue, bool) {
+    let mut matches = true;
+    let mut types = JsonValue::new_object();
+    for (name, input_type_stats, database_type_stats) in [
+        ("nodes", &input_stats.nodes, &database_stats.nodes),
+        ("ways", &input_stats.ways, &database_stats.ways),
+        ("relations", &input_stats.relations, &database_stats.relations),
+    ] {
+        let mismatches = input_type_stats.mismatches(database_type_stats);
+        matches = matches && mismatches.is_empty();
+        types[name] = object! {
+            input: input_type_stats.to_json(),
+            database: database_type_stats.to_json(),
+            mismatches: mismatches,
+        };
+    }
+    (
+        object! {
+            input: input,
+            database: database,
+            match: matches,
+            nodes: types["nodes"].clone(),
+            ways: types["ways"].clone(),
+            relations: types["relations"].clone(),
+        },
+        matches
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::verify::stats::{report, tag_hash, Stats};
+    use crate::xml::reader::Reader;
+
+    #[test]
+    fn test_input_stats() -> Result<(), anyhow::Error> {
+        // the first 16 hex digits of md5('1001:2:name=Niue')
+        assert_eq!(tag_hash(1001, 2, "name", "Niue"), 0xd8e5949b66de2b29);
+
+        let input_path = PathBuf::from("./tests/fixtures/small.osm");
+        let stats = Stats::from_elements(Reader::from_path(&input_path)?.map(|result| result.map(|(_, element)| element)))?;
+        assert_eq!(stats.nodes.count, 3);
+        assert_eq!(stats.nodes.max_id, 1002);
+        assert_eq!(stats.ways.count, 1);
+        assert_eq!(stats.relations.count, 1);
+
+        let (value, matches) = report("small.osm", "localhost", &stats, &stats);
+        assert!(matches);
+        assert_eq!(value["nodes"]["mismatches"].len(), 0);
+
+        let mut other = stats.clone();
+        other.ways.tag_hash = other.ways.tag_hash.wrapping_add(1);
+        let (value, matches) = report("small.osm", "localhost", &stats, &other);
+        assert!(!matches);
+        assert_eq!(value["ways"]["mismatches"][0], "tag_hash");
+        Ok(())
+    }
+}
@@ -0,0 +1,18 @@
+/// The osmChange action that encloses an element
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl Action {
+    pub(crate) fn from_tag_name(name: &[u8]) -> Option<Action> {
+        match name {
+            b"create" => { Some(Action::Create) }
+            b"modify" => { Some(Action::Modify) }
+            b"delete" => { Some(Action::Delete) }
+            _ => { None }
+        }
+    }
+}
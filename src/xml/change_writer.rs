@@ -0,0 +1,99 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use osm_io::osm::model::element::Element;
+use quick_xml::escape::escape;
+
+use crate::output::compression::CompressedFile;
+use crate::xml::action::Action;
+use crate::xml::writer::write_element;
+
+/// Writer of osmChange files
+///
+/// Consecutive elements with the same action are enclosed in a single `<create>`, `<modify>` or
+/// `<delete>` element.
+pub(crate) struct ChangeWriter {
+    file: Option<CompressedFile>,
+    action: Option<Action>,
+}
+
+impl ChangeWriter {
+    pub(crate) fn new(path: &PathBuf, generator: &str) -> Result<ChangeWriter, anyhow::Error> {
+        let mut file = CompressedFile::create(path)?;
+        write!(
+            file,
+            "<?xml version='1.0' encoding='UTF-8'?>\n<osmChange version=\"0.6\" generator=\"{}\">\n",
+            escape(generator)
+        )?;
+        Ok(
+            ChangeWriter {
+                file: Some(file),
+                action: None,
+            }
+        )
+    }
+
+    pub(crate) fn write(&mut self, action: Action, element: &Element) -> Result<(), anyhow::Error> {
+        let file = self.file.as_mut().ok_or(anyhow!("Writer is closed"))?;
+        if self.action != Some(action) {
+            if let Some(previous) = self.action {
+                writeln!(file, "  </{}>", action_name(previous))?;
+            }
+            writeln!(file, "  <{}>", action_name(action))?;
+            self.action = Some(action);
+        }
+        write_element(file, element, "    ", false)
+    }
+
+    pub(crate) fn close(&mut self) -> Result<(), anyhow::Error> {
+        let mut file = self.file.take().ok_or(anyhow!("Writer is closed"))?;
+        if let Some(action) = self.action.take() {
+            writeln!(file, "  </{}>", action_name(action))?;
+        }
+        file.write_all(b"</osmChange>\n")?;
+        file.finish()
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Create => { "create" }
+        Action::Modify => { "modify" }
+        Action::Delete => { "delete" }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::xml::action::Action;
+    use crate::xml::change_writer::ChangeWriter;
+    use crate::xml::reader::Reader;
+
+    #[test]
+    fn test_write_read_change() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/small.osm");
+        let output_path = std::env::temp_dir().join("osm-admin-test-write-read-change.osc.gz");
+        let elements: Vec<_> = Reader::from_path(&input_path)?
+            .map(|result| result.map(|(_, element)| element))
+            .collect::<Result<_, anyhow::Error>>()?;
+        let actions = [Action::Create, Action::Modify, Action::Delete, Action::Delete, Action::Create];
+        let mut writer = ChangeWriter::new(&output_path, "osm-admin-test")?;
+        for (action, element) in actions.iter().zip(elements.iter()) {
+            writer.write(*action, element)?;
+        }
+        writer.close()?;
+
+        let written: Vec<_> = Reader::from_path(&output_path)?.collect::<Result<_, anyhow::Error>>()?;
+        fs::remove_file(&output_path)?;
+        assert_eq!(written.len(), elements.len());
+        for ((action, element), (expected_action, expected_element)) in written.iter().zip(actions.iter().zip(elements.iter())) {
+            assert_eq!(action.as_ref(), Some(expected_action));
+            assert_eq!(element, expected_element);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+pub(crate) mod action;
+pub(crate) mod change_writer;
+pub(crate) mod reader;
+pub(crate) mod writer;
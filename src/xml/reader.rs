@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use bzip2::read::MultiBzDecoder;
+use chrono::DateTime;
+use flate2::read::MultiGzDecoder;
+use osm_io::osm::model::coordinate::Coordinate;
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::node::Node;
+use osm_io::osm::model::relation::{Member, MemberData, Relation};
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::model::way::Way;
+use quick_xml::events::{BytesStart, Event};
+
+use crate::xml::action::Action;
+
+/// Streaming reader of OSM XML and osmChange files
+///
+/// Produces the elements in the order they appear in the file, each paired with the enclosing
+/// osmChange action. Elements of a plain OSM XML file have no action. Elements enclosed in a
+/// `<delete>` action are produced as not visible.
+pub(crate) struct Reader {
+    xml_reader: quick_xml::Reader<Box<dyn BufRead>>,
+    buf: Vec<u8>,
+    action: Option<Action>,
+    element: Option<ElementBuilder>,
+}
+
+impl Reader {
+    /// Open an OSM XML or osmChange file. Files ending with .gz or .bz2 are decompressed on the fly.
+    pub(crate) fn from_path(path: &PathBuf) -> Result<Reader, anyhow::Error> {
+        let file = File::open(path)
+            .with_context(|| anyhow!("path: {}", path.display()))?;
+        let input: Box<dyn BufRead> = match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => {
+                Box::new(BufReader::new(MultiGzDecoder::new(file)))
+            }
+            Some("bz2") => {
+                Box::new(BufReader::new(MultiBzDecoder::new(file)))
+            }
+            _ => {
+                Box::new(BufReader::new(file))
+            }
+        };
+        Ok(Self::new(input))
+    }
+
+    pub(crate) fn new(input: Box<dyn BufRead>) -> Reader {
+        let mut xml_reader = quick_xml::Reader::from_reader(input);
+        xml_reader.expand_empty_elements(true);
+        xml_reader.trim_text(true);
+        Reader {
+            xml_reader,
+            buf: Vec::new(),
+            action: None,
+            element: None,
+        }
+    }
+
+    fn next_element(&mut self) -> Result<Option<(Option<Action>, Element)>, anyhow::Error> {
+        loop {
+            self.buf.clear();
+            let (start, name, attributes) = match self.xml_reader.read_event_into(&mut self.buf)? {
+                Event::Start(e) => {
+                    (true, e.name().as_ref().to_vec(), Self::attributes(&e)?)
+                }
+                Event::End(e) => {
+                    (false, e.name().as_ref().to_vec(), HashMap::new())
+                }
+                Event::Eof => {
+                    return if self.element.is_some() {
+                        Err(anyhow!("Unexpected end of file inside an element"))
+                    } else {
+                        Ok(None)
+                    };
+                }
+                _ => {
+                    continue;
+                }
+            };
+
+            if start {
+                self.start(&name, attributes)?;
+            } else {
+                match name.as_slice() {
+                    b"node" | b"way" | b"relation" => {
+                        let element = self.element.take()
+                            .ok_or(anyhow!("Unexpected closing tag: {}", String::from_utf8_lossy(&name)))?
+                            .build()?;
+                        return Ok(Some((self.action, element)));
+                    }
+                    b"create" | b"modify" | b"delete" => {
+                        self.action = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, name: &[u8], attributes: HashMap<String, String>) -> Result<(), anyhow::Error> {
+        match name {
+            b"node" | b"way" | b"relation" => {
+                let visible = self.action != Some(Action::Delete);
+                self.element = Some(ElementBuilder::new(name, attributes, visible)?);
+            }
+            b"tag" => {
+                if let Some(element) = self.element.as_mut() {
+                    element.tags.push(
+                        Tag::new(
+                            required(&attributes, "k")?.clone(),
+                            required(&attributes, "v")?.clone(),
+                        )
+                    );
+                }
+            }
+            b"nd" => {
+                if let Some(element) = self.element.as_mut() {
+                    element.refs.push(i64::from_str(required(&attributes, "ref")?)?);
+                }
+            }
+            b"member" => {
+                if let Some(element) = self.element.as_mut() {
+                    let member_data = MemberData::new(
+                        i64::from_str(required(&attributes, "ref")?)?,
+                        attributes.get("role").cloned().unwrap_or_default(),
+                    );
+                    let member = match required(&attributes, "type")?.as_str() {
+                        "node" => { Member::Node { member: member_data } }
+                        "way" => { Member::Way { member: member_data } }
+                        "relation" => { Member::Relation { member: member_data } }
+                        t => {
+                            return Err(anyhow!("Unknown relation member type: {}", t));
+                        }
+                    };
+                    element.members.push(member);
+                }
+            }
+            _ => {
+                if let Some(action) = Action::from_tag_name(name) {
+                    self.action = Some(action);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn attributes(e: &BytesStart) -> Result<HashMap<String, String>, anyhow::Error> {
+        let mut attributes = HashMap::new();
+        for attribute_result in e.attributes() {
+            let attribute = attribute_result?;
+            attributes.insert(
+                String::from_utf8(attribute.key.as_ref().to_vec())?,
+                attribute.unescape_value()?.to_string(),
+            );
+        }
+        Ok(attributes)
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<(Option<Action>, Element), anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_element().transpose()
+    }
+}
+
+fn required<'a>(attributes: &'a HashMap<String, String>, name: &str) -> Result<&'a String, anyhow::Error> {
+    attributes.get(name).ok_or(anyhow!("Missing required attribute: {}", name))
+}
+
+fn optional<T: FromStr>(attributes: &HashMap<String, String>, name: &str, default: T) -> Result<T, anyhow::Error>
+    where <T as FromStr>::Err: std::error::Error + Send + Sync + 'static {
+    match attributes.get(name) {
+        None => {
+            Ok(default)
+        }
+        Some(value) => {
+            T::from_str(value).with_context(|| anyhow!("attribute {}: {}", name, value))
+        }
+    }
+}
+
+struct ElementBuilder {
+    name: Vec<u8>,
+    id: i64,
+    version: i32,
+    coordinate: Coordinate,
+    timestamp: i64,
+    changeset: i64,
+    uid: i32,
+    user: String,
+    visible: bool,
+    tags: Vec<Tag>,
+    refs: Vec<i64>,
+    members: Vec<Member>,
+}
+
+impl ElementBuilder {
+    fn new(name: &[u8], attributes: HashMap<String, String>, visible: bool) -> Result<ElementBuilder, anyhow::Error> {
+        let timestamp = match attributes.get("timestamp") {
+            None => {
+                0
+            }
+            Some(timestamp) => {
+                DateTime::parse_from_rfc3339(timestamp)
+                    .with_context(|| anyhow!("timestamp: {}", timestamp))?
+                    .timestamp_millis()
+            }
+        };
+        Ok(
+            ElementBuilder {
+                name: name.to_vec(),
+                id: i64::from_str(required(&attributes, "id")?)?,
+                version: optional(&attributes, "version", 1)?,
+                coordinate: Coordinate::new(
+                    optional(&attributes, "lat", 0.0)?,
+                    optional(&attributes, "lon", 0.0)?,
+                ),
+                timestamp,
+                changeset: optional(&attributes, "changeset", 0)?,
+                uid: optional(&attributes, "uid", 0)?,
+                user: attributes.get("user").cloned().unwrap_or_default(),
+                visible: visible && optional(&attributes, "visible", true)?,
+                tags: Vec::new(),
+                refs: Vec::new(),
+                members: Vec::new(),
+            }
+        )
+    }
+
+    fn build(self) -> Result<Element, anyhow::Error> {
+        match self.name.as_slice() {
+            b"node" => {
+                Ok(
+                    Element::Node {
+                        node: Node::new(self.id, self.version, self.coordinate, self.timestamp, self.changeset, self.uid, self.user, self.visible, self.tags),
+                    }
+                )
+            }
+            b"way" => {
+                Ok(
+                    Element::Way {
+                        way: Way::new(self.id, self.version, self.timestamp, self.changeset, self.uid, self.user, self.visible, self.refs, self.tags),
+                    }
+                )
+            }
+            b"relation" => {
+                Ok(
+                    Element::Relation {
+                        relation: Relation::new(self.id, self.version, self.timestamp, self.changeset, self.uid, self.user, self.visible, self.members, self.tags),
+                    }
+                )
+            }
+            _ => {
+                Err(anyhow!("Unknown element: {}", String::from_utf8_lossy(&self.name)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use osm_io::osm::model::element::Element;
+
+    use crate::xml::action::Action;
+    use crate::xml::reader::Reader;
+
+    #[test]
+    fn test_read_osm_change() -> Result<(), anyhow::Error> {
+        let path = PathBuf::from("./tests/fixtures/changes.osc");
+        let changes = Reader::from_path(&path)?.collect::<Result<Vec<(Option<Action>, Element)>, anyhow::Error>>()?;
+        assert_eq!(changes.len(), 5);
+
+        let (action, element) = &changes[0];
+        assert_eq!(*action, Some(Action::Create));
+        match element {
+            Element::Node { node } => {
+                assert_eq!(node.id(), 1001);
+                assert_eq!(node.version(), 1);
+                assert_eq!(node.coordinate().lat7(), -190543210);
+                assert_eq!(node.coordinate().lon7(), -1699123456);
+                assert_eq!(node.timestamp(), 1686564000000);
+                assert_eq!(node.user(), "Mapper & Co");
+                assert_eq!(node.tags().len(), 2);
+                assert!(node.visible());
+            }
+            _ => {
+                panic!("Not a node");
+            }
+        }
+
+        let (action, element) = &changes[2];
+        assert_eq!(*action, Some(Action::Modify));
+        match element {
+            Element::Way { way } => {
+                assert_eq!(way.refs(), &vec![1001, 1002]);
+            }
+            _ => {
+                panic!("Not a way");
+            }
+        }
+
+        let (action, element) = &changes[3];
+        assert_eq!(*action, Some(Action::Modify));
+        match element {
+            Element::Relation { relation } => {
+                assert_eq!(relation.members().len(), 2);
+            }
+            _ => {
+                panic!("Not a relation");
+            }
+        }
+
+        let (action, element) = &changes[4];
+        assert_eq!(*action, Some(Action::Delete));
+        assert!(element.is_node());
+        if let Element::Node { node } = element {
+            assert!(!node.visible());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_compressed_osm_change() -> Result<(), anyhow::Error> {
+        let path = PathBuf::from("./tests/fixtures/changes.osc.gz");
+        assert_eq!(Reader::from_path(&path)?.count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_osm() -> Result<(), anyhow::Error> {
+        let path = PathBuf::from("./tests/fixtures/small.osm.bz2");
+        let elements = Reader::from_path(&path)?.collect::<Result<Vec<(Option<Action>, Element)>, anyhow::Error>>()?;
+        assert_eq!(elements.len(), 5);
+        assert!(elements.iter().all(|(action, _)| action.is_none()));
+        assert!(elements[3].1.is_way());
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat};
+use osm_io::osm::model::element::Element;
+use osm_io::osm::model::relation::Member;
+use osm_io::osm::model::tag::Tag;
+use osm_io::osm::pbf::file_info::FileInfo;
+use quick_xml::escape::escape;
+
+use crate::output::compression::CompressedFile;
+use crate::output::writer::ElementWriter;
+
+/// Writer of OSM XML files
+///
+/// The bounding box of the [FileInfo] is written as the `<bounds>` element and the osmosis
+/// replication timestamp as the `timestamp` attribute of the `<osm>` element. The `visible`
+/// attribute is written only for files with history.
+pub(crate) struct Writer {
+    file: Option<CompressedFile>,
+    info: FileInfo,
+    history: bool,
+}
+
+impl Writer {
+    pub(crate) fn new(path: &PathBuf, info: FileInfo) -> Result<Writer, anyhow::Error> {
+        let history = info.required("HistoricalInformation");
+        Ok(
+            Writer {
+                file: Some(CompressedFile::create(path)?),
+                info,
+                history,
+            }
+        )
+    }
+
+    fn file(&mut self) -> Result<&mut CompressedFile, anyhow::Error> {
+        self.file.as_mut().ok_or(anyhow!("Writer is closed"))
+    }
+}
+
+impl ElementWriter for Writer {
+    fn write_header(&mut self) -> Result<(), anyhow::Error> {
+        let mut header = String::from("<?xml version='1.0' encoding='UTF-8'?>\n<osm version=\"0.6\"");
+        if let Some(writingprogram) = self.info.writingprogram() {
+            header.push_str(&format!(" generator=\"{}\"", escape(writingprogram)));
+        }
+        if let Some(timestamp) = self.info.osmosis_replication_timestamp() {
+            header.push_str(&format!(" timestamp=\"{}\"", format_timestamp(*timestamp * 1000)?));
+        }
+        header.push_str(">\n");
+        if let Some(bounding_box) = self.info.bounding_box() {
+            header.push_str(
+                &format!(
+                    "  <bounds minlat=\"{:.7}\" minlon=\"{:.7}\" maxlat=\"{:.7}\" maxlon=\"{:.7}\"/>\n",
+                    bounding_box.bottom(),
+                    bounding_box.left(),
+                    bounding_box.top(),
+                    bounding_box.right(),
+                )
+            );
+        }
+        self.file()?.write_all(header.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: Element) -> Result<(), anyhow::Error> {
+        let history = self.history;
+        write_element(self.file()?, &element, "  ", history)
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        let mut file = self.file.take().ok_or(anyhow!("Writer is closed"))?;
+        file.write_all(b"</osm>\n")?;
+        file.finish()
+    }
+}
+
+/// Write a single element in OSM XML format, indented by `indent`
+pub(crate) fn write_element<W: Write>(out: &mut W, element: &Element, indent: &str, with_visible: bool) -> Result<(), anyhow::Error> {
+    match element {
+        Element::Node { node } => {
+            let mut line = format!(
+                "{}<node{}",
+                indent,
+                attributes(node.id(), node.version(), node.timestamp(), node.uid(), node.user(), node.changeset(), with_visible.then_some(node.visible()))?
+            );
+            if node.visible() {
+                line.push_str(&format!(" lat=\"{:.7}\" lon=\"{:.7}\"", node.coordinate().lat(), node.coordinate().lon()));
+            }
+            write_start(out, line, node.tags().is_empty())?;
+            write_tags(out, node.tags(), indent)?;
+            write_end(out, "node", indent, node.tags().is_empty())?;
+        }
+        Element::Way { way } => {
+            let line = format!(
+                "{}<way{}",
+                indent,
+                attributes(way.id(), way.version(), way.timestamp(), way.uid(), way.user(), way.changeset(), with_visible.then_some(way.visible()))?
+            );
+            let empty = way.refs().is_empty() && way.tags().is_empty();
+            write_start(out, line, empty)?;
+            for node_ref in way.refs() {
+                writeln!(out, "{}  <nd ref=\"{}\"/>", indent, node_ref)?;
+            }
+            write_tags(out, way.tags(), indent)?;
+            write_end(out, "way", indent, empty)?;
+        }
+        Element::Relation { relation } => {
+            let line = format!(
+                "{}<relation{}",
+                indent,
+                attributes(relation.id(), relation.version(), relation.timestamp(), relation.uid(), relation.user(), relation.changeset(), with_visible.then_some(relation.visible()))?
+            );
+            let empty = relation.members().is_empty() && relation.tags().is_empty();
+            write_start(out, line, empty)?;
+            for member in relation.members() {
+                let (member_type, member_data) = match member {
+                    Member::Node { member } => { ("node", member) }
+                    Member::Way { member } => { ("way", member) }
+                    Member::Relation { member } => { ("relation", member) }
+                };
+                writeln!(
+                    out,
+                    "{}  <member type=\"{}\" ref=\"{}\" role=\"{}\"/>",
+                    indent,
+                    member_type,
+                    member_data.id(),
+                    escape(member_data.role())
+                )?;
+            }
+            write_tags(out, relation.tags(), indent)?;
+            write_end(out, "relation", indent, empty)?;
+        }
+        Element::Sentinel => {}
+    }
+    Ok(())
+}
+
+fn attributes(id: i64, version: i32, timestamp: i64, uid: i32, user: &str, changeset: i64, visible: Option<bool>) -> Result<String, anyhow::Error> {
+    let mut attributes = format!(
+        " id=\"{}\" version=\"{}\" timestamp=\"{}\" uid=\"{}\" user=\"{}\" changeset=\"{}\"",
+        id,
+        version,
+        format_timestamp(timestamp)?,
+        uid,
+        escape(user),
+        changeset,
+    );
+    if let Some(visible) = visible {
+        attributes.push_str(&format!(" visible=\"{}\"", visible));
+    }
+    Ok(attributes)
+}
+
+fn write_start<W: Write>(out: &mut W, line: String, empty: bool) -> Result<(), anyhow::Error> {
+    if empty {
+        writeln!(out, "{}/>", line)?;
+    } else {
+        writeln!(out, "{}>", line)?;
+    }
+    Ok(())
+}
+
+fn write_end<W: Write>(out: &mut W, name: &str, indent: &str, empty: bool) -> Result<(), anyhow::Error> {
+    if !empty {
+        writeln!(out, "{}</{}>", indent, name)?;
+    }
+    Ok(())
+}
+
+fn write_tags<W: Write>(out: &mut W, tags: &Vec<Tag>, indent: &str) -> Result<(), anyhow::Error> {
+    for tag in tags {
+        writeln!(out, "{}  <tag k=\"{}\" v=\"{}\"/>", indent, escape(tag.k()), escape(tag.v()))?;
+    }
+    Ok(())
+}
+
+fn format_timestamp(millis: i64) -> Result<String, anyhow::Error> {
+    Ok(
+        DateTime::from_timestamp_millis(millis)
+            .ok_or(anyhow!("Invalid timestamp: {}", millis))?
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use osm_io::osm::model::bounding_box::BoundingBox;
+    use osm_io::osm::pbf::file_info::FileInfo;
+
+    use crate::output::writer::ElementWriter;
+    use crate::xml::action::Action;
+    use crate::xml::reader::Reader;
+    use crate::xml::writer::Writer;
+
+    #[test]
+    fn test_write_read_round_trip() -> Result<(), anyhow::Error> {
+        let input_path = PathBuf::from("./tests/fixtures/small.osm");
+        let output_path = std::env::temp_dir().join("osm-admin-test-write-read-round-trip.osm.gz");
+        let info = FileInfo::new(
+            Some(BoundingBox::new(-169.92, -19.06, -169.91, -19.05)),
+            ["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"].map(|s| s.to_string()).to_vec(),
+            vec![],
+            Some("osm-admin-test".to_string()),
+            None,
+            Some(1686564002),
+            Some(42),
+            None,
+        );
+        let elements = Reader::from_path(&input_path)?.collect::<Result<Vec<(Option<Action>, _)>, anyhow::Error>>()?;
+        let mut writer = Writer::new(&output_path, info)?;
+        writer.write_header()?;
+        for (_, element) in elements.clone() {
+            writer.write_element(element)?;
+        }
+        writer.close()?;
+
+        let written = Reader::from_path(&output_path)?.collect::<Result<Vec<(Option<Action>, _)>, anyhow::Error>>()?;
+        fs::remove_file(&output_path)?;
+        assert_eq!(written.len(), elements.len());
+        for ((_, expected), (_, actual)) in elements.iter().zip(written.iter()) {
+            assert_eq!(format!("{:?}", expected), format!("{:?}", actual));
+        }
+        Ok(())
+    }
+}